@@ -1,25 +1,32 @@
-use std::fs::File;
-use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::sleep;
-use std::time::Duration;
-use std::{env, thread};
+use std::time::{Duration, SystemTime};
+use std::{env, fs, thread};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, Result};
+use nix::libc::c_int;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
 
 use raspi::config::Config;
 use raspi::server;
 
+/// Set by SIGHUP to reload the configuration
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_reload(_: c_int) {
+    RELOAD.store(true, Ordering::Relaxed);
+}
+
 fn main() -> Result<()> {
     let args: Vec<_> = env::args().collect();
     if args.len() != 2 {
         return Err(anyhow!("missing config file argument"));
     }
-    let file = File::open(&args[1])
-        .with_context(|| "Cannot open config file")?;
-    let reader = BufReader::new(file);
-
-    let cfg: Config = serde_json::from_reader(reader)?;
+    let path = &args[1];
+    let (cfg, mut files) = Config::load(path)?;
     let mut recorder = raspi::Recorder::new(cfg.recorder, &cfg.db_path)?;
+    let (server_cfg, db_path) = (cfg.server.clone(), cfg.db_path.clone());
     let handle = thread::spawn(move || -> Result<()> {
         server::serve(cfg.server, &cfg.db_path)?;
         Ok(())
@@ -28,6 +35,41 @@ fn main() -> Result<()> {
     if handle.is_finished() {
         handle.join().expect("Couldn't join server thread")?;
     }
-    recorder.run()?;
+
+    // The configuration is reloaded on SIGHUP, or when a file it has been read from is modified
+    let handler = SigAction::new(
+        SigHandler::Handler(request_reload),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    unsafe { sigaction(Signal::SIGHUP, &handler) }?;
+    let mut modified = modified_times(&files);
+    recorder.run(|| {
+        let signaled = RELOAD.swap(false, Ordering::Relaxed);
+        let now_modified = modified_times(&files);
+        if !signaled && now_modified == modified {
+            return None;
+        }
+        modified = now_modified;
+        println!("Reloading configuration from {path}");
+        Some(Config::load(path).map(|(cfg, read)| {
+            // The includes and referenced files may have changed
+            if read != files {
+                files = read;
+                modified = modified_times(&files);
+            }
+            if cfg.server != server_cfg || cfg.db_path != db_path {
+                println!("Warning: the server and database settings are applied on restart only");
+            }
+            cfg.recorder
+        }))
+    })?;
     Ok(())
 }
+
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+        .collect()
+}
@@ -0,0 +1,19 @@
+use std::env;
+
+use anyhow::Result;
+
+use raspi::sensors::ds18b20::list_ds18b20;
+use raspi::sensors::one_wire::read_thermometer;
+
+fn main() -> Result<()> {
+    let w1_root = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "/sys/bus/w1/devices".to_owned());
+    for sn in list_ds18b20(&w1_root)? {
+        match read_thermometer(&w1_root, &sn) {
+            Ok(t) => println!("{sn}\t{t:.3} °C"),
+            Err(e) => println!("{sn}\terror: {e}"),
+        }
+    }
+    Ok(())
+}
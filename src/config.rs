@@ -1,9 +1,14 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::series::SeriesDef;
 
-#[derive(Deserialize, JsonSchema)]
+mod load;
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
 pub struct Config {
     /// Sensors and series settings
     pub recorder: Recorder,
@@ -11,9 +16,14 @@ pub struct Config {
     pub server: Server,
     /// Path to the sqlite database
     pub db_path: String,
+    /// Configuration files merged into this one, such as files of sensor definitions, relative
+    /// to this file. Their lists are appended to the ones of this file, whose other values take
+    /// precedence.
+    #[serde(default)]
+    pub include: Vec<String>,
 }
 
-#[derive(Deserialize, JsonSchema)]
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
 pub struct Server {
     /// Port the server should listen to
     pub port: u16,
@@ -21,41 +31,151 @@ pub struct Server {
     pub allowed_origin: String,
 }
 
-#[derive(Deserialize, JsonSchema)]
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
 pub struct Recorder {
     pub sensors: Vec<Sensor>,
     pub series: Vec<SeriesConfig>,
 }
 
-#[derive(Deserialize, JsonSchema)]
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
 pub struct Sensor {
     pub id: String,
     pub config: SensorConfig,
 }
 
-#[derive(Deserialize, JsonSchema)]
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum SensorConfig {
     Ds18b20(Ds18b20Config),
+    Ds18b20Bus(Ds18b20BusConfig),
+    Ds2438(Ds2438Config),
+    Ds2413(Ds2413Config),
     Bme280(Bme280Config),
-    OpenWeatherMap(OpenWeatherMapConfig),
+    Bmp280(Bmp280Config),
+    Bme680(Bme680Config),
+    Sht3x(Sht3xConfig),
+    Sht4x(Sht4xConfig),
+    Ads1115(Ads1115Config),
+    Mcp3008(Mcp3008Config),
+    Pms5003(Pms5003Config),
+    Sds011(Sds011Config),
+    Mhz19(Mhz19Config),
+    SenseairS8(SenseairS8Config),
+    Modbus(ModbusConfig),
+    Snmp(SnmpConfig),
+    Ble(BleConfig),
+    File(FileConfig),
+    Command(CommandConfig),
+    System(SystemConfig),
+    Gpio(GpioConfig),
+    HttpJson(HttpJsonConfig),
+    OpenWeatherMap(Box<OpenWeatherMapConfig>),
+    OpenMeteo(OpenMeteoConfig),
+    Metar(MetarConfig),
 }
 
-#[derive(Deserialize, JsonSchema)]
+/// DS18B20 probe, or any other 1-Wire thermometer (DS18S20, DS1822)
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
 pub struct Ds18b20Config {
+    /// Path to the 1-Wire devices directory (default "/sys/bus/w1/devices")
+    #[serde(default = "default_w1_root")]
+    pub w1_root: String,
     /// Serial number of the sensor. Automatically detected if not configured.
     pub serial_number: Option<String>,
+    #[serde(flatten)]
+    pub settings: Ds18b20Settings,
+    pub temperature_series: Option<String>,
+}
+
+/// All the DS18B20 probes, or any other 1-Wire thermometers, connected to the 1-Wire bus.
+/// Newly attached probes are reported in the logs.
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Ds18b20BusConfig {
+    /// Path to the 1-Wire devices directory (default "/sys/bus/w1/devices")
+    #[serde(default = "default_w1_root")]
+    pub w1_root: String,
+    /// Series associated to each probe, by serial number (e.g. "28-0316a2797cff": "cellar").
+    /// Probes not listed here are not recorded.
+    #[serde(default)]
+    pub probes: HashMap<String, String>,
+    /// Settings applied to every probe
+    #[serde(flatten)]
+    pub settings: Ds18b20Settings,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Ds18b20Settings {
+    /// Resolution of the measurements in bits, from 9 (0.5 °C, 94 ms conversion) to 12
+    /// (0.0625 °C, 750 ms conversion). The probe setting is left untouched if not configured.
+    /// Not supported by DS18S20 probes.
+    pub resolution: Option<u8>,
+    /// Time to wait for a conversion to complete, in the form "500ms", "1s", etc. Defaults to
+    /// the maximum conversion time of the configured resolution.
+    pub conversion_time: Option<String>,
+}
+
+/// DS2438 battery monitor
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Ds2438Config {
+    /// Path to the 1-Wire devices directory (default "/sys/bus/w1/devices")
+    #[serde(default = "default_w1_root")]
+    pub w1_root: String,
+    pub serial_number: String,
+    /// Value of the current sense resistor, in ohms. Required by the current series.
+    pub sense_resistor: Option<f64>,
+
     pub temperature_series: Option<String>,
+    /// Voltage of the VAD input, in volts
+    pub vad_series: Option<String>,
+    /// Voltage of the VDD input, in volts
+    pub vdd_series: Option<String>,
+    /// Current flowing through the sense resistor, in amperes
+    pub current_series: Option<String>,
+}
+
+/// DS2413 dual channel switch
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Ds2413Config {
+    /// Path to the 1-Wire devices directory (default "/sys/bus/w1/devices")
+    #[serde(default = "default_w1_root")]
+    pub w1_root: String,
+    pub serial_number: String,
+
+    /// Logic level of the PIO A pin (0 or 1)
+    pub pio_a_series: Option<String>,
+    /// Logic level of the PIO B pin (0 or 1)
+    pub pio_b_series: Option<String>,
 }
 
-#[derive(Deserialize, JsonSchema)]
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
 pub struct Bme280Config {
-    /// Path to the i2c directory (default "/dev/i2c-1")
-    #[serde(default = "Bme280Config::default_path")]
-    pub path: String,
+    /// Bus the sensor is connected to (default I2C on "/dev/i2c-1", address "0x76")
+    pub bus: Option<BoschBusConfig>,
+    /// Deprecated, use the path of the i2c bus instead
+    pub path: Option<String>,
+    /// Deprecated, use the address of the i2c bus instead
+    pub address: Option<BoschAddress>,
 
+    /// Measurement mode (default "normal"). In forced mode the sensor sleeps between samples,
+    /// which reduces self-heating.
+    #[serde(default)]
+    pub mode: BoschMode,
+    /// Temperature oversampling (default "x8")
+    #[serde(default)]
+    pub temperature_oversampling: BoschOversampling,
+    /// Pressure oversampling (default "x8")
+    #[serde(default)]
+    pub pressure_oversampling: BoschOversampling,
+    /// Humidity oversampling (default "x8")
+    #[serde(default)]
+    pub humidity_oversampling: BoschOversampling,
+    /// IIR filter coefficient (default "x16")
+    #[serde(default)]
+    pub filter: BoschFilter,
+    /// Inactive duration between two measurements in normal mode (default "1000ms").
+    /// Ignored in forced mode.
     #[serde(default)]
-    pub address: Bme280Address,
+    pub standby_time: Bme280Standby,
 
     pub temperature_series: Option<String>,
     pub humidity_series: Option<String>,
@@ -63,34 +183,1192 @@ pub struct Bme280Config {
 }
 
 impl Bme280Config {
-    fn default_path() -> String {
-        "/dev/i2c-1".to_owned()
+    /// Bus of the sensor, built from the deprecated `path` and `address` if they are set
+    pub fn bus(&self) -> Result<BoschBusConfig> {
+        match (&self.bus, &self.path, self.address) {
+            (Some(bus), None, None) => Ok(bus.clone()),
+            (Some(_), _, _) => bail!(anyhow!(
+                "the deprecated path and address of the BME280 cannot be set along with its bus"
+            )),
+            (None, path, address) => Ok(BoschBusConfig::I2c {
+                path: path.clone().unwrap_or_else(default_i2c_path),
+                address: address.unwrap_or_default(),
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Bmp280Config {
+    /// Bus the sensor is connected to (default I2C on "/dev/i2c-1", address "0x76")
+    #[serde(default)]
+    pub bus: BoschBusConfig,
+
+    /// Measurement mode (default "normal"). In forced mode the sensor sleeps between samples,
+    /// which reduces self-heating.
+    #[serde(default)]
+    pub mode: BoschMode,
+    /// Temperature oversampling (default "x8")
+    #[serde(default)]
+    pub temperature_oversampling: BoschOversampling,
+    /// Pressure oversampling (default "x8")
+    #[serde(default)]
+    pub pressure_oversampling: BoschOversampling,
+    /// IIR filter coefficient (default "x16")
+    #[serde(default)]
+    pub filter: BoschFilter,
+    /// Inactive duration between two measurements in normal mode (default "1000ms").
+    /// Ignored in forced mode.
+    #[serde(default)]
+    pub standby_time: Bmp280Standby,
+
+    pub temperature_series: Option<String>,
+    pub pressure_series: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Bme680Config {
+    /// Bus the sensor is connected to (default I2C on "/dev/i2c-1", address "0x76")
+    #[serde(default)]
+    pub bus: BoschBusConfig,
+
+    /// Temperature oversampling (default "x8")
+    #[serde(default)]
+    pub temperature_oversampling: BoschOversampling,
+    /// Pressure oversampling (default "x8")
+    #[serde(default)]
+    pub pressure_oversampling: BoschOversampling,
+    /// Humidity oversampling (default "x8")
+    #[serde(default)]
+    pub humidity_oversampling: BoschOversampling,
+    /// IIR filter coefficient (default "3")
+    #[serde(default)]
+    pub filter: Bme680Filter,
+    /// Target temperature of the gas sensor hot plate, in °C (default 320, at most 400)
+    #[serde(default = "Bme680Config::default_heater_temperature")]
+    pub heater_temperature: u16,
+    /// Heating duration before the gas resistance is measured, in ms (default 150, at most 4032)
+    #[serde(default = "Bme680Config::default_heater_duration")]
+    pub heater_duration: u16,
+
+    pub temperature_series: Option<String>,
+    pub humidity_series: Option<String>,
+    pub pressure_series: Option<String>,
+    /// Series receiving the gas sensor resistance, in ohms. The gas sensor heater is only
+    /// turned on when this series is configured.
+    pub gas_resistance_series: Option<String>,
+}
+
+impl Bme680Config {
+    fn default_heater_temperature() -> u16 {
+        320
+    }
+
+    fn default_heater_duration() -> u16 {
+        150
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BoschBusConfig {
+    I2c {
+        /// Path to the i2c directory (default "/dev/i2c-1")
+        #[serde(default = "default_i2c_path")]
+        path: String,
+        #[serde(default)]
+        address: BoschAddress,
+    },
+    Spi {
+        /// Path to the spidev device, e.g. "/dev/spidev0.0"
+        path: String,
+        #[serde(default)]
+        chip_select: BoschChipSelect,
+        /// SPI clock frequency in Hz (default 1000000, at most 10000000)
+        #[serde(default = "BoschBusConfig::default_spi_speed")]
+        speed_hz: u32,
+    },
+}
+
+impl BoschBusConfig {
+    fn default_spi_speed() -> u32 {
+        1_000_000
+    }
+}
+
+impl Default for BoschBusConfig {
+    fn default() -> Self {
+        BoschBusConfig::I2c {
+            path: default_i2c_path(),
+            address: BoschAddress::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BoschChipSelect {
+    /// Chip select line driven by the SPI controller (CE0 for "/dev/spidev0.0", CE1 for
+    /// "/dev/spidev0.1", etc.)
+    #[default]
+    Hardware,
+    /// Chip select driven by a GPIO line, allowing more sensors than hardware chip select lines
+    Gpio {
+        /// Path to the GPIO chip (default "/dev/gpiochip0")
+        #[serde(default = "default_gpio_chip")]
+        chip: String,
+        /// Offset of the GPIO line on the chip
+        line: u32,
+    },
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Sht3xConfig {
+    /// Path to the i2c directory (default "/dev/i2c-1")
+    #[serde(default = "default_i2c_path")]
+    pub path: String,
+
+    #[serde(default)]
+    pub address: Sht3xAddress,
+
+    /// Measurement repeatability (default "high"). A higher repeatability lowers the noise but
+    /// takes longer to measure.
+    #[serde(default)]
+    pub repeatability: ShtRepeatability,
+    /// Keeps the internal heater enabled, to evaporate condensation. Temperature readings are
+    /// skewed by a few degrees while the heater is on.
+    #[serde(default)]
+    pub heater: bool,
+
+    pub temperature_series: Option<String>,
+    pub humidity_series: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Sht4xConfig {
+    /// Path to the i2c directory (default "/dev/i2c-1")
+    #[serde(default = "default_i2c_path")]
+    pub path: String,
+
+    #[serde(default)]
+    pub address: Sht4xAddress,
+
+    /// Measurement repeatability (default "high"). A higher repeatability lowers the noise but
+    /// takes longer to measure.
+    #[serde(default)]
+    pub repeatability: ShtRepeatability,
+    /// Heater pulse triggered after each measurement, to evaporate condensation. Not triggered
+    /// if not configured.
+    pub heater: Option<Sht4xHeater>,
+
+    pub temperature_series: Option<String>,
+    pub humidity_series: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, PartialEq)]
+pub enum Sht3xAddress {
+    #[default]
+    #[serde(rename = "0x44")]
+    AddrLow,
+    #[serde(rename = "0x45")]
+    AddrHigh,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, PartialEq)]
+pub enum Sht4xAddress {
+    #[default]
+    #[serde(rename = "0x44")]
+    A,
+    #[serde(rename = "0x45")]
+    B,
+    #[serde(rename = "0x46")]
+    C,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ShtRepeatability {
+    #[default]
+    High,
+    Medium,
+    Low,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Sht4xHeater {
+    pub power: Sht4xHeaterPower,
+    pub duration: Sht4xHeaterDuration,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, PartialEq)]
+pub enum Sht4xHeaterPower {
+    #[serde(rename = "200mW")]
+    High,
+    #[serde(rename = "110mW")]
+    Medium,
+    #[serde(rename = "20mW")]
+    Low,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, PartialEq)]
+pub enum Sht4xHeaterDuration {
+    #[serde(rename = "1s")]
+    Long,
+    #[serde(rename = "0.1s")]
+    Short,
+}
+
+/// Texas Instruments ADS1115 16-bit analog to digital converter, on the I2C bus
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Ads1115Config {
+    /// Path to the i2c directory (default "/dev/i2c-1")
+    #[serde(default = "default_i2c_path")]
+    pub path: String,
+    #[serde(default)]
+    pub address: Ads1115Address,
+    /// Samples per second (default "128")
+    #[serde(default)]
+    pub data_rate: Ads1115DataRate,
+    pub channels: Vec<Ads1115Channel>,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq)]
+pub enum Ads1115Address {
+    /// ADDR pin connected to GND
+    #[default]
+    #[serde(rename = "0x48")]
+    Gnd,
+    /// ADDR pin connected to VDD
+    #[serde(rename = "0x49")]
+    Vdd,
+    /// ADDR pin connected to SDA
+    #[serde(rename = "0x4a")]
+    Sda,
+    /// ADDR pin connected to SCL
+    #[serde(rename = "0x4b")]
+    Scl,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq)]
+pub enum Ads1115DataRate {
+    #[serde(rename = "8")]
+    Sps8,
+    #[serde(rename = "16")]
+    Sps16,
+    #[serde(rename = "32")]
+    Sps32,
+    #[serde(rename = "64")]
+    Sps64,
+    #[default]
+    #[serde(rename = "128")]
+    Sps128,
+    #[serde(rename = "250")]
+    Sps250,
+    #[serde(rename = "475")]
+    Sps475,
+    #[serde(rename = "860")]
+    Sps860,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Ads1115Channel {
+    #[serde(flatten)]
+    pub input: AdcInput,
+    /// Full scale range of the programmable gain amplifier (default "2.048V"). The input voltage
+    /// must not exceed VDD whatever the range.
+    #[serde(default)]
+    pub gain: Ads1115Gain,
+    pub conversion: Option<AdcConversion>,
+    pub series: String,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq)]
+pub enum Ads1115Gain {
+    #[serde(rename = "6.144V")]
+    Fsr6144,
+    #[serde(rename = "4.096V")]
+    Fsr4096,
+    #[default]
+    #[serde(rename = "2.048V")]
+    Fsr2048,
+    #[serde(rename = "1.024V")]
+    Fsr1024,
+    #[serde(rename = "0.512V")]
+    Fsr512,
+    #[serde(rename = "0.256V")]
+    Fsr256,
+}
+
+/// Microchip MCP3008 10-bit analog to digital converter, on the SPI bus
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Mcp3008Config {
+    /// Path to the spidev device, e.g. "/dev/spidev0.0"
+    pub path: String,
+    /// SPI clock frequency in Hz (default 1000000, at most 1350000 with a 2.7V supply)
+    #[serde(default = "Mcp3008Config::default_spi_speed")]
+    pub speed_hz: u32,
+    /// Voltage of the VREF pin (default 3.3)
+    #[serde(default = "Mcp3008Config::default_vref")]
+    pub vref: f64,
+    pub channels: Vec<Mcp3008Channel>,
+}
+
+impl Mcp3008Config {
+    fn default_spi_speed() -> u32 {
+        1_000_000
+    }
+
+    fn default_vref() -> f64 {
+        3.3
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Mcp3008Channel {
+    #[serde(flatten)]
+    pub input: AdcInput,
+    pub conversion: Option<AdcConversion>,
+    pub series: String,
+}
+
+/// Inputs a voltage is measured between
+#[derive(Deserialize, JsonSchema, Clone, Copy, PartialEq)]
+pub struct AdcInput {
+    /// Positive input
+    pub input: u8,
+    /// Negative input of a differential measurement. The positive input is measured against
+    /// the ground if not configured. ADS1115 pairs: 0-1, 0-3, 1-3 and 2-3. MCP3008 pairs: 0-1,
+    /// 2-3, 4-5, 6-7 and their reverse.
+    pub negative_input: Option<u8>,
+}
+
+/// Conversion of the measured voltage to the unit of the series. The voltage is recorded if not
+/// configured.
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AdcConversion {
+    /// volts × scale + offset
+    Linear {
+        scale: f64,
+        #[serde(default)]
+        offset: f64,
+    },
+    /// Linear interpolation between calibration points, as [volts, value] pairs sorted by
+    /// voltage. Voltages out of the table are clamped to its ends.
+    Table(Vec<(f64, f64)>),
+}
+
+/// Plantower PMS5003 particulate matter sensor, on a serial port
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Pms5003Config {
+    /// Path to the serial port, e.g. "/dev/ttyS0"
+    pub path: String,
+    /// Duration the fan runs before each sample, in the form "30s", "1min", etc. The fan and
+    /// laser are stopped between samples when the sampling interval is long enough, extending
+    /// their lifetime. They run continuously if not configured.
+    pub warmup: Option<String>,
+    /// PM1.0 concentration, in µg/m³
+    pub pm1_series: Option<String>,
+    /// PM2.5 concentration, in µg/m³
+    pub pm2_5_series: Option<String>,
+    /// PM10 concentration, in µg/m³
+    pub pm10_series: Option<String>,
+}
+
+/// Nova Fitness SDS011 particulate matter sensor, on a serial port
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Sds011Config {
+    /// Path to the serial port, e.g. "/dev/ttyUSB0"
+    pub path: String,
+    /// Duration the fan runs before each sample, in the form "30s", "1min", etc. The fan and
+    /// laser are stopped between samples when the sampling interval is long enough, extending
+    /// their lifetime. They run continuously if not configured.
+    pub warmup: Option<String>,
+    /// PM2.5 concentration, in µg/m³
+    pub pm2_5_series: Option<String>,
+    /// PM10 concentration, in µg/m³
+    pub pm10_series: Option<String>,
+}
+
+/// Winsen MH-Z19 CO2 sensor, on a serial port
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct Mhz19Config {
+    /// Path to the serial port, e.g. "/dev/ttyS0"
+    pub path: String,
+    /// CO2 concentration, in ppm
+    pub co2_series: String,
+}
+
+/// Senseair S8 CO2 sensor, on a serial port
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct SenseairS8Config {
+    /// Path to the serial port, e.g. "/dev/ttyS0"
+    pub path: String,
+    /// CO2 concentration, in ppm
+    pub co2_series: String,
+}
+
+/// Device read over Modbus, such as an energy meter or a solar inverter
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct ModbusConfig {
+    pub transport: ModbusTransport,
+    /// Unit identifier of the device (default 1)
+    #[serde(default = "ModbusConfig::default_unit_id")]
+    pub unit_id: u8,
+    /// Maximum response time, in the form "1s", "500ms", etc. (default "1s")
+    #[serde(default = "ModbusConfig::default_timeout")]
+    pub timeout: String,
+    /// Register read for each series. Adjacent registers are read with a single request.
+    pub series: HashMap<String, ModbusRegister>,
+}
+
+impl ModbusConfig {
+    fn default_unit_id() -> u8 {
+        1
+    }
+
+    fn default_timeout() -> String {
+        "1s".to_owned()
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModbusTransport {
+    Tcp {
+        host: String,
+        /// TCP port (default 502)
+        #[serde(default = "ModbusTransport::default_port")]
+        port: u16,
+    },
+    Rtu {
+        /// Path to the serial port, e.g. "/dev/ttyUSB0"
+        path: String,
+        /// Default "9600"
+        #[serde(default)]
+        baud_rate: ModbusBaudRate,
+        /// Default "even", as specified by Modbus
+        #[serde(default)]
+        parity: ModbusParity,
+    },
+}
+
+impl ModbusTransport {
+    fn default_port() -> u16 {
+        502
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ModbusBaudRate {
+    #[serde(rename = "1200")]
+    B1200,
+    #[serde(rename = "2400")]
+    B2400,
+    #[serde(rename = "4800")]
+    B4800,
+    #[default]
+    #[serde(rename = "9600")]
+    B9600,
+    #[serde(rename = "19200")]
+    B19200,
+    #[serde(rename = "38400")]
+    B38400,
+    #[serde(rename = "57600")]
+    B57600,
+    #[serde(rename = "115200")]
+    B115200,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModbusParity {
+    None,
+    #[default]
+    Even,
+    Odd,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct ModbusRegister {
+    /// Address of the (first) register, starting from 0
+    pub address: u16,
+    /// Default "holding"
+    #[serde(default)]
+    pub register_type: ModbusRegisterType,
+    /// Default "u16"
+    #[serde(default)]
+    pub data_type: ModbusDataType,
+    /// Order of the two registers of 32-bit values (default "high_word_first")
+    #[serde(default)]
+    pub word_order: ModbusWordOrder,
+    /// Factor the value is multiplied by (default 1)
+    #[serde(default = "ModbusRegister::default_scale")]
+    pub scale: f64,
+    /// Value added after scaling (default 0)
+    #[serde(default)]
+    pub offset: f64,
+}
+
+impl ModbusRegister {
+    fn default_scale() -> f64 {
+        1.0
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModbusRegisterType {
+    #[default]
+    Holding,
+    Input,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModbusDataType {
+    #[default]
+    U16,
+    I16,
+    U32,
+    I32,
+    /// IEEE 754 single precision float
+    F32,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModbusWordOrder {
+    #[default]
+    HighWordFirst,
+    LowWordFirst,
+}
+
+/// Network device polled over SNMP, such as a UPS, a switch or a router
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct SnmpConfig {
+    /// Host name or address of the agent
+    pub host: String,
+    /// UDP port (default 161)
+    #[serde(default = "SnmpConfig::default_port")]
+    pub port: u16,
+    pub version: SnmpVersion,
+    /// Maximum response time, in the form "2s", "500ms", etc. (default "2s")
+    #[serde(default = "SnmpConfig::default_timeout")]
+    pub timeout: String,
+    /// Object read for each series. All the objects are read together.
+    pub series: HashMap<String, SnmpObject>,
+}
+
+impl SnmpConfig {
+    fn default_port() -> u16 {
+        161
+    }
+
+    fn default_timeout() -> String {
+        "2s".to_owned()
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SnmpVersion {
+    V2c {
+        community: String,
+    },
+    V3 {
+        username: String,
+        /// Authentication, required by privacy. Messages are not authenticated if not
+        /// configured.
+        auth: Option<SnmpAuth>,
+        /// Encryption. Messages are sent in clear if not configured.
+        privacy: Option<SnmpPrivacy>,
+    },
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct SnmpAuth {
+    /// Default "sha"
+    #[serde(default)]
+    pub protocol: SnmpAuthProtocol,
+    /// At least 8 characters
+    pub password: String,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SnmpAuthProtocol {
+    Md5,
+    #[default]
+    Sha,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct SnmpPrivacy {
+    /// Default "aes"
+    #[serde(default)]
+    pub protocol: SnmpPrivacyProtocol,
+    /// At least 8 characters
+    pub password: String,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SnmpPrivacyProtocol {
+    Des,
+    /// AES-128
+    #[default]
+    Aes,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct SnmpObject {
+    /// Object identifier, in numeric dotted form, e.g. "1.3.6.1.2.1.1.3.0". Counter objects
+    /// are converted to a rate per second, and time ticks to seconds.
+    pub oid: String,
+    /// Factor the value is multiplied by (default 1)
+    #[serde(default = "SnmpObject::default_scale")]
+    pub scale: f64,
+    /// Value added after scaling (default 0)
+    #[serde(default)]
+    pub offset: f64,
+}
+
+impl SnmpObject {
+    fn default_scale() -> f64 {
+        1.0
+    }
+}
+
+/// Bluetooth LE devices broadcasting their measures in advertisements: Xiaomi thermometers
+/// running the ATC or pvvx firmware, BTHome v2 devices and RuuviTags. Scanning requires the
+/// CAP_NET_RAW capability.
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct BleConfig {
+    /// Index of the Bluetooth adapter, 0 for hci0 (default 0)
+    #[serde(default)]
+    pub adapter: u16,
+    /// Values older than this are not returned, in the form "5min", "1h", etc. (default "5min")
+    #[serde(default = "BleConfig::default_max_age")]
+    pub max_age: String,
+    pub series: HashMap<String, BleSeries>,
+}
+
+impl BleConfig {
+    fn default_max_age() -> String {
+        "5min".to_owned()
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct BleSeries {
+    /// MAC address of the device, e.g. "A4:C1:38:12:34:56"
+    pub mac: String,
+    pub measure: BleMeasure,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum BleMeasure {
+    /// In °C
+    Temperature,
+    /// Relative humidity, in %
+    Humidity,
+    /// In hPa
+    Pressure,
+    /// In °C
+    DewPoint,
+    /// Battery level, in %
+    Battery,
+    /// Battery voltage, in V
+    Voltage,
+    /// In lux
+    Illuminance,
+    /// CO2 concentration, in ppm
+    Co2,
+    /// PM2.5 concentration, in µg/m³
+    Pm2_5,
+    /// PM10 concentration, in µg/m³
+    Pm10,
+    /// Volatile organic compounds, in µg/m³
+    Tvoc,
+    /// Soil moisture, in %
+    Moisture,
+    /// In W
+    Power,
+    /// In kWh
+    Energy,
+    /// In g
+    AccelerationX,
+    /// In g
+    AccelerationY,
+    /// In g
+    AccelerationZ,
+    /// Number of movements detected by a RuuviTag
+    MovementCount,
+    /// Transmission power, in dBm
+    TxPower,
+    /// Received signal strength, in dBm
+    Rssi,
+}
+
+/// Number read from any file, such as "/sys/class/thermal/thermal_zone0/temp"
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct FileConfig {
+    pub path: String,
+    /// Regular expression extracting the number from the file content. The first capture
+    /// group is used if any, the whole match otherwise. The whole content is parsed if not
+    /// configured.
+    pub regex: Option<String>,
+    /// Factor the number is multiplied by (default 1)
+    #[serde(default = "FileConfig::default_scale")]
+    pub scale: f64,
+    /// Value added to the number after scaling (default 0)
+    #[serde(default)]
+    pub offset: f64,
+    pub series: String,
+}
+
+impl FileConfig {
+    fn default_scale() -> f64 {
+        1.0
+    }
+}
+
+/// Values printed by an external program on its standard output
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct CommandConfig {
+    /// Path to the executable
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Maximum run duration, in the form "10s", "500ms", etc. (default "10s")
+    #[serde(default = "CommandConfig::default_timeout")]
+    pub timeout: String,
+    /// Format of the standard output (default "number")
+    #[serde(default)]
+    pub output: CommandOutput,
+    /// Series fed by the command
+    pub series: Vec<String>,
+}
+
+impl CommandConfig {
+    fn default_timeout() -> String {
+        "10s".to_owned()
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandOutput {
+    /// A single number, for a single series
+    #[default]
+    Number,
+    /// A single state label such as "open", for a single series
+    State,
+    /// A JSON object mapping series ids to values, e.g. {"ups_load": 12, "on_battery": false}.
+    /// Arrays of numbers are vectors, and strings are states.
+    Json,
+}
+
+/// Health metrics of the host, read from /proc and /sys
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct SystemConfig {
+    /// Directory holding the proc and sys filesystems (default "/")
+    #[serde(default = "SystemConfig::default_root")]
+    pub root: String,
+    /// Path on the partition whose free space is measured. Defaults to the directory of the
+    /// database.
+    pub disk_path: Option<String>,
+    /// Block device whose I/O is measured (default "mmcblk0", the SD card)
+    #[serde(default = "SystemConfig::default_block_device")]
+    pub block_device: String,
+
+    /// CPU temperature, in °C
+    pub cpu_temperature_series: Option<String>,
+    /// Load average over the last minute
+    pub load_average_series: Option<String>,
+    /// Used memory, in % of the total memory
+    pub memory_usage_series: Option<String>,
+    /// Free space on the partition of `disk_path`, in MB
+    pub disk_free_series: Option<String>,
+    /// Data read from the block device, in kB/s
+    pub disk_read_series: Option<String>,
+    /// Data written to the block device, in kB/s
+    pub disk_write_series: Option<String>,
+    /// Throttling flags reported by the Raspberry Pi firmware (under-voltage, frequency
+    /// capping, throttling, soft temperature limit), as a bitmask
+    pub throttled_series: Option<String>,
+    /// Time since boot, in seconds
+    pub uptime_series: Option<String>,
+}
+
+impl SystemConfig {
+    fn default_root() -> String {
+        "/".to_owned()
+    }
+
+    fn default_block_device() -> String {
+        "mmcblk0".to_owned()
+    }
+}
+
+/// Input read from a GPIO line through the GPIO character device
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct GpioConfig {
+    /// Path to the GPIO chip (default "/dev/gpiochip0")
+    #[serde(default = "default_gpio_chip")]
+    pub chip: String,
+    /// Offset of the GPIO line on the chip
+    pub line: u32,
+    /// Inverts the line level, for contacts pulling the line low
+    #[serde(default)]
+    pub active_low: bool,
+    /// Debounce duration, in the form "5ms", "100us", etc. A counter ignores the edges coming
+    /// sooner than this after the last counted edge, such as contact bounces. A state is only
+    /// returned once the level has been stable for this duration. Disabled if not configured.
+    pub debounce: Option<String>,
+    pub mode: GpioMode,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GpioMode {
+    /// Counts pulses continuously, for rain gauges, anemometers, reed contact and S0 meters
+    Counter {
+        /// Edges counted as pulses (default "rising")
+        #[serde(default)]
+        edge: GpioEdge,
+        /// Units per pulse, e.g. 0.2794 mm per rain gauge tip or 0.001 kWh per S0 pulse
+        /// (default 1)
+        #[serde(default = "GpioMode::default_factor")]
+        factor: f64,
+        /// Pulses since the previous sample, multiplied by the factor
+        count_series: Option<String>,
+        /// Pulses per second since the previous sample, multiplied by the factor
+        rate_series: Option<String>,
+    },
+    /// Level of the line, 0 or 1, for doors and float switches
+    State { state_series: String },
+}
+
+impl GpioMode {
+    fn default_factor() -> f64 {
+        1.0
     }
 }
 
-#[derive(Deserialize, JsonSchema)]
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GpioEdge {
+    #[default]
+    Rising,
+    Falling,
+    Both,
+}
+
+/// Values extracted from the JSON response of an HTTP API, such as a smart plug or an inverter
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct HttpJsonConfig {
+    /// URL of the API. `{name}` placeholders are replaced by the matching `params` value, e.g.
+    /// "http://{host}/rpc/Switch.GetStatus?id={id}".
+    pub url: String,
+    /// Values of the URL placeholders
+    #[serde(default)]
+    pub params: HashMap<String, String>,
+    /// HTTP method (default "get")
+    #[serde(default)]
+    pub method: HttpMethod,
+    /// Request body, sent as is with POST requests
+    pub body: Option<String>,
+    /// Additional request headers, e.g. "Content-Type": "application/json"
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub auth: Option<HttpAuth>,
+    /// Maximum request duration, in the form "10s", "500ms", etc. (default "10s")
+    #[serde(default = "default_http_timeout")]
+    pub timeout: String,
+    /// Value extracted from the response for each series, by series id
+    pub series: HashMap<String, HttpJsonField>,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HttpMethod {
+    #[default]
+    Get,
+    Post,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HttpAuth {
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    Bearer {
+        token: String,
+    },
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct HttpJsonField {
+    /// JSON pointer to the value in the response, e.g. "/meters/0/power". Numbers, numeric
+    /// strings and booleans (as 0 or 1) are accepted.
+    pub pointer: String,
+    /// Factor the value is multiplied by (default 1)
+    #[serde(default = "HttpJsonField::default_scale")]
+    pub scale: f64,
+    /// Value added after scaling (default 0)
+    #[serde(default)]
+    pub offset: f64,
+}
+
+impl HttpJsonField {
+    fn default_scale() -> f64 {
+        1.0
+    }
+}
+
+/// Series fed by the weather fields common to all the weather providers. Fields a provider
+/// does not report are rejected when the sensor is created.
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct WeatherSeries {
+    /// Temperature, in °C
+    pub temperature_series: Option<String>,
+    /// Perceived temperature, in °C
+    pub feels_like_series: Option<String>,
+    /// Dew point, in °C
+    pub dew_point_series: Option<String>,
+    /// Relative humidity, in %
+    pub humidity_series: Option<String>,
+    /// Pressure at sea level, in hPa
+    pub pressure_series: Option<String>,
+    /// Wind speed, in m/s
+    pub wind_speed_series: Option<String>,
+    /// Wind direction, in degrees
+    pub wind_direction_series: Option<String>,
+    /// Wind gust, in m/s
+    pub wind_gust_series: Option<String>,
+    /// Cloudiness, in %
+    pub clouds_series: Option<String>,
+    /// Precipitation (rain and snow) over the last hour, in mm
+    pub precipitation_series: Option<String>,
+    /// Visibility, in m
+    pub visibility_series: Option<String>,
+}
+
+/// Current weather from the OpenWeatherMap API. All the series share a single API call.
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
 pub struct OpenWeatherMapConfig {
     pub api_key: String,
     pub lat: f64,
     pub lon: f64,
-    pub temperature_series: Option<String>,
+    /// Base URL of the API (default "https://api.openweathermap.org/data/2.5")
+    #[serde(default = "OpenWeatherMapConfig::default_base_url")]
+    pub base_url: String,
+    /// Maximum request duration, in the form "10s", "500ms", etc. (default "10s")
+    #[serde(default = "default_http_timeout")]
+    pub timeout: String,
+    /// Maximum number of API calls per day (default 1000, the free tier budget). Series sampled
+    /// more often reuse the last response.
+    #[serde(default = "OpenWeatherMapConfig::default_max_calls_per_day")]
+    pub max_calls_per_day: u32,
+    #[serde(flatten)]
+    pub weather: WeatherSeries,
+    /// Minimum temperature currently observed in the area, in °C
+    pub temperature_min_series: Option<String>,
+    /// Maximum temperature currently observed in the area, in °C
+    pub temperature_max_series: Option<String>,
+    /// Rain volume over the last hour, in mm
+    pub rain_1h_series: Option<String>,
+    /// Snow volume over the last hour, in mm
+    pub snow_1h_series: Option<String>,
+    /// Sunrise time, as a UNIX timestamp
+    pub sunrise_series: Option<String>,
+    /// Sunset time, as a UNIX timestamp
+    pub sunset_series: Option<String>,
+    /// Series fed with the 5 day / 3 hour forecast, whose records have future timestamps.
+    /// Precipitation is given in mm per hour. When configured, the API calls budget is split
+    /// between the current weather and the forecast.
+    pub forecast: Option<WeatherSeries>,
+}
+
+impl OpenWeatherMapConfig {
+    fn default_base_url() -> String {
+        "https://api.openweathermap.org/data/2.5".to_owned()
+    }
+
+    fn default_max_calls_per_day() -> u32 {
+        1000
+    }
+}
+
+/// Current weather from the Open-Meteo API, which requires no API key
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct OpenMeteoConfig {
+    pub lat: f64,
+    pub lon: f64,
+    /// Base URL of the API (default "https://api.open-meteo.com/v1")
+    #[serde(default = "OpenMeteoConfig::default_base_url")]
+    pub base_url: String,
+    /// Maximum request duration, in the form "10s", "500ms", etc. (default "10s")
+    #[serde(default = "default_http_timeout")]
+    pub timeout: String,
+    /// Maximum number of API calls per day (default 10000, the free tier budget)
+    #[serde(default = "OpenMeteoConfig::default_max_calls_per_day")]
+    pub max_calls_per_day: u32,
+    #[serde(flatten)]
+    pub weather: WeatherSeries,
+}
+
+impl OpenMeteoConfig {
+    fn default_base_url() -> String {
+        "https://api.open-meteo.com/v1".to_owned()
+    }
+
+    fn default_max_calls_per_day() -> u32 {
+        10000
+    }
+}
+
+/// Weather observed at an airport, from its METAR reports
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
+pub struct MetarConfig {
+    /// ICAO code of the airport, e.g. "LFPG"
+    pub station: String,
+    /// Base URL of the aviation weather API (default "https://aviationweather.gov/api/data")
+    #[serde(default = "MetarConfig::default_base_url")]
+    pub base_url: String,
+    /// Maximum request duration, in the form "10s", "500ms", etc. (default "10s")
+    #[serde(default = "default_http_timeout")]
+    pub timeout: String,
+    /// Maximum number of API calls per day (default 288, once every 5 minutes). Reports are
+    /// issued every 30 or 60 minutes.
+    #[serde(default = "MetarConfig::default_max_calls_per_day")]
+    pub max_calls_per_day: u32,
+    #[serde(flatten)]
+    pub weather: WeatherSeries,
 }
 
-#[derive(Deserialize, JsonSchema)]
-pub enum Bme280Address {
+impl MetarConfig {
+    fn default_base_url() -> String {
+        "https://aviationweather.gov/api/data".to_owned()
+    }
+
+    fn default_max_calls_per_day() -> u32 {
+        288
+    }
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq)]
+pub enum BoschAddress {
+    #[default]
     #[serde(rename = "0x76")]
     SdoGnd,
     #[serde(rename = "0x77")]
     SdoVddio,
 }
 
-impl Default for Bme280Address {
-    fn default() -> Self {
-        Bme280Address::SdoGnd
-    }
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BoschMode {
+    /// Measurements are performed continuously, separated by the standby time
+    #[default]
+    Normal,
+    /// A single measurement is performed on each sample, then the sensor goes back to sleep
+    Forced,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BoschOversampling {
+    /// The measurement is disabled
+    Skip,
+    X1,
+    X2,
+    X4,
+    #[default]
+    X8,
+    X16,
 }
 
-#[derive(Deserialize, JsonSchema)]
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BoschFilter {
+    Off,
+    X2,
+    X4,
+    X8,
+    #[default]
+    X16,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq)]
+pub enum Bme280Standby {
+    #[serde(rename = "0.5ms")]
+    Micros500,
+    #[serde(rename = "10ms")]
+    Millis10,
+    #[serde(rename = "20ms")]
+    Millis20,
+    #[serde(rename = "62.5ms")]
+    Micros62500,
+    #[serde(rename = "125ms")]
+    Millis125,
+    #[serde(rename = "250ms")]
+    Millis250,
+    #[serde(rename = "500ms")]
+    Millis500,
+    #[default]
+    #[serde(rename = "1000ms")]
+    Millis1000,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq)]
+pub enum Bmp280Standby {
+    #[serde(rename = "0.5ms")]
+    Micros500,
+    #[serde(rename = "62.5ms")]
+    Micros62500,
+    #[serde(rename = "125ms")]
+    Millis125,
+    #[serde(rename = "250ms")]
+    Millis250,
+    #[serde(rename = "500ms")]
+    Millis500,
+    #[default]
+    #[serde(rename = "1000ms")]
+    Millis1000,
+    #[serde(rename = "2000ms")]
+    Millis2000,
+    #[serde(rename = "4000ms")]
+    Millis4000,
+}
+
+#[derive(Deserialize, JsonSchema, Default, Clone, Copy, PartialEq)]
+pub enum Bme680Filter {
+    #[serde(rename = "off")]
+    Off,
+    #[serde(rename = "1")]
+    C1,
+    #[default]
+    #[serde(rename = "3")]
+    C3,
+    #[serde(rename = "7")]
+    C7,
+    #[serde(rename = "15")]
+    C15,
+    #[serde(rename = "31")]
+    C31,
+    #[serde(rename = "63")]
+    C63,
+    #[serde(rename = "127")]
+    C127,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, PartialEq)]
 pub struct SeriesConfig {
     pub id: String,
     pub name: String,
@@ -102,6 +1380,39 @@ pub struct SeriesConfig {
     pub color: String,
     /// Interval between two measures, in the form "1min", "30sec", "1h", etc.
     pub sampling_interval: String,
+    /// Whether the measures are instantaneous values or increasing totals (default gauge)
+    #[serde(default)]
+    pub kind: SeriesKind,
+    /// Value at which a counter wraps around to 0, e.g. 65536 for a 16 bits counter
+    pub wrap: Option<f64>,
+    /// Type of the values (default number)
+    #[serde(default)]
+    pub value_type: ValueType,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SeriesKind {
+    /// Instantaneous value, such as a temperature
+    #[default]
+    Gauge,
+    /// Monotonically increasing total, such as the energy of a meter or the rain of a gauge.
+    /// Its deltas and rates can be fetched from the API.
+    Counter,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueType {
+    #[default]
+    Number,
+    Integer,
+    /// Numbers 0 and 1 sampled by sensors are stored as false and true
+    Boolean,
+    /// Label of a state, such as "open" or "closed"
+    State,
+    /// 2D or 3D vector, such as the wind velocity or a GPS position
+    Vector,
 }
 
 impl SeriesConfig {
@@ -112,6 +1423,51 @@ impl SeriesConfig {
             unit: self.unit.clone(),
             category: self.category.clone(),
             color: self.color.clone(),
+            kind: self.kind,
+            wrap: self.wrap,
+            value_type: self.value_type,
         }
     }
 }
+
+fn default_i2c_path() -> String {
+    "/dev/i2c-1".to_owned()
+}
+
+fn default_gpio_chip() -> String {
+    "/dev/gpiochip0".to_owned()
+}
+
+fn default_w1_root() -> String {
+    "/sys/bus/w1/devices".to_owned()
+}
+
+fn default_http_timeout() -> String {
+    "10s".to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bme280_deprecated_address() {
+        let config: Bme280Config = serde_json::from_str(r#"{"address": "0x77"}"#).unwrap();
+        assert!(matches!(
+            config.bus().unwrap(),
+            BoschBusConfig::I2c { path, address: BoschAddress::SdoVddio } if path == "/dev/i2c-1"
+        ));
+
+        let config: Bme280Config = serde_json::from_str(r#"{"path": "/dev/i2c-3"}"#).unwrap();
+        assert!(matches!(
+            config.bus().unwrap(),
+            BoschBusConfig::I2c { path, address: BoschAddress::SdoGnd } if path == "/dev/i2c-3"
+        ));
+
+        let config: Bme280Config = serde_json::from_str(
+            r#"{"bus": {"spi": {"path": "/dev/spidev0.0"}}, "address": "0x77"}"#,
+        )
+        .unwrap();
+        assert!(config.bus().is_err());
+    }
+}
@@ -0,0 +1,321 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+
+use crate::config::Config;
+
+/// Includes nested deeper than this are assumed to form a cycle
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+impl Config {
+    /// Reads a configuration file, in TOML for a ".toml" extension, in YAML for ".yaml" and
+    /// ".yml", and in JSON otherwise. Strings may reference environment variables as `${NAME}`
+    /// and files as `${file:/run/secrets/name}`, and "$${" stands for a literal "${". Relative
+    /// file paths, like include paths, are relative to the directory of the file referencing them.
+    /// Returns the configuration with the files it has been read from: the file itself, its
+    /// includes and the referenced files.
+    pub fn load(path: &str) -> Result<(Config, Vec<PathBuf>)> {
+        let mut files = vec![];
+        let value = read(Path::new(path), 0, &mut files)?;
+        let config =
+            serde_json::from_value(value).with_context(|| format!("Invalid config file {path}"))?;
+        Ok((config, files))
+    }
+}
+
+/// Reads a configuration file and merges the files it includes into it. The files read are
+/// added to `files`.
+fn read(path: &Path, depth: usize, files: &mut Vec<PathBuf>) -> Result<Value> {
+    if depth > MAX_INCLUDE_DEPTH {
+        bail!(anyhow!(
+            "too many nested includes at {}, the includes may form a cycle",
+            path.display()
+        ));
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Cannot open config file {}", path.display()))?;
+    add_file(files, path);
+    let value: Value = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&content).map_err(anyhow::Error::from),
+        Some("yaml" | "yml") => serde_yaml::from_str(&content).map_err(anyhow::Error::from),
+        _ => serde_json::from_str(&content).map_err(anyhow::Error::from),
+    }
+    .with_context(|| format!("Cannot parse config file {}", path.display()))?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut value = substitute(value, dir, files)
+        .with_context(|| format!("Cannot substitute values in {}", path.display()))?;
+
+    let includes = match value.as_object_mut().and_then(|o| o.remove("include")) {
+        Some(Value::Array(includes)) => includes,
+        Some(_) => bail!(anyhow!(
+            "the include of {} must be a list of paths",
+            path.display()
+        )),
+        None => vec![],
+    };
+    for include in includes {
+        let Value::String(include) = include else {
+            bail!(anyhow!(
+                "the include of {} must be a list of paths",
+                path.display()
+            ));
+        };
+        merge(&mut value, read(&dir.join(include), depth + 1, files)?);
+    }
+    Ok(value)
+}
+
+/// Merges an included value: the lists are concatenated and the objects merged, while the other
+/// values of `value` take precedence.
+fn merge(value: &mut Value, included: Value) {
+    match (value, included) {
+        (Value::Array(values), Value::Array(included)) => values.extend(included),
+        (Value::Object(values), Value::Object(included)) => {
+            for (key, included) in included {
+                match values.get_mut(&key) {
+                    Some(value) => merge(value, included),
+                    None => {
+                        values.insert(key, included);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn add_file(files: &mut Vec<PathBuf>, path: &Path) {
+    if !files.iter().any(|f| f == path) {
+        files.push(path.to_owned());
+    }
+}
+
+/// Substitutes the references of the strings of a value, the relative file paths being relative
+/// to `dir`. The referenced files are added to `files`.
+fn substitute(value: Value, dir: &Path, files: &mut Vec<PathBuf>) -> Result<Value> {
+    Ok(match value {
+        Value::String(s) => Value::String(expand(&s, dir, files)?),
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| substitute(value, dir, files))
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(values) => Value::Object(
+            values
+                .into_iter()
+                .map(|(key, value)| Ok((key, substitute(value, dir, files)?)))
+                .collect::<Result<_>>()?,
+        ),
+        value => value,
+    })
+}
+
+/// Replaces the `${NAME}` and `${file:path}` references of a string by the value of the
+/// environment variable and the content of the file, without its trailing newline. Relative file
+/// paths are relative to `dir`.
+fn expand(s: &str, dir: &Path, files: &mut Vec<PathBuf>) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        if let Some(escaped) = rest[..start].strip_suffix('$') {
+            expanded.push_str(escaped);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        expanded.push_str(&rest[..start]);
+        let len = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("unclosed reference in \"{s}\""))?;
+        let reference = &rest[start + 2..start + len];
+        match reference.strip_prefix("file:") {
+            Some(path) => {
+                let path = dir.join(path);
+                let content = fs::read_to_string(&path).with_context(|| {
+                    format!("Cannot read {} for ${{{reference}}}", path.display())
+                })?;
+                add_file(files, &path);
+                expanded.push_str(content.trim_end_matches(['\n', '\r']));
+            }
+            None => {
+                let value = env::var(reference)
+                    .with_context(|| format!("Cannot read {reference} for ${{{reference}}}"))?;
+                expanded.push_str(&value);
+            }
+        }
+        rest = &rest[start + len + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Empty directory for the files of a test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("raspi-load-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn expand_references() {
+        let dir = test_dir("expand");
+        let mut files = vec![];
+        env::set_var("RASPI_LOAD_TEST_HOST", "example.org");
+        assert_eq!(
+            expand("https://${RASPI_LOAD_TEST_HOST}/api", &dir, &mut files).unwrap(),
+            "https://example.org/api"
+        );
+        assert_eq!(
+            expand("no reference", &dir, &mut files).unwrap(),
+            "no reference"
+        );
+        assert_eq!(
+            expand("$${RASPI_LOAD_TEST_HOST}", &dir, &mut files).unwrap(),
+            "${RASPI_LOAD_TEST_HOST}"
+        );
+        assert_eq!(
+            expand("a$${b} ${RASPI_LOAD_TEST_HOST} $c}", &dir, &mut files).unwrap(),
+            "a${b} example.org $c}"
+        );
+        assert!(expand("${RASPI_LOAD_TEST_HOST", &dir, &mut files).is_err());
+        assert!(expand("${RASPI_LOAD_TEST_UNDEFINED}", &dir, &mut files).is_err());
+        assert!(files.is_empty());
+
+        let secret = dir.join("secret");
+        for (content, expected) in [
+            ("s3cret\n", "s3cret"),
+            ("s3cret\r\n\n", "s3cret"),
+            ("line 1\nline 2\n", "line 1\nline 2"),
+        ] {
+            fs::write(&secret, content).unwrap();
+            let reference = format!("key=${{file:{}}}", secret.display());
+            assert_eq!(
+                expand(&reference, &dir, &mut files).unwrap(),
+                format!("key={expected}")
+            );
+        }
+        assert_eq!(files, [secret.as_path()]);
+        // Relative to the directory of the config file
+        fs::create_dir(dir.join("secrets")).unwrap();
+        fs::write(dir.join("secrets/token"), "t0ken\n").unwrap();
+        assert_eq!(
+            expand("${file:secrets/token}", &dir, &mut files).unwrap(),
+            "t0ken"
+        );
+        assert_eq!(
+            expand("${file:secret}", &dir.join("secrets"), &mut files)
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "Cannot read {} for ${{file:secret}}",
+                dir.join("secrets/secret").display()
+            )
+        );
+        assert_eq!(files, [secret, dir.join("secrets/token")]);
+        assert!(expand(
+            &format!("${{file:{}}}", dir.join("missing").display()),
+            &dir,
+            &mut files
+        )
+        .is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn merge_values() {
+        let mut value = json!({
+            "db_path": "main.db",
+            "recorder": {"sensors": [{"id": "a"}], "series": []},
+            "server": {"port": 8080},
+        });
+        merge(
+            &mut value,
+            json!({
+                "db_path": "included.db",
+                "recorder": {"sensors": [{"id": "b"}], "series": [{"id": "b"}]},
+                "server": {"port": 9090, "address": "0.0.0.0"},
+                "extra": [1],
+            }),
+        );
+        assert_eq!(
+            value,
+            json!({
+                "db_path": "main.db",
+                "recorder": {"sensors": [{"id": "a"}, {"id": "b"}], "series": [{"id": "b"}]},
+                "server": {"port": 8080, "address": "0.0.0.0"},
+                "extra": [1],
+            })
+        );
+        // Values of different types are not merged
+        let mut value = json!({"series": "a"});
+        merge(&mut value, json!({"series": ["b"]}));
+        assert_eq!(value, json!({"series": "a"}));
+    }
+
+    #[test]
+    fn includes() {
+        let dir = test_dir("includes");
+        fs::create_dir(dir.join("sensors")).unwrap();
+        fs::write(dir.join("token"), "t0k3n\n").unwrap();
+        fs::write(
+            dir.join("main.toml"),
+            format!(
+                "include = [\"sensors/weather.yaml\"]\ndb_path = \"main.db\"\ntoken = \"${{file:{}}}\"\n",
+                dir.join("token").display()
+            ),
+        )
+        .unwrap();
+        // Paths are relative to the including file
+        fs::write(
+            dir.join("sensors/weather.yaml"),
+            "include: [common.json]\ndb_path: weather.db\nkey: ${file:key}\nsensors: [weather]\n",
+        )
+        .unwrap();
+        fs::write(dir.join("sensors/key"), "k3y").unwrap();
+        fs::write(
+            dir.join("sensors/common.json"),
+            r#"{"sensors": ["common"], "port": 8080}"#,
+        )
+        .unwrap();
+
+        let mut files = vec![];
+        let value = read(&dir.join("main.toml"), 0, &mut files).unwrap();
+        assert_eq!(
+            value,
+            json!({"db_path": "main.db", "token": "t0k3n", "key": "k3y", "sensors": ["weather", "common"], "port": 8080})
+        );
+        assert_eq!(
+            files,
+            [
+                dir.join("main.toml"),
+                dir.join("token"),
+                dir.join("sensors/weather.yaml"),
+                dir.join("sensors/key"),
+                dir.join("sensors/common.json"),
+            ]
+        );
+
+        // Cycles are detected through the depth of the includes
+        fs::write(dir.join("a.json"), r#"{"include": ["b.json"]}"#).unwrap();
+        fs::write(dir.join("b.json"), r#"{"include": ["a.json"]}"#).unwrap();
+        let err = read(&dir.join("a.json"), 0, &mut vec![]).unwrap_err();
+        assert!(
+            err.to_string().contains("the includes may form a cycle"),
+            "{err:#}"
+        );
+
+        fs::write(dir.join("c.json"), r#"{"include": "a.json"}"#).unwrap();
+        assert!(read(&dir.join("c.json"), 0, &mut vec![]).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::thread::sleep;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
@@ -8,29 +8,54 @@ use sensors::{sensor_factory, Sensor};
 use series::SeriesState;
 use store::Store;
 
-use crate::record::Record;
+use crate::record::{Record, TypedRecord, Value};
 
 pub mod config;
 mod record;
-mod sensors;
+pub mod sensors;
 mod series;
 pub mod server;
 mod store;
 
 pub struct Recorder {
     store: Store,
+    db_path: String,
     sensors: HashMap<String, Box<dyn Sensor>>,
+    /// Configurations the sensors were built from, to rebuild only the changed ones on reload
+    sensor_configs: HashMap<String, config::SensorConfig>,
     series: HashMap<String, SeriesState>,
     sensor_by_series: HashMap<String, String>,
+    /// Series fed with forecasts
+    forecast_series: HashSet<String>,
 }
 
 impl Recorder {
     pub fn new(cfg: config::Recorder, db_path: &str) -> Result<Recorder> {
-        let store = Store::new(db_path)?;
-        let mut sensors = HashMap::new();
+        let mut recorder = Recorder {
+            store: Store::new(db_path)?,
+            db_path: db_path.to_owned(),
+            sensors: HashMap::new(),
+            sensor_configs: HashMap::new(),
+            series: HashMap::new(),
+            sensor_by_series: HashMap::new(),
+            forecast_series: HashSet::new(),
+        };
+        recorder.apply(cfg)?;
+        Ok(recorder)
+    }
+
+    /// Applies a new configuration in place. Only the added and changed sensors are built, and
+    /// the series keep their sampling schedule. The current configuration is kept if the new one
+    /// is invalid.
+    pub fn reload(&mut self, cfg: config::Recorder) -> Result<()> {
+        self.apply(cfg)?;
+        println!("Configuration reloaded");
+        Ok(())
+    }
+
+    fn apply(&mut self, cfg: config::Recorder) -> Result<()> {
         let mut series_state = HashMap::new();
         let mut series_def = vec![];
-        let mut sensor_by_series = HashMap::new();
 
         // Create series
         for series_cfg in cfg.series {
@@ -41,13 +66,98 @@ impl Recorder {
             series_def.push(series_cfg.to_series_def());
         }
 
-        store.update_series(&series_def)?;
-
-        // Create sensors
+        let mut sensor_configs = HashMap::new();
         for sensor_cfg in cfg.sensors {
-            let sensor_id = sensor_cfg.id.clone();
-            let sensor = sensor_factory(sensor_cfg.config)?;
-            for s in sensor.series() {
+            let sensor_id = sensor_cfg.id;
+            let sensor_cfg = sensor_cfg.config;
+            if sensor_configs.insert(sensor_id.clone(), sensor_cfg).is_some() {
+                bail!(anyhow!("the \"{sensor_id}\" sensor is defined twice"));
+            }
+        }
+
+        let changed: Vec<_> = self
+            .sensors
+            .keys()
+            .filter(|id| sensor_configs.get(*id) != self.sensor_configs.get(*id))
+            .cloned()
+            .collect();
+        let mut dropped = vec![];
+        let result = self
+            .create_sensors(&sensor_configs, &changed, &mut dropped)
+            .and_then(|sensors| {
+                let associations =
+                    self.associate(sensor_configs.keys(), &sensors, &series_state)?;
+                self.store.update_series(&series_def)?;
+                Ok((sensors, associations))
+            });
+        let (sensors, (sensor_by_series, forecast_series)) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                self.restore(&dropped);
+                return Err(e);
+            }
+        };
+
+        // Series kept from the previous configuration continue their schedule
+        for (id, s) in series_state.iter_mut() {
+            if let Some(prev) = self.series.remove(id) {
+                s.last_measure_instant = prev.last_measure_instant;
+                s.last_measure = prev.last_measure;
+            }
+        }
+        self.sensors.retain(|id, _| sensor_configs.contains_key(id));
+        self.sensors.extend(sensors);
+        self.sensor_configs = sensor_configs;
+        self.series = series_state;
+        self.sensor_by_series = sensor_by_series;
+        self.forecast_series = forecast_series;
+        Ok(())
+    }
+
+    /// Creates the sensors that are not running yet, and the `changed` ones. A changed sensor is
+    /// built while the running one is kept, unless this fails as the running one may hold the
+    /// device: it is then dropped, and its id added to `dropped`.
+    fn create_sensors(
+        &mut self,
+        sensor_configs: &HashMap<String, config::SensorConfig>,
+        changed: &[String],
+        dropped: &mut Vec<String>,
+    ) -> Result<HashMap<String, Box<dyn Sensor>>> {
+        let mut sensors = HashMap::new();
+        for (sensor_id, sensor_cfg) in sensor_configs {
+            if self.sensors.contains_key(sensor_id) && !changed.contains(sensor_id) {
+                continue;
+            }
+            let sensor = match sensor_factory(sensor_cfg.clone(), &self.db_path) {
+                Ok(sensor) => sensor,
+                Err(_) if self.sensors.remove(sensor_id).is_some() => {
+                    dropped.push(sensor_id.clone());
+                    sensor_factory(sensor_cfg.clone(), &self.db_path)?
+                }
+                Err(e) => return Err(e),
+            };
+            sensors.insert(sensor_id.clone(), sensor);
+        }
+        Ok(sensors)
+    }
+
+    /// Maps the series to the sensors feeding them, checking that each series is fed by exactly
+    /// one sensor. Returns the mapping and the series fed with forecasts.
+    fn associate<'a>(
+        &self,
+        sensor_ids: impl Iterator<Item = &'a String>,
+        new_sensors: &HashMap<String, Box<dyn Sensor>>,
+        series_state: &HashMap<String, SeriesState>,
+    ) -> Result<(HashMap<String, String>, HashSet<String>)> {
+        let mut sensor_by_series = HashMap::new();
+        let mut forecast_series = HashSet::new();
+        for sensor_id in sensor_ids {
+            let sensor = new_sensors
+                .get(sensor_id)
+                .or_else(|| self.sensors.get(sensor_id))
+                .ok_or_else(|| anyhow!("no sensor with id {sensor_id}"))?;
+            forecast_series.extend(sensor.forecast_series());
+            for s in sensor.series().into_iter().chain(sensor.forecast_series()) {
                 if !series_state.contains_key(&s) {
                     bail!(anyhow!(
                         "the \"{s}\" series associated to \"{sensor_id}\" sensor does not exist"
@@ -58,7 +168,12 @@ impl Recorder {
                     bail!(anyhow!("the \"{s}\" series is associated to \"{prev_sensor}\" and \"{sensor_id}\" sensors"));
                 }
             }
-            sensors.insert(sensor_id, sensor);
+            // Forecasts are stored as numbers
+            for s in sensor.forecast_series() {
+                if series_state[&s].value_type != config::ValueType::Number {
+                    bail!(anyhow!("the \"{s}\" forecast series must hold numbers"));
+                }
+            }
         }
 
         // Check for unused series
@@ -69,23 +184,48 @@ impl Recorder {
                 ));
             }
         }
+        Ok((sensor_by_series, forecast_series))
+    }
 
-        Ok(Recorder {
-            sensors,
-            series: series_state,
-            sensor_by_series,
-            store,
-        })
+    /// Rebuilds the sensors dropped by a configuration that could not be applied. The sensors
+    /// that cannot be rebuilt are built again by the next reload, their series fail until then.
+    fn restore(&mut self, ids: &[String]) {
+        for id in ids {
+            match sensor_factory(self.sensor_configs[id].clone(), &self.db_path) {
+                Ok(sensor) => {
+                    self.sensors.insert(id.clone(), sensor);
+                }
+                Err(e) => println!("Warning: cannot restore \"{id}\" sensor: {e:#}"),
+            }
+        }
     }
 
-    pub fn run(&mut self) -> Result<()> {
+    /// Samples the series until no series remain. `reload` is polled every second, and returns
+    /// a new configuration when it should be applied.
+    pub fn run(
+        &mut self,
+        mut reload: impl FnMut() -> Option<Result<config::Recorder>>,
+    ) -> Result<()> {
         loop {
             let next_measure = self.next_measure_instant();
             if let Some(t) = next_measure {
                 let now = Instant::now();
                 let wait = t.saturating_duration_since(now);
-                sleep(wait);
-                self.measure()?;
+                sleep(wait.min(Duration::from_secs(1)));
+                match reload() {
+                    Some(Ok(cfg)) => {
+                        if let Err(e) = self.reload(cfg) {
+                            println!("Warning: keeping the current configuration, the new one is invalid: {e:#}");
+                        }
+                    }
+                    Some(Err(e)) => {
+                        println!("Warning: keeping the current configuration, the new one cannot be read: {e:#}");
+                    }
+                    None => {}
+                }
+                if Instant::now() >= t {
+                    self.measure()?;
+                }
             } else {
                 println!("Warning: no series have been configured");
                 return Ok(());
@@ -110,12 +250,42 @@ impl Recorder {
             let next = s.next_measure_instant();
             if next <= now {
                 let sensor_id = &self.sensor_by_series[id];
-                let record = self
-                    .sensors
-                    .get_mut(sensor_id)
-                    .ok_or_else(|| anyhow!("no sensor with id {sensor_id}"))?
-                    .sample(id)
-                    .map(|v| Record {
+                let Some(sensor) = self.sensors.get_mut(sensor_id) else {
+                    println!("Cannot measure {id}: the \"{sensor_id}\" sensor could not be restored");
+                    s.notify_measured(Err(anyhow!("the \"{sensor_id}\" sensor is not running")));
+                    continue;
+                };
+                if self.forecast_series.contains(id) {
+                    let forecast = sensor.forecast(id);
+                    match &forecast {
+                        Ok(forecast) => {
+                            println!(
+                                "Measured \"{}\" forecast series with \"{}\" sensor: got {} values issued at {}",
+                                id,
+                                sensor_id,
+                                forecast.records.len(),
+                                forecast.issued_at
+                            );
+                            self.store.save_forecast(forecast, id)?;
+                        }
+                        Err(e) => {
+                            println!("Cannot measure {id} with {sensor_id} sensor: {e}");
+                        }
+                    }
+                    // The value forecast for the nearest timestamp stands for the last measure
+                    s.notify_measured(forecast.and_then(|f| {
+                        f.records
+                            .first()
+                            .map(|r| TypedRecord::from(*r))
+                            .ok_or_else(|| anyhow!("empty forecast"))
+                    }));
+                    continue;
+                }
+
+                let record = sensor
+                    .sample_value(id)
+                    .and_then(|v| v.into_type(s.value_type))
+                    .map(|v| TypedRecord {
                         timestamp: SystemTime::now()
                             .duration_since(UNIX_EPOCH)
                             .unwrap()
@@ -129,7 +299,17 @@ impl Recorder {
                             "Measured \"{}\" series with \"{}\" sensor: got {}",
                             id, sensor_id, record.value
                         );
-                        self.store.save(*record, id)?;
+                        // Numbers are kept in their own table, for the numeric queries
+                        match record.value {
+                            Value::Number(value) => self.store.save(
+                                Record {
+                                    timestamp: record.timestamp,
+                                    value,
+                                },
+                                id,
+                            )?,
+                            _ => self.store.save_typed(record, id)?,
+                        }
                     }
                     Err(e) => {
                         println!("Cannot measure {id} with {sensor_id} sensor: {e}");
@@ -141,3 +321,178 @@ impl Recorder {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(id: &str, value_type: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "name": id,
+            "unit": "",
+            "category": "Test",
+            "color": "#000000",
+            "sampling_interval": "1min",
+            "value_type": value_type,
+        })
+    }
+
+    fn recorder_config(
+        sensors: serde_json::Value,
+        series: Vec<serde_json::Value>,
+    ) -> config::Recorder {
+        serde_json::from_value(serde_json::json!({"sensors": sensors, "series": series})).unwrap()
+    }
+
+    fn file_sensor(id: &str, series: &str) -> serde_json::Value {
+        serde_json::json!({"id": id, "config": {"file": {"path": "/nonexistent", "series": series}}})
+    }
+
+    fn command_sensor(id: &str, arg: &str, series: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "config": {"command": {"command": "echo", "args": [arg], "series": [series]}},
+        })
+    }
+
+    /// Address of a sensor, telling whether it has been rebuilt
+    fn address(recorder: &Recorder, id: &str) -> *const () {
+        &*recorder.sensors[id] as *const dyn Sensor as *const ()
+    }
+
+    #[test]
+    fn reload() {
+        let cfg = recorder_config(
+            serde_json::json!([file_sensor("a", "a"), command_sensor("b", "1", "b")]),
+            vec![series("a", "number"), series("b", "number")],
+        );
+        let mut recorder = Recorder::new(cfg, ":memory:").unwrap();
+        let measured = Instant::now();
+        for s in recorder.series.values_mut() {
+            s.last_measure_instant = Some(measured);
+        }
+        let (a, b) = (address(&recorder, "a"), address(&recorder, "b"));
+
+        // Unchanged sensors are kept, changed ones rebuilt
+        let cfg = recorder_config(
+            serde_json::json!([
+                file_sensor("a", "a"),
+                command_sensor("b", "2", "b"),
+                file_sensor("c", "c"),
+            ]),
+            vec![
+                series("a", "number"),
+                series("b", "number"),
+                series("c", "number"),
+            ],
+        );
+        recorder.reload(cfg).unwrap();
+        assert_eq!(address(&recorder, "a"), a);
+        assert_ne!(address(&recorder, "b"), b);
+        let b = address(&recorder, "b");
+        assert_eq!(recorder.sensor_by_series["c"], "c");
+        assert_eq!(recorder.series["a"].last_measure_instant, Some(measured));
+        assert_eq!(recorder.series["b"].last_measure_instant, Some(measured));
+        assert_eq!(recorder.series["c"].last_measure_instant, None);
+
+        // Invalid configurations are not applied, even partially
+        let sensor_configs = recorder.sensor_configs.clone();
+        let sensor_by_series = recorder.sensor_by_series.clone();
+        let cfg = recorder_config(
+            serde_json::json!([file_sensor("a", "a"), command_sensor("b", "3", "b")]),
+            vec![
+                series("a", "number"),
+                series("b", "number"),
+                series("c", "number"),
+            ],
+        );
+        let err = recorder.reload(cfg).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the \"c\" series is not associated to any sensors"
+        );
+        assert_eq!(address(&recorder, "a"), a);
+        assert_eq!(address(&recorder, "b"), b);
+        assert!(recorder.sensor_configs == sensor_configs);
+        assert_eq!(recorder.sensor_by_series, sensor_by_series);
+        assert_eq!(recorder.series.len(), 3);
+        assert_eq!(recorder.series["a"].last_measure_instant, Some(measured));
+
+        // Removed sensors and series are dropped
+        let cfg = recorder_config(
+            serde_json::json!([file_sensor("a", "a")]),
+            vec![series("a", "number")],
+        );
+        recorder.reload(cfg).unwrap();
+        assert_eq!(recorder.sensors.len(), 1);
+        assert_eq!(address(&recorder, "a"), a);
+        assert_eq!(recorder.series.len(), 1);
+        assert_eq!(recorder.series["a"].last_measure_instant, Some(measured));
+    }
+
+    #[test]
+    fn failed_restore() {
+        let cfg = || {
+            recorder_config(
+                serde_json::json!([file_sensor("a", "a"), command_sensor("b", "1", "b")]),
+                vec![series("a", "number"), series("b", "number")],
+            )
+        };
+        let mut recorder = Recorder::new(cfg(), ":memory:").unwrap();
+        // The sensor dropped by an invalid configuration cannot be rebuilt anymore
+        recorder.sensors.remove("a");
+        let invalid =
+            serde_json::json!({"file": {"path": "/nonexistent", "regex": "(", "series": "a"}});
+        recorder
+            .sensor_configs
+            .insert("a".to_owned(), serde_json::from_value(invalid).unwrap());
+        recorder.restore(&["a".to_owned()]);
+        assert!(!recorder.sensors.contains_key("a"));
+
+        // Its series fail while the others are still sampled
+        recorder.measure().unwrap();
+        assert!(matches!(recorder.series["a"].last_measure, Some(Err(_))));
+        let b = recorder.series["b"].last_measure.as_ref().unwrap();
+        assert_eq!(b.as_ref().unwrap().value, Value::Number(1.0));
+
+        // The next reload builds it again
+        recorder.reload(cfg()).unwrap();
+        assert!(recorder.sensors.contains_key("a"));
+    }
+
+    #[test]
+    fn forecast_numbers() {
+        let sensors = serde_json::json!([{
+            "id": "weather",
+            "config": {"open_weather_map": {
+                "api_key": "key",
+                "lat": 48.85,
+                "lon": 2.35,
+                "temperature_series": "temperature",
+                "forecast": {"temperature_series": "temperature_forecast"},
+            }},
+        }]);
+        let cfg = recorder_config(
+            sensors.clone(),
+            vec![
+                series("temperature", "number"),
+                series("temperature_forecast", "number"),
+            ],
+        );
+        assert!(Recorder::new(cfg, ":memory:").is_ok());
+
+        let cfg = recorder_config(
+            sensors,
+            vec![
+                series("temperature", "number"),
+                series("temperature_forecast", "integer"),
+            ],
+        );
+        let err = Recorder::new(cfg, ":memory:").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "the \"temperature_forecast\" forecast series must hold numbers"
+        );
+    }
+}
@@ -1,7 +1,171 @@
+use std::fmt::Display;
+
+use anyhow::{anyhow, bail, Result};
 use serde::{Deserialize, Serialize};
 
+use crate::config::ValueType;
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Record {
     pub timestamp: u64,
     pub value: f64,
 }
+
+/// Values forecast for future timestamps, all issued at the same time
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Forecast {
+    pub issued_at: u64,
+    pub records: Vec<Record>,
+}
+
+/// Value of a series, of the type configured for it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    State(String),
+    /// 2D or 3D vector, such as the wind velocity or a GPS position
+    Vector(Vec<f64>),
+}
+
+impl Value {
+    /// Converts the value to a type, when no information is lost: integers to numbers, and
+    /// numbers to integers or booleans (0 or 1).
+    pub fn into_type(self, value_type: ValueType) -> Result<Value> {
+        match (value_type, self) {
+            (ValueType::Number, Value::Integer(i)) => Ok(Value::Number(i as f64)),
+            (ValueType::Integer, Value::Number(n))
+                if n.fract() == 0.0 && n.abs() < 2f64.powi(63) =>
+            {
+                Ok(Value::Integer(n as i64))
+            }
+            (ValueType::Boolean, Value::Number(n)) if n == 0.0 || n == 1.0 => {
+                Ok(Value::Boolean(n == 1.0))
+            }
+            (ValueType::Boolean, Value::Integer(i @ (0 | 1))) => Ok(Value::Boolean(i == 1)),
+            (ValueType::Vector, Value::Vector(v)) if !(2..=3).contains(&v.len()) => {
+                bail!(anyhow!(
+                    "expected a 2D or 3D vector, got {} components",
+                    v.len()
+                ))
+            }
+            (value_type, value) if value.value_type() == value_type => Ok(value),
+            (value_type, value) => bail!(anyhow!(
+                "{value} is not a valid {} value",
+                format!("{value_type:?}").to_lowercase()
+            )),
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::Boolean(_) => ValueType::Boolean,
+            Value::Integer(_) => ValueType::Integer,
+            Value::Number(_) => ValueType::Number,
+            Value::State(_) => ValueType::State,
+            Value::Vector(_) => ValueType::Vector,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Boolean(b) => write!(f, "{b}"),
+            Value::Integer(i) => write!(f, "{i}"),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::State(s) => write!(f, "\"{s}\""),
+            Value::Vector(v) => write!(f, "{v:?}"),
+        }
+    }
+}
+
+/// Record of a series of any value type
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TypedRecord {
+    pub timestamp: u64,
+    pub value: Value,
+}
+
+impl From<Record> for TypedRecord {
+    fn from(record: Record) -> Self {
+        TypedRecord {
+            timestamp: record.timestamp,
+            value: Value::Number(record.value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn into_type() {
+        let convert = |value: Value, value_type| value.into_type(value_type).ok();
+        assert_eq!(
+            convert(Value::Number(1.0), ValueType::Integer),
+            Some(Value::Integer(1))
+        );
+        assert_eq!(
+            convert(Value::Number(-42.0), ValueType::Integer),
+            Some(Value::Integer(-42))
+        );
+        assert_eq!(convert(Value::Number(1.5), ValueType::Integer), None);
+        assert_eq!(convert(Value::Number(1e19), ValueType::Integer), None);
+        assert_eq!(
+            convert(Value::Integer(3), ValueType::Number),
+            Some(Value::Number(3.0))
+        );
+
+        assert_eq!(
+            convert(Value::Number(0.0), ValueType::Boolean),
+            Some(Value::Boolean(false))
+        );
+        assert_eq!(
+            convert(Value::Number(1.0), ValueType::Boolean),
+            Some(Value::Boolean(true))
+        );
+        assert_eq!(
+            convert(Value::Integer(0), ValueType::Boolean),
+            Some(Value::Boolean(false))
+        );
+        assert_eq!(
+            convert(Value::Integer(1), ValueType::Boolean),
+            Some(Value::Boolean(true))
+        );
+        assert_eq!(convert(Value::Integer(2), ValueType::Boolean), None);
+        let err = Value::Number(2.0)
+            .into_type(ValueType::Boolean)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "2 is not a valid boolean value");
+
+        for len in [2, 3] {
+            let vector = Value::Vector(vec![1.0; len]);
+            assert_eq!(convert(vector.clone(), ValueType::Vector), Some(vector));
+        }
+        for len in [0, 1, 4] {
+            let err = Value::Vector(vec![1.0; len])
+                .into_type(ValueType::Vector)
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("expected a 2D or 3D vector, got {len} components")
+            );
+        }
+
+        let state = Value::State("open".to_owned());
+        assert_eq!(
+            convert(state.clone(), ValueType::State),
+            Some(state.clone())
+        );
+        assert_eq!(convert(state, ValueType::Number), None);
+        assert_eq!(convert(Value::Boolean(true), ValueType::Number), None);
+        assert_eq!(
+            convert(Value::Vector(vec![1.0, 2.0]), ValueType::Number),
+            None
+        );
+    }
+}
@@ -1,22 +1,86 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 use crate::config::SensorConfig;
+use crate::record::{Forecast, Value};
 
+mod adc;
+mod ble;
 mod bme280;
-mod ds18b20;
+mod bme680;
+mod bmp280;
+mod bosch;
+mod cache;
+mod co2;
+mod command;
+pub mod ds18b20;
+mod ds2413;
+mod ds2438;
+mod file;
+mod gpio;
+mod http;
+mod http_json;
+mod metar;
+mod modbus;
+pub mod one_wire;
+mod open_meteo;
 mod open_weather_map;
+mod particulate;
+mod serial;
+mod sht;
+mod snmp;
+mod system;
+mod weather;
 
 pub trait Sensor {
     fn sample(&mut self, series: &str) -> Result<f64>;
     fn series(&self) -> Vec<String>;
+
+    /// Samples a value of any type, for the sensors measuring more than numbers
+    fn sample_value(&mut self, series: &str) -> Result<Value> {
+        self.sample(series).map(Value::Number)
+    }
+
+    /// Series fed with forecasts, sampled with `forecast` rather than `sample`
+    fn forecast_series(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn forecast(&mut self, series: &str) -> Result<Forecast> {
+        Err(anyhow!("no forecast series configured with name {series}"))
+    }
 }
 
-pub fn sensor_factory(cfg: SensorConfig) -> Result<Box<dyn Sensor>> {
+/// Builds the sensor of a configuration. `db_path` is the path of the database, whose partition
+/// is monitored by the system sensor by default.
+pub fn sensor_factory(cfg: SensorConfig, db_path: &str) -> Result<Box<dyn Sensor>> {
     match cfg {
         SensorConfig::Ds18b20(cfg) => Ok(Box::new(ds18b20::Ds18b20::new(cfg)?)),
+        SensorConfig::Ds18b20Bus(cfg) => Ok(Box::new(ds18b20::Ds18b20Bus::new(cfg)?)),
+        SensorConfig::Ds2438(cfg) => Ok(Box::new(ds2438::Ds2438::new(cfg)?)),
+        SensorConfig::Ds2413(cfg) => Ok(Box::new(ds2413::Ds2413::new(cfg)?)),
         SensorConfig::Bme280(cfg) => Ok(Box::new(bme280::Bme280::new(cfg)?)),
+        SensorConfig::Bmp280(cfg) => Ok(Box::new(bmp280::Bmp280::new(cfg)?)),
+        SensorConfig::Bme680(cfg) => Ok(Box::new(bme680::Bme680::new(cfg)?)),
+        SensorConfig::Sht3x(cfg) => Ok(Box::new(sht::Sht3x::new(cfg)?)),
+        SensorConfig::Sht4x(cfg) => Ok(Box::new(sht::Sht4x::new(cfg)?)),
+        SensorConfig::Ads1115(cfg) => Ok(Box::new(adc::Ads1115::new(cfg)?)),
+        SensorConfig::Mcp3008(cfg) => Ok(Box::new(adc::Mcp3008::new(cfg)?)),
+        SensorConfig::Pms5003(cfg) => Ok(Box::new(particulate::Particulate::pms5003(cfg)?)),
+        SensorConfig::Sds011(cfg) => Ok(Box::new(particulate::Particulate::sds011(cfg)?)),
+        SensorConfig::Mhz19(cfg) => Ok(Box::new(co2::Mhz19::new(cfg)?)),
+        SensorConfig::SenseairS8(cfg) => Ok(Box::new(co2::SenseairS8::new(cfg)?)),
+        SensorConfig::Modbus(cfg) => Ok(Box::new(modbus::Modbus::new(cfg)?)),
+        SensorConfig::Snmp(cfg) => Ok(Box::new(snmp::Snmp::new(cfg)?)),
+        SensorConfig::Ble(cfg) => Ok(Box::new(ble::Ble::new(cfg)?)),
+        SensorConfig::File(cfg) => Ok(Box::new(file::File::new(cfg)?)),
+        SensorConfig::Command(cfg) => Ok(Box::new(command::Command::new(cfg)?)),
+        SensorConfig::System(cfg) => Ok(Box::new(system::System::new(cfg, db_path)?)),
+        SensorConfig::Gpio(cfg) => Ok(Box::new(gpio::Gpio::new(cfg)?)),
+        SensorConfig::HttpJson(cfg) => Ok(Box::new(http_json::HttpJson::new(cfg)?)),
         SensorConfig::OpenWeatherMap(cfg) => {
-            Ok(Box::new(open_weather_map::OpenWeatherMap::new(cfg)))
+            Ok(Box::new(open_weather_map::OpenWeatherMap::new(*cfg)?))
         }
+        SensorConfig::OpenMeteo(cfg) => Ok(Box::new(open_meteo::OpenMeteo::new(cfg)?)),
+        SensorConfig::Metar(cfg) => Ok(Box::new(metar::Metar::new(cfg)?)),
     }
 }
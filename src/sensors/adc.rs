@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::config::{
+    AdcConversion, AdcInput, Ads1115Address, Ads1115Channel, Ads1115Config, Ads1115DataRate,
+    Ads1115Gain, Mcp3008Channel, Mcp3008Config,
+};
+use crate::sensors::Sensor;
+use anyhow::{anyhow, bail, Context, Result};
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use linux_embedded_hal::spidev::{SpiModeFlags, Spidev, SpidevOptions, SpidevTransfer};
+use linux_embedded_hal::I2cdev;
+
+const ADS1115_REG_CONVERSION: u8 = 0x00;
+const ADS1115_REG_CONFIG: u8 = 0x01;
+/// Written to start a single conversion, read as set once the conversion is done
+const ADS1115_OS: u16 = 1 << 15;
+const MCP3008_MAX_SPI_FREQ: u32 = 3_600_000;
+
+/// Texas Instruments ADS1115 analog to digital converter
+pub struct Ads1115 {
+    i2c: I2cdev,
+    address: u8,
+    data_rate: Ads1115DataRate,
+    channels: HashMap<String, Ads1115Channel>,
+}
+
+impl Ads1115 {
+    pub fn new(config: Ads1115Config) -> Result<Ads1115> {
+        let address = match config.address {
+            Ads1115Address::Gnd => 0x48,
+            Ads1115Address::Vdd => 0x49,
+            Ads1115Address::Sda => 0x4A,
+            Ads1115Address::Scl => 0x4B,
+        };
+        let mut channels = HashMap::new();
+        for channel in config.channels {
+            ads1115_mux(channel.input)?;
+            check_conversion(&channel.conversion, &channel.series)?;
+            let series = channel.series.clone();
+            if channels.insert(series.clone(), channel).is_some() {
+                bail!(anyhow!(
+                    "the \"{series}\" series is associated to several channels"
+                ));
+            }
+        }
+        let i2c = I2cdev::new(&config.path).context("Cannot open I2C bus")?;
+        Ok(Ads1115 {
+            i2c,
+            address,
+            data_rate: config.data_rate,
+            channels,
+        })
+    }
+
+    /// Performs a single conversion and returns the voltage.
+    fn read_voltage(&mut self, input: AdcInput, gain: Ads1115Gain) -> Result<f64> {
+        let (config, full_scale, sps) = ads1115_config(input, gain, self.data_rate)?;
+        let [msb, lsb] = config.to_be_bytes();
+        self.i2c
+            .write(self.address, &[ADS1115_REG_CONFIG, msb, lsb])
+            .context("Cannot start ADS1115 conversion")?;
+
+        let conversion_time = Duration::from_secs(1) / sps;
+        sleep(conversion_time);
+        let start = Instant::now();
+        while self.read_register(ADS1115_REG_CONFIG)? & ADS1115_OS == 0 {
+            if start.elapsed() > conversion_time * 2 {
+                bail!(anyhow!("ADS1115 conversion timed out"));
+            }
+            sleep(Duration::from_millis(1));
+        }
+        let raw = self.read_register(ADS1115_REG_CONVERSION)? as i16;
+        Ok(raw as f64 * full_scale / 32768.0)
+    }
+
+    fn read_register(&mut self, reg: u8) -> Result<u16> {
+        let mut buf = [0; 2];
+        self.i2c
+            .write_read(self.address, &[reg], &mut buf)
+            .context("Cannot read ADS1115 register")?;
+        Ok(u16::from_be_bytes(buf))
+    }
+}
+
+impl Sensor for Ads1115 {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        let (input, gain) = self
+            .channels
+            .get(series)
+            .map(|c| (c.input, c.gain))
+            .ok_or_else(|| anyhow!("no series configured with name {series}"))?;
+        let voltage = self.read_voltage(input, gain)?;
+        Ok(convert(&self.channels[series].conversion, voltage))
+    }
+
+    fn series(&self) -> Vec<String> {
+        self.channels.keys().cloned().collect()
+    }
+}
+
+/// Config register word starting a single conversion of the input, along with the full scale
+/// voltage and the data rate in samples per second
+fn ads1115_config(
+    input: AdcInput,
+    gain: Ads1115Gain,
+    data_rate: Ads1115DataRate,
+) -> Result<(u16, f64, u32)> {
+    let (pga, full_scale) = match gain {
+        Ads1115Gain::Fsr6144 => (0b000, 6.144),
+        Ads1115Gain::Fsr4096 => (0b001, 4.096),
+        Ads1115Gain::Fsr2048 => (0b010, 2.048),
+        Ads1115Gain::Fsr1024 => (0b011, 1.024),
+        Ads1115Gain::Fsr512 => (0b100, 0.512),
+        Ads1115Gain::Fsr256 => (0b101, 0.256),
+    };
+    let (dr, sps) = match data_rate {
+        Ads1115DataRate::Sps8 => (0b000, 8),
+        Ads1115DataRate::Sps16 => (0b001, 16),
+        Ads1115DataRate::Sps32 => (0b010, 32),
+        Ads1115DataRate::Sps64 => (0b011, 64),
+        Ads1115DataRate::Sps128 => (0b100, 128),
+        Ads1115DataRate::Sps250 => (0b101, 250),
+        Ads1115DataRate::Sps475 => (0b110, 475),
+        Ads1115DataRate::Sps860 => (0b111, 860),
+    };
+    // Single-shot mode, comparator disabled
+    let config = ADS1115_OS | ads1115_mux(input)? << 12 | pga << 9 | 1 << 8 | dr << 5 | 0b11;
+    Ok((config, full_scale, sps))
+}
+
+/// Input multiplexer setting of the ADS1115
+fn ads1115_mux(input: AdcInput) -> Result<u16> {
+    match (input.input, input.negative_input) {
+        (0, Some(1)) => Ok(0b000),
+        (0, Some(3)) => Ok(0b001),
+        (1, Some(3)) => Ok(0b010),
+        (2, Some(3)) => Ok(0b011),
+        (i @ 0..=3, None) => Ok(0b100 | i as u16),
+        (i, None) => bail!(anyhow!("ADS1115 has no input {i}")),
+        (p, Some(n)) => bail!(anyhow!(
+            "ADS1115 cannot measure input {p} against input {n}, the differential pairs are \
+             0-1, 0-3, 1-3 and 2-3"
+        )),
+    }
+}
+
+/// Microchip MCP3008 analog to digital converter
+pub struct Mcp3008 {
+    spi: Spidev,
+    vref: f64,
+    channels: HashMap<String, Mcp3008Channel>,
+}
+
+impl Mcp3008 {
+    pub fn new(config: Mcp3008Config) -> Result<Mcp3008> {
+        if config.speed_hz > MCP3008_MAX_SPI_FREQ {
+            bail!(anyhow!(
+                "SPI clock frequency must be at most {MCP3008_MAX_SPI_FREQ} Hz, got {}",
+                config.speed_hz
+            ));
+        }
+        let mut channels = HashMap::new();
+        for channel in config.channels {
+            mcp3008_code(channel.input)?;
+            check_conversion(&channel.conversion, &channel.series)?;
+            let series = channel.series.clone();
+            if channels.insert(series.clone(), channel).is_some() {
+                bail!(anyhow!(
+                    "the \"{series}\" series is associated to several channels"
+                ));
+            }
+        }
+        let mut spi = Spidev::open(&config.path).context("Cannot open SPI bus")?;
+        spi.configure(
+            &SpidevOptions::new()
+                .mode(SpiModeFlags::SPI_MODE_0)
+                .bits_per_word(8)
+                .max_speed_hz(config.speed_hz)
+                .build(),
+        )
+        .context("Cannot configure SPI bus")?;
+        Ok(Mcp3008 {
+            spi,
+            vref: config.vref,
+            channels,
+        })
+    }
+
+    fn read_voltage(&mut self, input: AdcInput) -> Result<f64> {
+        // Start bit, then the single-ended/differential bit and the channel code
+        let tx = [0x01, mcp3008_code(input)? << 4, 0x00];
+        let mut rx = [0; 3];
+        self.spi
+            .transfer(&mut SpidevTransfer::read_write(&tx, &mut rx))
+            .context("SPI transfer failed")?;
+        let raw = ((rx[1] as u16 & 0x03) << 8) | rx[2] as u16;
+        Ok(raw as f64 * self.vref / 1024.0)
+    }
+}
+
+impl Sensor for Mcp3008 {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        let input = self
+            .channels
+            .get(series)
+            .map(|c| c.input)
+            .ok_or_else(|| anyhow!("no series configured with name {series}"))?;
+        let voltage = self.read_voltage(input)?;
+        Ok(convert(&self.channels[series].conversion, voltage))
+    }
+
+    fn series(&self) -> Vec<String> {
+        self.channels.keys().cloned().collect()
+    }
+}
+
+/// Single-ended/differential bit followed by the channel code of the MCP3008
+fn mcp3008_code(input: AdcInput) -> Result<u8> {
+    match (input.input, input.negative_input) {
+        (i @ 0..=7, None) => Ok(0b1000 | i),
+        // Differential pairs are made of adjacent inputs, the code being the positive input
+        (p @ 0..=7, Some(n)) if n == p ^ 1 => Ok(p),
+        (i, None) => bail!(anyhow!("MCP3008 has no input {i}")),
+        (p, Some(n)) => bail!(anyhow!(
+            "MCP3008 cannot measure input {p} against input {n}, the differential pairs are \
+             0-1, 2-3, 4-5 and 6-7"
+        )),
+    }
+}
+
+fn check_conversion(conversion: &Option<AdcConversion>, series: &str) -> Result<()> {
+    if let Some(AdcConversion::Table(points)) = conversion {
+        if points.is_empty() {
+            bail!(anyhow!(
+                "the conversion table of \"{series}\" series is empty"
+            ));
+        }
+        if points.windows(2).any(|w| w[0].0 >= w[1].0) {
+            bail!(anyhow!(
+                "the conversion table of \"{series}\" series must be sorted by increasing voltage"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Converts a voltage to the unit of the series.
+fn convert(conversion: &Option<AdcConversion>, volts: f64) -> f64 {
+    match conversion {
+        None => volts,
+        Some(AdcConversion::Linear { scale, offset }) => volts * scale + offset,
+        Some(AdcConversion::Table(points)) => {
+            let i = points.partition_point(|(v, _)| *v < volts);
+            if i == 0 {
+                points[0].1
+            } else if i == points.len() {
+                points[i - 1].1
+            } else {
+                let (v0, y0) = points[i - 1];
+                let (v1, y1) = points[i];
+                y0 + (volts - v0) * (y1 - y0) / (v1 - v0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(input: u8, negative_input: Option<u8>) -> AdcInput {
+        AdcInput {
+            input,
+            negative_input,
+        }
+    }
+
+    #[test]
+    fn ads1115_config_word() {
+        let (config, full_scale, sps) = ads1115_config(
+            input(0, None),
+            Ads1115Gain::Fsr4096,
+            Ads1115DataRate::Sps128,
+        )
+        .unwrap();
+        // OS, MUX=100 (AIN0/GND), PGA=001, MODE=1, DR=100, COMP_QUE=11
+        assert_eq!(config, 0xC383);
+        assert_eq!((full_scale, sps), (4.096, 128));
+
+        let (config, full_scale, sps) = ads1115_config(
+            input(0, Some(1)),
+            Ads1115Gain::Fsr256,
+            Ads1115DataRate::Sps860,
+        )
+        .unwrap();
+        assert_eq!(config, 0x8BE3);
+        assert_eq!((full_scale, sps), (0.256, 860));
+
+        let (config, _, _) =
+            ads1115_config(input(3, None), Ads1115Gain::Fsr6144, Ads1115DataRate::Sps8).unwrap();
+        assert_eq!(config, 0xF103);
+        assert!(
+            ads1115_config(input(4, None), Ads1115Gain::Fsr6144, Ads1115DataRate::Sps8).is_err()
+        );
+        assert!(ads1115_config(
+            input(1, Some(2)),
+            Ads1115Gain::Fsr6144,
+            Ads1115DataRate::Sps8
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn mcp3008_codes() {
+        assert_eq!(mcp3008_code(input(5, None)).unwrap(), 0b1101);
+        assert_eq!(mcp3008_code(input(2, Some(3))).unwrap(), 0b0010);
+        assert_eq!(mcp3008_code(input(3, Some(2))).unwrap(), 0b0011);
+        assert!(mcp3008_code(input(8, None)).is_err());
+        assert!(mcp3008_code(input(1, Some(2))).is_err());
+    }
+
+    #[test]
+    fn conversion() {
+        assert_eq!(convert(&None, 1.25), 1.25);
+        let linear = Some(AdcConversion::Linear {
+            scale: 100.0,
+            offset: -50.0,
+        });
+        assert_eq!(convert(&linear, 0.75), 25.0);
+
+        let table = Some(AdcConversion::Table(vec![
+            (0.5, -10.0),
+            (1.0, 0.0),
+            (2.0, 50.0),
+        ]));
+        assert_eq!(convert(&table, 0.75), -5.0);
+        assert_eq!(convert(&table, 1.0), 0.0);
+        assert_eq!(convert(&table, 1.5), 25.0);
+        // Clamped to the ends of the table
+        assert_eq!(convert(&table, 0.1), -10.0);
+        assert_eq!(convert(&table, 3.3), 50.0);
+        assert!(check_conversion(&table, "level").is_ok());
+        assert!(check_conversion(&linear, "level").is_ok());
+
+        let single = Some(AdcConversion::Table(vec![(1.0, 42.0)]));
+        assert_eq!(convert(&single, 0.0), 42.0);
+        assert_eq!(convert(&single, 2.0), 42.0);
+
+        for points in [
+            vec![],
+            vec![(1.0, 0.0), (0.5, 10.0)],
+            vec![(0.5, 0.0), (1.0, 5.0), (1.0, 10.0)],
+        ] {
+            assert!(check_conversion(&Some(AdcConversion::Table(points)), "level").is_err());
+        }
+    }
+}
@@ -0,0 +1,678 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use nix::errno::Errno;
+use nix::libc::{self, c_int, c_void, sockaddr, socklen_t};
+use nix::poll::{poll, PollFd, PollFlags};
+
+use crate::config::{BleConfig, BleMeasure};
+use crate::sensors::Sensor;
+
+const AF_BLUETOOTH: c_int = 31;
+const BTPROTO_HCI: c_int = 1;
+const SOL_HCI: c_int = 0;
+const HCI_FILTER: c_int = 2;
+const HCI_COMMAND_PKT: u8 = 0x01;
+const HCI_EVENT_PKT: u8 = 0x04;
+const EVT_LE_META: u8 = 0x3E;
+const LE_ADVERTISING_REPORT: u8 = 0x02;
+const LE_SET_SCAN_PARAMETERS: u16 = 0x200B;
+const LE_SET_SCAN_ENABLE: u16 = 0x200C;
+
+const AD_SERVICE_DATA_16: u8 = 0x16;
+const AD_MANUFACTURER_DATA: u8 = 0xFF;
+/// Environmental Sensing service, used by the ATC and pvvx firmwares
+const UUID_ENVIRONMENTAL_SENSING: u16 = 0x181A;
+const UUID_BTHOME: u16 = 0xFCD2;
+const COMPANY_RUUVI: u16 = 0x0499;
+
+/// Measures of a device, with the time they were received
+type Measures = HashMap<BleMeasure, (Instant, f64)>;
+
+/// Measures received from Bluetooth LE devices
+pub struct Ble {
+    scanner: Arc<Scanner>,
+    max_age: Duration,
+    series: HashMap<String, ([u8; 6], BleMeasure)>,
+}
+
+impl Ble {
+    pub fn new(config: BleConfig) -> Result<Ble> {
+        let mut series = HashMap::new();
+        for (name, s) in config.series {
+            let mac = parse_mac(&s.mac)
+                .ok_or_else(|| anyhow!("invalid MAC address {} for \"{name}\" series", s.mac))?;
+            series.insert(name, (mac, s.measure));
+        }
+        Ok(Ble {
+            scanner: Scanner::shared(config.adapter)?,
+            max_age: humantime::parse_duration(&config.max_age)?,
+            series,
+        })
+    }
+}
+
+impl Sensor for Ble {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        let (mac, measure) = self
+            .series
+            .get(series)
+            .ok_or_else(|| anyhow!("no series configured with name {series}"))?;
+        let state = self.scanner.state.lock().unwrap();
+        if let Some(e) = &state.error {
+            bail!(anyhow!("Bluetooth scanning stopped: {e}"));
+        }
+        match state.devices.get(mac).and_then(|m| m.get(measure)) {
+            Some((t, value)) if t.elapsed() <= self.max_age => Ok(*value),
+            _ => bail!(anyhow!(
+                "no {series} value advertised by {} in the last {}",
+                format_mac(mac),
+                humantime::format_duration(self.max_age)
+            )),
+        }
+    }
+
+    fn series(&self) -> Vec<String> {
+        self.series.keys().cloned().collect()
+    }
+}
+
+#[derive(Default)]
+struct State {
+    devices: HashMap<[u8; 6], Measures>,
+    /// Error that stopped the scanning
+    error: Option<String>,
+}
+
+/// Passive scanning of an adapter by a background thread, shared by the sensors using the adapter
+struct Scanner {
+    state: Arc<Mutex<State>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Scanner {
+    fn shared(adapter: u16) -> Result<Arc<Scanner>> {
+        static SCANNERS: OnceLock<Mutex<HashMap<u16, Weak<Scanner>>>> = OnceLock::new();
+        let mut scanners = SCANNERS.get_or_init(Default::default).lock().unwrap();
+        if let Some(scanner) = scanners.get(&adapter).and_then(Weak::upgrade) {
+            return Ok(scanner);
+        }
+        let scanner = Arc::new(Scanner::start(adapter)?);
+        scanners.insert(adapter, Arc::downgrade(&scanner));
+        Ok(scanner)
+    }
+
+    fn start(adapter: u16) -> Result<Scanner> {
+        let mut socket = open_hci(adapter)
+            .with_context(|| format!("Cannot open Bluetooth adapter hci{adapter}"))?;
+        // Passive scanning every 100ms, reporting duplicates as they carry new measures
+        let mut parameters = vec![0x00];
+        parameters.extend(0x00A0u16.to_le_bytes());
+        parameters.extend(0x00A0u16.to_le_bytes());
+        parameters.extend([0x00, 0x00]);
+        send_command(&mut socket, LE_SET_SCAN_PARAMETERS, &parameters)?;
+        send_command(&mut socket, LE_SET_SCAN_ENABLE, &[0x01, 0x00])?;
+
+        let state = Arc::new(Mutex::new(State::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let state = state.clone();
+            let stop = stop.clone();
+            spawn(move || {
+                let result = scan(&mut socket, &state, &stop);
+                let _ = send_command(&mut socket, LE_SET_SCAN_ENABLE, &[0x00, 0x00]);
+                if let Err(e) = result {
+                    println!("Warning: Bluetooth scanning stopped: {e:#}");
+                    state.lock().unwrap().error = Some(format!("{e:#}"));
+                }
+            })
+        };
+        Ok(Scanner {
+            state,
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Scanner {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[repr(C)]
+struct SockaddrHci {
+    family: u16,
+    dev: u16,
+    channel: u16,
+}
+
+#[repr(C)]
+struct HciFilter {
+    type_mask: u32,
+    event_mask: [u32; 2],
+    opcode: u16,
+}
+
+/// Opens a raw HCI socket on an adapter, receiving only the LE meta events.
+fn open_hci(adapter: u16) -> Result<File> {
+    // The raw socket API is not wrapped by nix, so it is called through libc
+    let fd = unsafe {
+        libc::socket(
+            AF_BLUETOOTH,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            BTPROTO_HCI,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let addr = SockaddrHci {
+        family: AF_BLUETOOTH as u16,
+        dev: adapter,
+        channel: 0,
+    };
+    let len = size_of::<SockaddrHci>() as socklen_t;
+    if unsafe { libc::bind(fd.as_raw_fd(), &addr as *const _ as *const sockaddr, len) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let filter = HciFilter {
+        type_mask: 1 << HCI_EVENT_PKT,
+        event_mask: [0, 1 << (EVT_LE_META - 32)],
+        opcode: 0,
+    };
+    let len = size_of::<HciFilter>() as socklen_t;
+    let filter = &filter as *const _ as *const c_void;
+    if unsafe { libc::setsockopt(fd.as_raw_fd(), SOL_HCI, HCI_FILTER, filter, len) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(File::from(fd))
+}
+
+fn send_command(socket: &mut File, opcode: u16, parameters: &[u8]) -> Result<()> {
+    let mut packet = vec![HCI_COMMAND_PKT];
+    packet.extend(opcode.to_le_bytes());
+    packet.push(parameters.len() as u8);
+    packet.extend(parameters);
+    socket
+        .write_all(&packet)
+        .context("Cannot send Bluetooth command")
+}
+
+/// Decodes the advertising reports until `stop` is set.
+fn scan(socket: &mut File, state: &Mutex<State>, stop: &AtomicBool) -> Result<()> {
+    let mut buf = [0; 260];
+    while !stop.load(Ordering::Relaxed) {
+        // Wake up regularly to check whether the scanner has been dropped
+        let mut fds = [PollFd::new(socket.as_raw_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, 500) {
+            Ok(0) | Err(Errno::EINTR) => continue,
+            Ok(_) => {}
+            Err(e) => return Err(e).context("Cannot wait for Bluetooth events"),
+        }
+        let len = socket
+            .read(&mut buf)
+            .context("Cannot read Bluetooth event")?;
+        let now = Instant::now();
+        for (mac, rssi, data) in advertising_reports(&buf[..len]) {
+            let mut state = state.lock().unwrap();
+            let measures = state.devices.entry(mac).or_default();
+            measures.insert(BleMeasure::Rssi, (now, rssi as f64));
+            for (measure, value) in decode(data) {
+                measures.insert(measure, (now, value));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Extracts the address, signal strength and data of the reports of an LE advertising report
+/// event.
+fn advertising_reports(event: &[u8]) -> Vec<([u8; 6], i8, &[u8])> {
+    let mut reports = vec![];
+    let [HCI_EVENT_PKT, EVT_LE_META, _, LE_ADVERTISING_REPORT, count, rest @ ..] = event else {
+        return reports;
+    };
+    let mut rest = rest;
+    for _ in 0..*count {
+        // Event type, address type, address, data length, data and RSSI
+        let Some(&len) = rest.get(8) else { break };
+        let len = len as usize;
+        if rest.len() < 10 + len {
+            break;
+        }
+        let mut mac: [u8; 6] = rest[2..8].try_into().unwrap();
+        mac.reverse();
+        reports.push((mac, rest[9 + len] as i8, &rest[9..9 + len]));
+        rest = &rest[10 + len..];
+    }
+    reports
+}
+
+/// Decodes the measures found in advertisement data.
+fn decode(data: &[u8]) -> Vec<(BleMeasure, f64)> {
+    let mut measures = vec![];
+    let mut rest = data;
+    while let [len, tail @ ..] = rest {
+        let len = *len as usize;
+        if len == 0 || tail.len() < len {
+            break;
+        }
+        let (structure, next) = tail.split_at(len);
+        rest = next;
+        match structure {
+            [AD_SERVICE_DATA_16, uuid_low, uuid_high, payload @ ..] => {
+                match u16::from_le_bytes([*uuid_low, *uuid_high]) {
+                    UUID_ENVIRONMENTAL_SENSING => measures.extend(decode_atc(payload)),
+                    UUID_BTHOME => measures.extend(decode_bthome(payload)),
+                    _ => {}
+                }
+            }
+            [AD_MANUFACTURER_DATA, company_low, company_high, payload @ ..]
+                if u16::from_le_bytes([*company_low, *company_high]) == COMPANY_RUUVI =>
+            {
+                measures.extend(decode_ruuvi(payload))
+            }
+            _ => {}
+        }
+    }
+    measures
+}
+
+/// Decodes the custom formats of the ATC1441 and pvvx firmwares for Xiaomi thermometers.
+fn decode_atc(payload: &[u8]) -> Vec<(BleMeasure, f64)> {
+    let be = |i: usize| u16::from_be_bytes([payload[i], payload[i + 1]]);
+    let le = |i: usize| u16::from_le_bytes([payload[i], payload[i + 1]]);
+    match payload.len() {
+        // MAC, temperature, humidity, battery level and voltage, frame counter
+        13 => vec![
+            (BleMeasure::Temperature, be(6) as i16 as f64 / 10.0),
+            (BleMeasure::Humidity, payload[8] as f64),
+            (BleMeasure::Battery, payload[9] as f64),
+            (BleMeasure::Voltage, be(10) as f64 / 1000.0),
+        ],
+        // Reversed MAC, temperature, humidity, battery voltage and level, counter, flags
+        15 => vec![
+            (BleMeasure::Temperature, le(6) as i16 as f64 / 100.0),
+            (BleMeasure::Humidity, le(8) as f64 / 100.0),
+            (BleMeasure::Voltage, le(10) as f64 / 1000.0),
+            (BleMeasure::Battery, payload[12] as f64),
+        ],
+        _ => vec![],
+    }
+}
+
+/// Decodes the objects of an unencrypted BTHome v2 payload.
+fn decode_bthome(payload: &[u8]) -> Vec<(BleMeasure, f64)> {
+    let mut measures = vec![];
+    let Some((&info, mut objects)) = payload.split_first() else {
+        return measures;
+    };
+    // Encrypted payloads are not supported
+    if info & 0x01 != 0 || info >> 5 != 2 {
+        return measures;
+    }
+    while let Some((&id, rest)) = objects.split_first() {
+        let (len, measure) = match id {
+            // Text and raw data, prefixed by their length
+            0x53 | 0x54 => match rest.first() {
+                Some(len) => (*len as usize + 1, None),
+                None => return vec![],
+            },
+            // The length of unknown objects is unknown, so the following ones cannot be decoded
+            _ => match bthome_object(id) {
+                Some(object) => object,
+                None => break,
+            },
+        };
+        // The payload is corrupted if an object is truncated
+        if rest.len() < len {
+            return vec![];
+        }
+        let (value, next) = rest.split_at(len);
+        if let Some((measure, divisor, signed)) = measure {
+            let mut bytes = [0; 8];
+            bytes[..len].copy_from_slice(value);
+            if signed && value[len - 1] & 0x80 != 0 {
+                bytes[len..].fill(0xFF);
+            }
+            measures.push((measure, i64::from_le_bytes(bytes) as f64 / divisor));
+        }
+        objects = next;
+    }
+    measures
+}
+
+/// Measure of a BTHome object, with the divisor of its raw value and its signedness
+type BthomeMeasure = (BleMeasure, f64, bool);
+
+/// Length of a BTHome object, with its measure if it is decoded
+fn bthome_object(id: u8) -> Option<(usize, Option<BthomeMeasure>)> {
+    let measure = |len, measure, divisor, signed| Some((len, Some((measure, divisor, signed))));
+    match id {
+        0x01 => measure(1, BleMeasure::Battery, 1.0, false),
+        0x02 => measure(2, BleMeasure::Temperature, 100.0, true),
+        0x03 => measure(2, BleMeasure::Humidity, 100.0, false),
+        0x04 => measure(3, BleMeasure::Pressure, 100.0, false),
+        0x05 => measure(3, BleMeasure::Illuminance, 100.0, false),
+        0x08 => measure(2, BleMeasure::DewPoint, 100.0, true),
+        0x0A => measure(3, BleMeasure::Energy, 1000.0, false),
+        0x0B => measure(3, BleMeasure::Power, 100.0, false),
+        0x0C => measure(2, BleMeasure::Voltage, 1000.0, false),
+        0x0D => measure(2, BleMeasure::Pm2_5, 1.0, false),
+        0x0E => measure(2, BleMeasure::Pm10, 1.0, false),
+        0x12 => measure(2, BleMeasure::Co2, 1.0, false),
+        0x13 => measure(2, BleMeasure::Tvoc, 1.0, false),
+        0x14 => measure(2, BleMeasure::Moisture, 100.0, false),
+        0x2E => measure(1, BleMeasure::Humidity, 1.0, false),
+        0x2F => measure(1, BleMeasure::Moisture, 1.0, false),
+        0x45 => measure(2, BleMeasure::Temperature, 10.0, true),
+        0x4A => measure(2, BleMeasure::Voltage, 10.0, false),
+        0x4D => measure(4, BleMeasure::Energy, 1000.0, false),
+        0x57 => measure(1, BleMeasure::Temperature, 1.0, true),
+        0x5C => measure(4, BleMeasure::Power, 100.0, true),
+        // Packet ID, binary sensors, events and other measures
+        0x00 | 0x09 | 0x0F..=0x11 | 0x15..=0x2D | 0x3A | 0x46 | 0x58 | 0x59 | 0x60 => {
+            Some((1, None))
+        }
+        0x06
+        | 0x07
+        | 0x3C
+        | 0x3D
+        | 0x3F
+        | 0x40
+        | 0x41
+        | 0x43
+        | 0x44
+        | 0x47..=0x49
+        | 0x51
+        | 0x52
+        | 0x56
+        | 0x5A
+        | 0x5D..=0x5F
+        | 0xF0 => Some((2, None)),
+        0x42 | 0x4B | 0xF2 => Some((3, None)),
+        0x3E | 0x4C | 0x4E | 0x4F | 0x50 | 0x55 | 0x5B | 0xF1 => Some((4, None)),
+        _ => None,
+    }
+}
+
+/// Decodes the RAWv2 (data format 5) payload of RuuviTags, skipping the fields marked invalid.
+fn decode_ruuvi(payload: &[u8]) -> Vec<(BleMeasure, f64)> {
+    let mut measures = vec![];
+    if payload.len() < 24 || payload[0] != 5 {
+        return measures;
+    }
+    let word = |i: usize| u16::from_be_bytes([payload[i], payload[i + 1]]);
+    if word(1) != 0x8000 {
+        measures.push((BleMeasure::Temperature, word(1) as i16 as f64 / 200.0));
+    }
+    if word(3) != 0xFFFF {
+        measures.push((BleMeasure::Humidity, word(3) as f64 / 400.0));
+    }
+    if word(5) != 0xFFFF {
+        measures.push((BleMeasure::Pressure, (word(5) as f64 + 50000.0) / 100.0));
+    }
+    for (i, measure) in [
+        (7, BleMeasure::AccelerationX),
+        (9, BleMeasure::AccelerationY),
+        (11, BleMeasure::AccelerationZ),
+    ] {
+        if word(i) != 0x8000 {
+            measures.push((measure, word(i) as i16 as f64 / 1000.0));
+        }
+    }
+    let power = word(13);
+    if power >> 5 != 0x7FF {
+        measures.push((BleMeasure::Voltage, ((power >> 5) as f64 + 1600.0) / 1000.0));
+    }
+    if power & 0x1F != 0x1F {
+        measures.push((BleMeasure::TxPower, (power & 0x1F) as f64 * 2.0 - 40.0));
+    }
+    if payload[15] != 0xFF {
+        measures.push((BleMeasure::MovementCount, payload[15] as f64));
+    }
+    measures
+}
+
+fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let bytes = mac
+        .split(':')
+        .map(|b| u8::from_str_radix(b, 16).ok().filter(|_| b.len() == 2))
+        .collect::<Option<Vec<_>>>()?;
+    bytes.try_into().ok()
+}
+
+fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// MAC, -5.3 °C, 55 %, battery at 90 % and 3 V, counter
+    const ATC1441: &str = concat!("a4c138123456", "ffcb", "37", "5a", "0bb8", "12");
+    /// Reversed MAC, 23.45 °C, 51.23 %, battery at 2.95 V and 75 %, counter, flags
+    const PVVX: &str = concat!("56341238c1a4", "2909", "0314", "860b", "4b", "07", "04");
+    /// Packet ID, battery, temperature, humidity, text, temperature, voltage, unknown object
+    /// and CO2, which cannot be decoded after the unknown object
+    const BTHOME: &str = concat!(
+        "40",
+        "0009",
+        "0161",
+        "02ca09",
+        "03bf13",
+        "5303414243",
+        "451aff",
+        "0c820c",
+        "ee0102",
+        "120004"
+    );
+    /// Valid, maximum, minimum and invalid values of the RAWv2 specification
+    const RUUVI_VALID: &str = "0512fc5394c37c0004fffc040cac364200cdcbb8334c884f";
+    const RUUVI_MAX: &str = "057ffffffefffe7fff7fff7fffffdefefffecbb8334c884f";
+    const RUUVI_MIN: &str = "058001000000008001800180010000000000cbb8334c884f";
+    const RUUVI_INVALID: &str = "058000ffffffff800080008000ffffffffffffffffffffff";
+
+    fn atc1441_measures() -> Vec<(BleMeasure, f64)> {
+        vec![
+            (BleMeasure::Temperature, -5.3),
+            (BleMeasure::Humidity, 55.0),
+            (BleMeasure::Battery, 90.0),
+            (BleMeasure::Voltage, 3.0),
+        ]
+    }
+
+    fn ruuvi_valid_measures() -> Vec<(BleMeasure, f64)> {
+        vec![
+            (BleMeasure::Temperature, 24.3),
+            (BleMeasure::Humidity, 53.49),
+            (BleMeasure::Pressure, 1000.44),
+            (BleMeasure::AccelerationX, 0.004),
+            (BleMeasure::AccelerationY, -0.004),
+            (BleMeasure::AccelerationZ, 1.036),
+            (BleMeasure::Voltage, 2.977),
+            (BleMeasure::TxPower, 4.0),
+            (BleMeasure::MovementCount, 66.0),
+        ]
+    }
+
+    #[test]
+    fn atc() {
+        let payload = hex(ATC1441);
+        assert_eq!(decode_atc(&payload), atc1441_measures());
+        for len in 0..payload.len() {
+            assert!(decode_atc(&payload[..len]).is_empty());
+        }
+
+        let payload = hex(PVVX);
+        assert_eq!(
+            decode_atc(&payload),
+            [
+                (BleMeasure::Temperature, 23.45),
+                (BleMeasure::Humidity, 51.23),
+                (BleMeasure::Voltage, 2.95),
+                (BleMeasure::Battery, 75.0),
+            ]
+        );
+        // Payloads of 13 bytes are in the ATC1441 format
+        for len in (0..payload.len()).filter(|len| *len != 13) {
+            assert!(decode_atc(&payload[..len]).is_empty());
+        }
+    }
+
+    #[test]
+    fn bthome() {
+        let payload = hex(BTHOME);
+        let measures = vec![
+            (BleMeasure::Battery, 97.0),
+            (BleMeasure::Temperature, 25.06),
+            (BleMeasure::Humidity, 50.55),
+            (BleMeasure::Temperature, -23.0),
+            (BleMeasure::Voltage, 3.202),
+        ];
+        assert_eq!(decode_bthome(&payload), measures);
+        // Truncated objects invalidate the payload, while complete ones form a valid payload
+        for len in 0..payload.len() {
+            let truncated = decode_bthome(&payload[..len]);
+            assert!(measures.starts_with(&truncated), "length {len}");
+        }
+        assert!(decode_bthome(&payload[..4]).is_empty());
+        assert!(decode_bthome(&payload[..7]).is_empty());
+        assert!(decode_bthome(&payload[..12]).is_empty());
+        assert!(decode_bthome(&payload[..14]).is_empty());
+        assert_eq!(decode_bthome(&payload[..11]), measures[..3]);
+
+        // Encrypted, and BTHome v1
+        let mut encrypted = payload.clone();
+        encrypted[0] |= 0x01;
+        assert!(decode_bthome(&encrypted).is_empty());
+        let mut v1 = payload;
+        v1[0] = 0x20;
+        assert!(decode_bthome(&v1).is_empty());
+    }
+
+    #[test]
+    fn ruuvi() {
+        let payload = hex(RUUVI_VALID);
+        assert_eq!(decode_ruuvi(&payload), ruuvi_valid_measures());
+        for len in 0..payload.len() {
+            assert!(decode_ruuvi(&payload[..len]).is_empty());
+        }
+        assert_eq!(
+            decode_ruuvi(&hex(RUUVI_MAX)),
+            [
+                (BleMeasure::Temperature, 163.835),
+                (BleMeasure::Humidity, 163.835),
+                (BleMeasure::Pressure, 1155.34),
+                (BleMeasure::AccelerationX, 32.767),
+                (BleMeasure::AccelerationY, 32.767),
+                (BleMeasure::AccelerationZ, 32.767),
+                (BleMeasure::Voltage, 3.646),
+                (BleMeasure::TxPower, 20.0),
+                (BleMeasure::MovementCount, 254.0),
+            ]
+        );
+        assert_eq!(
+            decode_ruuvi(&hex(RUUVI_MIN)),
+            [
+                (BleMeasure::Temperature, -163.835),
+                (BleMeasure::Humidity, 0.0),
+                (BleMeasure::Pressure, 500.0),
+                (BleMeasure::AccelerationX, -32.767),
+                (BleMeasure::AccelerationY, -32.767),
+                (BleMeasure::AccelerationZ, -32.767),
+                (BleMeasure::Voltage, 1.6),
+                (BleMeasure::TxPower, -40.0),
+                (BleMeasure::MovementCount, 0.0),
+            ]
+        );
+        // Every field is marked as not available
+        assert!(decode_ruuvi(&hex(RUUVI_INVALID)).is_empty());
+        let mut other_format = payload;
+        other_format[0] = 3;
+        assert!(decode_ruuvi(&other_format).is_empty());
+    }
+
+    /// LE advertising report event holding a report for each (address, data, RSSI)
+    fn event(reports: &[([u8; 6], Vec<u8>, i8)]) -> Vec<u8> {
+        let mut parameters = vec![LE_ADVERTISING_REPORT, reports.len() as u8];
+        for (mac, data, rssi) in reports {
+            parameters.extend([0x00, 0x00]);
+            parameters.extend(mac.iter().rev());
+            parameters.push(data.len() as u8);
+            parameters.extend(data);
+            parameters.push(*rssi as u8);
+        }
+        [
+            vec![HCI_EVENT_PKT, EVT_LE_META, parameters.len() as u8],
+            parameters,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn advertising_report_event() {
+        let atc_mac = parse_mac("A4:C1:38:12:34:56").unwrap();
+        let ruuvi_mac = parse_mac("CB:B8:33:4C:88:4F").unwrap();
+        assert_eq!(format_mac(&ruuvi_mac), "CB:B8:33:4C:88:4F");
+        // Flags, then the service data or manufacturer data
+        let atc_data = hex(&format!("02010610161a18{ATC1441}"));
+        let ruuvi_data = hex(&format!("1bff9904{RUUVI_VALID}"));
+        let event = event(&[
+            (atc_mac, atc_data.clone(), -60),
+            (ruuvi_mac, ruuvi_data.clone(), -75),
+        ]);
+
+        let reports = advertising_reports(&event);
+        assert_eq!(
+            reports,
+            [
+                (atc_mac, -60, atc_data.as_slice()),
+                (ruuvi_mac, -75, ruuvi_data.as_slice())
+            ]
+        );
+        assert_eq!(decode(reports[0].2), atc1441_measures());
+        assert_eq!(decode(reports[1].2), ruuvi_valid_measures());
+
+        // The reports of a truncated event are dropped from the first incomplete one
+        let first_end = 5 + 10 + atc_data.len();
+        for len in 0..event.len() {
+            let reports = advertising_reports(&event[..len]);
+            assert_eq!(reports.len(), (len >= first_end) as usize, "length {len}");
+        }
+        for data in [&atc_data, &ruuvi_data] {
+            for len in 0..data.len() {
+                assert!(decode(&data[..len]).is_empty());
+            }
+        }
+        let mut other_event = event;
+        other_event[3] = 0x0D;
+        assert!(advertising_reports(&other_event).is_empty());
+    }
+}
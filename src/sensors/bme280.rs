@@ -1,54 +1,103 @@
-use crate::config::{Bme280Config, Bme280Address::{SdoGnd, SdoVddio}};
+use std::collections::HashMap;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::config::{Bme280Config, Bme280Standby, BoschFilter, BoschMode, BoschOversampling};
+use crate::sensors::bosch::{self, Bus, Chip};
+use crate::sensors::cache::BatchCache;
 use crate::sensors::Sensor;
-use anyhow::{anyhow, Context, Result};
-use bme280_multibus::{i2c::{Address, Bme280Bus}, Sample};
-use linux_embedded_hal::I2cdev;
+use anyhow::{anyhow, bail, Context, Result};
+use bme280_multibus::Sample;
 
-const SETTINGS: bme280_multibus::Settings = bme280_multibus::Settings {
-    config: bme280_multibus::Config::reset()
-        .set_standby_time(bme280_multibus::Standby::Millis1000)
-        .set_filter(bme280_multibus::Filter::X16),
-    ctrl_meas: bme280_multibus::CtrlMeas::reset()
-        .set_osrs_t(bme280_multibus::Oversampling::X8)
-        .set_osrs_p(bme280_multibus::Oversampling::X8)
-        .set_mode(bme280_multibus::Mode::Normal),
-    ctrl_hum: bme280_multibus::Oversampling::X8,
-};
+/// Maximum time to wait for a forced measurement to complete
+const MEASURE_TIMEOUT: Duration = Duration::from_secs(1);
 
 pub struct Bme280 {
     config: Bme280Config,
-    bme280: bme280_multibus::Bme280<Bme280Bus<I2cdev>>,
+    settings: bme280_multibus::Settings,
+    bme280: bme280_multibus::Bme280<Bus>,
+    /// Values of the last sample, shared by the series sampled in the same cycle
+    cache: BatchCache,
 }
 
 impl Bme280 {
     pub fn new(config: Bme280Config) -> Result<Bme280> {
-        let addr = match config.address {
-            SdoGnd => Address::SdoGnd,
-            SdoVddio => Address::SdoVddio,
-        };
-        let i2c = I2cdev::new(&config.path).context("Cannot open I2C bus")?;
-        let mut bme280 = bme280_multibus::Bme280::from_i2c(i2c, addr)
-            .context("Cannot create BME280 driver")?;
-        bme280.settings(&SETTINGS).context("Cannot configure BME280")?;
+        validate(&config)?;
+        let settings = settings(&config);
+        let mut bus = Bus::new(&config.bus()?, Chip::Bme280)?;
+        bus.check_chip_id(Chip::Bme280)?;
+        let mut bme280 =
+            bme280_multibus::Bme280::new(bus).context("Cannot create BME280 driver")?;
+        bme280.settings(&settings).context("Cannot configure BME280")?;
 
-        Ok(Bme280 { config, bme280 })
+        Ok(Bme280 {
+            config,
+            settings,
+            bme280,
+            cache: BatchCache::new(Duration::from_secs(5)),
+        })
     }
-}
 
-impl Sensor for Bme280 {
-    fn sample(&mut self, series: &str) -> Result<f64> {
+    /// Triggers a single measurement and waits for its completion.
+    fn force_measurement(&mut self) -> Result<()> {
+        self.bme280
+            .settings(&self.settings)
+            .context("Cannot trigger BME280 measurement")?;
+        sleep(bosch::measurement_time(
+            self.config.temperature_oversampling,
+            self.config.pressure_oversampling,
+            self.config.humidity_oversampling,
+        ));
+        let start = Instant::now();
+        while self
+            .bme280
+            .status()
+            .context("Cannot read BME280 status")?
+            .measuring()
+        {
+            if start.elapsed() > MEASURE_TIMEOUT {
+                bail!(anyhow!("BME280 measurement did not complete"));
+            }
+            sleep(Duration::from_millis(1));
+        }
+        Ok(())
+    }
+
+    /// Values of the configured series, from a single sample.
+    fn measure(&mut self) -> Result<HashMap<String, f64>> {
+        if self.config.mode == BoschMode::Forced {
+            self.force_measurement()?;
+        }
         let sample: Sample = self.bme280
             .sample()
             .map_err(|e| anyhow!("Cannot read sample from BME280: {:?}", e))?;
-        if self.config.humidity_series.as_ref().is_some_and(|s| s == series) {
-            return Ok(sample.humidity as f64)
-        } else if self.config.pressure_series.as_ref().is_some_and(|s| s == series) {
-            return Ok(sample.pressure as f64 / 100.0)
-        } else if self.config.temperature_series.as_ref().is_some_and(|s| s == series) {
-            return Ok(sample.temperature as f64)
-        } else {
-            Err(anyhow!("no series configured with name {series}"))
+        let mut values = HashMap::new();
+        if let Some(s) = &self.config.temperature_series {
+            values.insert(s.clone(), sample.temperature as f64);
+        }
+        if let Some(s) = &self.config.humidity_series {
+            values.insert(s.clone(), sample.humidity as f64);
         }
+        if let Some(s) = &self.config.pressure_series {
+            values.insert(s.clone(), sample.pressure as f64 / 100.0);
+        }
+        Ok(values)
+    }
+}
+
+impl Sensor for Bme280 {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        if !self.series().iter().any(|s| s == series) {
+            return Err(anyhow!("no series configured with name {series}"));
+        }
+        if let Some(value) = self.cache.take(series) {
+            return Ok(value);
+        }
+        let values = self.measure()?;
+        self.cache.store(values);
+        self.cache
+            .take(series)
+            .ok_or_else(|| anyhow!("no series configured with name {series}"))
     }
 
     fn series(&self) -> Vec<String> {
@@ -65,3 +114,67 @@ impl Sensor for Bme280 {
         ret
     }
 }
+
+fn validate(config: &Bme280Config) -> Result<()> {
+    // The temperature is needed to compensate pressure and humidity readings
+    if config.temperature_oversampling == BoschOversampling::Skip {
+        bail!(anyhow!("BME280 temperature oversampling cannot be \"skip\""));
+    }
+    bosch::check_oversampling(
+        Chip::Bme280,
+        "pressure",
+        config.pressure_oversampling,
+        &config.pressure_series,
+    )?;
+    bosch::check_oversampling(
+        Chip::Bme280,
+        "humidity",
+        config.humidity_oversampling,
+        &config.humidity_series,
+    )
+}
+
+fn settings(config: &Bme280Config) -> bme280_multibus::Settings {
+    let mode = match config.mode {
+        BoschMode::Normal => bme280_multibus::Mode::Normal,
+        BoschMode::Forced => bme280_multibus::Mode::Forced,
+    };
+    let filter = match config.filter {
+        BoschFilter::Off => bme280_multibus::Filter::Off,
+        BoschFilter::X2 => bme280_multibus::Filter::X2,
+        BoschFilter::X4 => bme280_multibus::Filter::X4,
+        BoschFilter::X8 => bme280_multibus::Filter::X8,
+        BoschFilter::X16 => bme280_multibus::Filter::X16,
+    };
+    let standby = match config.standby_time {
+        Bme280Standby::Micros500 => bme280_multibus::Standby::Micros500,
+        Bme280Standby::Millis10 => bme280_multibus::Standby::Millis10,
+        Bme280Standby::Millis20 => bme280_multibus::Standby::Millis20,
+        Bme280Standby::Micros62500 => bme280_multibus::Standby::Micros62500,
+        Bme280Standby::Millis125 => bme280_multibus::Standby::Millis125,
+        Bme280Standby::Millis250 => bme280_multibus::Standby::Millis250,
+        Bme280Standby::Millis500 => bme280_multibus::Standby::Millis500,
+        Bme280Standby::Millis1000 => bme280_multibus::Standby::Millis1000,
+    };
+    bme280_multibus::Settings {
+        config: bme280_multibus::Config::reset()
+            .set_standby_time(standby)
+            .set_filter(filter),
+        ctrl_meas: bme280_multibus::CtrlMeas::reset()
+            .set_osrs_t(oversampling(config.temperature_oversampling))
+            .set_osrs_p(oversampling(config.pressure_oversampling))
+            .set_mode(mode),
+        ctrl_hum: oversampling(config.humidity_oversampling),
+    }
+}
+
+fn oversampling(os: BoschOversampling) -> bme280_multibus::Oversampling {
+    match os {
+        BoschOversampling::Skip => bme280_multibus::Oversampling::Skip,
+        BoschOversampling::X1 => bme280_multibus::Oversampling::X1,
+        BoschOversampling::X2 => bme280_multibus::Oversampling::X2,
+        BoschOversampling::X4 => bme280_multibus::Oversampling::X4,
+        BoschOversampling::X8 => bme280_multibus::Oversampling::X8,
+        BoschOversampling::X16 => bme280_multibus::Oversampling::X16,
+    }
+}
@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::config::{Bme680Config, Bme680Filter, BoschOversampling};
+use crate::sensors::bosch::{self, Bus, Chip};
+use crate::sensors::cache::BatchCache;
+use crate::sensors::Sensor;
+use anyhow::{anyhow, bail, Context, Result};
+use bme280_multibus::Bme280Bus;
+
+const REG_RES_HEAT_VAL: u8 = 0x00;
+const REG_RES_HEAT_RANGE: u8 = 0x02;
+const REG_RANGE_SW_ERR: u8 = 0x04;
+const REG_MEAS_STATUS: u8 = 0x1D;
+const REG_RES_HEAT_0: u8 = 0x5A;
+const REG_GAS_WAIT_0: u8 = 0x64;
+const REG_CTRL_GAS_1: u8 = 0x71;
+const REG_CTRL_HUM: u8 = 0x72;
+const REG_CTRL_MEAS: u8 = 0x74;
+const REG_CONFIG: u8 = 0x75;
+const REG_COEFF_1: u8 = 0x89;
+const REG_COEFF_2: u8 = 0xE1;
+
+const MAX_HEATER_TEMPERATURE: u16 = 400;
+const MAX_HEATER_DURATION: u16 = 4032;
+
+/// Maximum time to wait for a measurement to complete, on top of its expected duration
+const MEASURE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct Bme680 {
+    config: Bme680Config,
+    bus: Bus,
+    calibration: Calibration,
+    /// Last measured temperature, used to compute the heater resistance
+    ambient_temperature: f64,
+    /// Values of the last conversion, shared by the series sampled in the same cycle, as each
+    /// conversion fires the gas heater
+    cache: BatchCache,
+}
+
+impl Bme680 {
+    pub fn new(config: Bme680Config) -> Result<Bme680> {
+        validate(&config)?;
+        let mut bus = Bus::new(&config.bus, Chip::Bme680)?;
+        bus.check_chip_id(Chip::Bme680)?;
+        let calibration = Calibration::read(&mut bus).context("Cannot read BME680 calibration")?;
+
+        let mut bme680 = Bme680 {
+            config,
+            bus,
+            calibration,
+            ambient_temperature: 25.0,
+            cache: BatchCache::new(Duration::from_secs(5)),
+        };
+        bme680
+            .bus
+            .write_reg(REG_CONFIG, filter_bits(bme680.config.filter) << 2)
+            .context("Cannot configure BME680")?;
+        Ok(bme680)
+    }
+
+    fn gas_enabled(&self) -> bool {
+        self.config.gas_resistance_series.is_some()
+    }
+
+    /// Triggers a single measurement, the BME680 only supporting forced mode, and waits for its
+    /// completion.
+    fn force_measurement(&mut self) -> Result<()> {
+        let ctrl_gas_1 = if self.gas_enabled() {
+            let res_heat = self
+                .calibration
+                .heater_resistance(self.config.heater_temperature, self.ambient_temperature);
+            self.bus.write_reg(REG_RES_HEAT_0, res_heat)?;
+            self.bus
+                .write_reg(REG_GAS_WAIT_0, gas_wait_bits(self.config.heater_duration))?;
+            // run_gas, heater set-point 0
+            0b0001_0000
+        } else {
+            0
+        };
+        self.bus.write_reg(REG_CTRL_GAS_1, ctrl_gas_1)?;
+        self.bus.write_reg(
+            REG_CTRL_HUM,
+            bosch::oversampling_bits(self.config.humidity_oversampling),
+        )?;
+        let ctrl_meas = (bosch::oversampling_bits(self.config.temperature_oversampling) << 5)
+            | (bosch::oversampling_bits(self.config.pressure_oversampling) << 2)
+            | 0b01;
+        self.bus.write_reg(REG_CTRL_MEAS, ctrl_meas)?;
+
+        sleep(self.measurement_time());
+        let start = Instant::now();
+        loop {
+            let mut status = [0];
+            self.bus
+                .read_regs(REG_MEAS_STATUS, &mut status)
+                .context("Cannot read BME680 status")?;
+            // new_data bit
+            if status[0] & 0b1000_0000 != 0 {
+                return Ok(());
+            }
+            if start.elapsed() > MEASURE_TIMEOUT {
+                bail!(anyhow!("BME680 measurement did not complete"));
+            }
+            sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Expected measurement duration, as computed by the Bosch BME68x API
+    fn measurement_time(&self) -> Duration {
+        let cycles: u64 = [
+            self.config.temperature_oversampling,
+            self.config.pressure_oversampling,
+            self.config.humidity_oversampling,
+        ]
+        .into_iter()
+        .map(bosch::oversampling_factor)
+        .sum();
+        // TPH switching and gas measurement durations, plus wake up time
+        let mut micros = cycles * 1963 + 477 * 4 + 477 * 5 + 1000;
+        if self.gas_enabled() {
+            micros += self.config.heater_duration as u64 * 1000;
+        }
+        Duration::from_micros(micros)
+    }
+
+    fn read_sample(&mut self) -> Result<Sample> {
+        self.force_measurement()
+            .context("Cannot trigger BME680 measurement")?;
+        let mut buf = [0; 15];
+        self.bus
+            .read_regs(REG_MEAS_STATUS, &mut buf)
+            .context("Cannot read sample from BME680")?;
+        let p = ((buf[2] as u32) << 12) | ((buf[3] as u32) << 4) | ((buf[4] as u32) >> 4);
+        let t = ((buf[5] as u32) << 12) | ((buf[6] as u32) << 4) | ((buf[7] as u32) >> 4);
+        let h = ((buf[8] as u32) << 8) | (buf[9] as u32);
+        let gas_adc = ((buf[13] as u32) << 2) | ((buf[14] as u32) >> 6);
+        let gas_range = buf[14] & 0x0F;
+        let gas_valid = buf[14] & 0b0010_0000 != 0;
+        let heat_stable = buf[14] & 0b0001_0000 != 0;
+
+        let mut sample = self.calibration.compensate(t, p, h);
+        self.ambient_temperature = sample.temperature;
+        if self.gas_enabled() {
+            if !gas_valid || !heat_stable {
+                bail!(anyhow!(
+                    "BME680 gas measurement is not valid, the heater duration may be too short"
+                ));
+            }
+            sample.gas_resistance = Some(self.calibration.gas_resistance(gas_adc, gas_range));
+        }
+        Ok(sample)
+    }
+
+    /// Values of the configured series, from a single conversion.
+    fn measure(&mut self) -> Result<HashMap<String, f64>> {
+        let sample = self.read_sample()?;
+        let mut values = HashMap::new();
+        if let Some(s) = &self.config.temperature_series {
+            values.insert(s.clone(), sample.temperature);
+        }
+        if let Some(s) = &self.config.humidity_series {
+            values.insert(s.clone(), sample.humidity);
+        }
+        if let Some(s) = &self.config.pressure_series {
+            values.insert(s.clone(), sample.pressure / 100.0);
+        }
+        if let (Some(s), Some(gas_resistance)) =
+            (&self.config.gas_resistance_series, sample.gas_resistance)
+        {
+            values.insert(s.clone(), gas_resistance);
+        }
+        Ok(values)
+    }
+}
+
+impl Sensor for Bme680 {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        if !self.series().iter().any(|s| s == series) {
+            return Err(anyhow!("no series configured with name {series}"));
+        }
+        if let Some(value) = self.cache.take(series) {
+            return Ok(value);
+        }
+        let values = self.measure()?;
+        self.cache.store(values);
+        self.cache
+            .take(series)
+            .ok_or_else(|| anyhow!("no series configured with name {series}"))
+    }
+
+    fn series(&self) -> Vec<String> {
+        let mut ret = vec![];
+        if let Some(s) = &self.config.temperature_series {
+            ret.push(s.clone());
+        }
+        if let Some(s) = &self.config.humidity_series {
+            ret.push(s.clone());
+        }
+        if let Some(s) = &self.config.pressure_series {
+            ret.push(s.clone());
+        }
+        if let Some(s) = &self.config.gas_resistance_series {
+            ret.push(s.clone());
+        }
+        ret
+    }
+}
+
+struct Sample {
+    /// Temperature in °C
+    temperature: f64,
+    /// Pressure in Pa
+    pressure: f64,
+    /// Relative humidity in %
+    humidity: f64,
+    /// Gas sensor resistance in ohms
+    gas_resistance: Option<f64>,
+}
+
+/// Trimming parameters stored in the sensor non-volatile memory
+struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i8,
+    p1: u16,
+    p2: i16,
+    p3: i8,
+    p4: i16,
+    p5: i16,
+    p6: i8,
+    p7: i8,
+    p8: i16,
+    p9: i16,
+    p10: u8,
+    h1: u16,
+    h2: u16,
+    h3: i8,
+    h4: i8,
+    h5: i8,
+    h6: u8,
+    h7: i8,
+    gh1: i8,
+    gh2: i16,
+    gh3: i8,
+    res_heat_range: u8,
+    res_heat_val: i8,
+    range_sw_err: i8,
+}
+
+impl Calibration {
+    fn read(bus: &mut Bus) -> Result<Calibration> {
+        let mut c = [0; 41];
+        bus.read_regs(REG_COEFF_1, &mut c[..25])?;
+        bus.read_regs(REG_COEFF_2, &mut c[25..])?;
+        let mut res_heat_val = [0];
+        bus.read_regs(REG_RES_HEAT_VAL, &mut res_heat_val)?;
+        let mut res_heat_range = [0];
+        bus.read_regs(REG_RES_HEAT_RANGE, &mut res_heat_range)?;
+        let mut range_sw_err = [0];
+        bus.read_regs(REG_RANGE_SW_ERR, &mut range_sw_err)?;
+
+        let u = |i: usize| u16::from_le_bytes([c[i], c[i + 1]]);
+        let s = |i: usize| i16::from_le_bytes([c[i], c[i + 1]]);
+        Ok(Calibration {
+            t1: u(33),
+            t2: s(1),
+            t3: c[3] as i8,
+            p1: u(5),
+            p2: s(7),
+            p3: c[9] as i8,
+            p4: s(11),
+            p5: s(13),
+            p6: c[16] as i8,
+            p7: c[15] as i8,
+            p8: s(19),
+            p9: s(21),
+            p10: c[23],
+            h1: ((c[27] as u16) << 4) | (c[26] as u16 & 0x0F),
+            h2: ((c[25] as u16) << 4) | (c[26] as u16 >> 4),
+            h3: c[28] as i8,
+            h4: c[29] as i8,
+            h5: c[30] as i8,
+            h6: c[31],
+            h7: c[32] as i8,
+            gh1: c[37] as i8,
+            gh2: s(35),
+            gh3: c[38] as i8,
+            res_heat_range: (res_heat_range[0] >> 4) & 0b11,
+            res_heat_val: res_heat_val[0] as i8,
+            // Signed 4 bits value in the upper nibble
+            range_sw_err: (range_sw_err[0] as i8) >> 4,
+        })
+    }
+
+    /// Floating point compensation formulas from the Bosch BME68x API
+    fn compensate(&self, t: u32, p: u32, h: u32) -> Sample {
+        let (t, p, h) = (t as f64, p as f64, h as f64);
+
+        let var1 = ((t / 16384.0) - (self.t1 as f64 / 1024.0)) * self.t2 as f64;
+        let var2 = ((t / 131072.0) - (self.t1 as f64 / 8192.0))
+            * ((t / 131072.0) - (self.t1 as f64 / 8192.0))
+            * (self.t3 as f64 * 16.0);
+        let t_fine = var1 + var2;
+        let temperature = t_fine / 5120.0;
+
+        let var1 = (t_fine / 2.0) - 64000.0;
+        let var2 = var1 * var1 * (self.p6 as f64 / 131072.0);
+        let var2 = var2 + (var1 * self.p5 as f64 * 2.0);
+        let var2 = (var2 / 4.0) + (self.p4 as f64 * 65536.0);
+        let var1 = (((self.p3 as f64 * var1 * var1) / 16384.0) + (self.p2 as f64 * var1)) / 524288.0;
+        let var1 = (1.0 + (var1 / 32768.0)) * self.p1 as f64;
+        let pressure = if var1 == 0.0 {
+            0.0
+        } else {
+            let pressure = 1048576.0 - p;
+            let pressure = ((pressure - (var2 / 4096.0)) * 6250.0) / var1;
+            let var1 = (self.p9 as f64 * pressure * pressure) / 2147483648.0;
+            let var2 = pressure * (self.p8 as f64 / 32768.0);
+            let var3 = (pressure / 256.0).powi(3) * (self.p10 as f64 / 131072.0);
+            pressure + (var1 + var2 + var3 + (self.p7 as f64 * 128.0)) / 16.0
+        };
+
+        let var1 = h - ((self.h1 as f64 * 16.0) + ((self.h3 as f64 / 2.0) * temperature));
+        let var2 = var1
+            * ((self.h2 as f64 / 262144.0)
+                * (1.0
+                    + ((self.h4 as f64 / 16384.0) * temperature)
+                    + ((self.h5 as f64 / 1048576.0) * temperature * temperature)));
+        let var3 = self.h6 as f64 / 16384.0;
+        let var4 = self.h7 as f64 / 2097152.0;
+        let humidity = (var2 + ((var3 + (var4 * temperature)) * var2 * var2)).clamp(0.0, 100.0);
+
+        Sample {
+            temperature,
+            pressure,
+            humidity,
+            gas_resistance: None,
+        }
+    }
+
+    fn gas_resistance(&self, gas_adc: u32, gas_range: u8) -> f64 {
+        const K1_RANGE: [f64; 16] = [
+            0.0, 0.0, 0.0, 0.0, 0.0, -1.0, 0.0, -0.8, 0.0, 0.0, -0.2, -0.5, 0.0, -1.0, 0.0, 0.0,
+        ];
+        const K2_RANGE: [f64; 16] = [
+            0.0, 0.0, 0.0, 0.0, 0.1, 0.7, 0.0, -0.8, -0.1, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+        ];
+        let range = gas_range as usize;
+        let var1 = 1340.0 + (5.0 * self.range_sw_err as f64);
+        let var2 = var1 * (1.0 + K1_RANGE[range] / 100.0);
+        let var3 = 1.0 + (K2_RANGE[range] / 100.0);
+        1.0 / (var3
+            * 0.000000125
+            * (1u32 << gas_range) as f64
+            * (((gas_adc as f64 - 512.0) / var2) + 1.0))
+    }
+
+    /// Register value of the heater resistance needed to reach `target` °C
+    fn heater_resistance(&self, target: u16, ambient: f64) -> u8 {
+        let target = target.min(MAX_HEATER_TEMPERATURE) as f64;
+        let var1 = (self.gh1 as f64 / 16.0) + 49.0;
+        let var2 = ((self.gh2 as f64 / 32768.0) * 0.0005) + 0.00235;
+        let var3 = self.gh3 as f64 / 1024.0;
+        let var4 = var1 * (1.0 + (var2 * target));
+        let var5 = var4 + (var3 * ambient);
+        let res_heat = 3.4
+            * ((var5
+                * (4.0 / (4.0 + self.res_heat_range as f64))
+                * (1.0 / (1.0 + (self.res_heat_val as f64 * 0.002))))
+                - 25.0);
+        res_heat.clamp(0.0, 255.0) as u8
+    }
+}
+
+fn validate(config: &Bme680Config) -> Result<()> {
+    // The temperature is needed to compensate the other readings
+    if config.temperature_oversampling == BoschOversampling::Skip {
+        bail!(anyhow!("BME680 temperature oversampling cannot be \"skip\""));
+    }
+    bosch::check_oversampling(
+        Chip::Bme680,
+        "pressure",
+        config.pressure_oversampling,
+        &config.pressure_series,
+    )?;
+    bosch::check_oversampling(
+        Chip::Bme680,
+        "humidity",
+        config.humidity_oversampling,
+        &config.humidity_series,
+    )?;
+    if config.heater_temperature > MAX_HEATER_TEMPERATURE {
+        bail!(anyhow!(
+            "BME680 heater temperature cannot exceed {MAX_HEATER_TEMPERATURE} °C"
+        ));
+    }
+    if config.heater_duration > MAX_HEATER_DURATION {
+        bail!(anyhow!(
+            "BME680 heater duration cannot exceed {MAX_HEATER_DURATION} ms"
+        ));
+    }
+    Ok(())
+}
+
+fn filter_bits(filter: Bme680Filter) -> u8 {
+    match filter {
+        Bme680Filter::Off => 0,
+        Bme680Filter::C1 => 1,
+        Bme680Filter::C3 => 2,
+        Bme680Filter::C7 => 3,
+        Bme680Filter::C15 => 4,
+        Bme680Filter::C31 => 5,
+        Bme680Filter::C63 => 6,
+        Bme680Filter::C127 => 7,
+    }
+}
+
+/// Encodes a heating duration: 6 bits of value and a 2 bits multiplication factor (1, 4, 16
+/// or 64).
+fn gas_wait_bits(duration_ms: u16) -> u8 {
+    let mut duration = duration_ms.min(MAX_HEATER_DURATION);
+    let mut factor = 0;
+    while duration > 0x3F {
+        duration /= 4;
+        factor += 1;
+    }
+    (duration as u8) | (factor << 6)
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::config::{Bmp280Config, Bmp280Standby, BoschFilter, BoschMode, BoschOversampling};
+use crate::sensors::bosch::{self, Bus, Chip};
+use crate::sensors::cache::BatchCache;
+use crate::sensors::Sensor;
+use anyhow::{anyhow, bail, Context, Result};
+use bme280_multibus::Bme280Bus;
+
+const REG_CALIB: u8 = 0x88;
+const REG_STATUS: u8 = 0xF3;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_CONFIG: u8 = 0xF5;
+const REG_PRESS_MSB: u8 = 0xF7;
+
+/// Maximum time to wait for a forced measurement to complete
+const MEASURE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct Bmp280 {
+    config: Bmp280Config,
+    bus: Bus,
+    calibration: Calibration,
+    /// Values of the last sample, shared by the series sampled in the same cycle
+    cache: BatchCache,
+}
+
+impl Bmp280 {
+    pub fn new(config: Bmp280Config) -> Result<Bmp280> {
+        validate(&config)?;
+        let mut bus = Bus::new(&config.bus, Chip::Bmp280)?;
+        bus.check_chip_id(Chip::Bmp280)?;
+
+        let mut calib = [0; 24];
+        bus.read_regs(REG_CALIB, &mut calib)
+            .context("Cannot read BMP280 calibration")?;
+        let calibration = Calibration::from(calib);
+
+        let mut bmp280 = Bmp280 {
+            config,
+            bus,
+            calibration,
+            cache: BatchCache::new(Duration::from_secs(5)),
+        };
+        bmp280.configure().context("Cannot configure BMP280")?;
+        Ok(bmp280)
+    }
+
+    fn configure(&mut self) -> Result<()> {
+        let config = (standby_bits(self.config.standby_time) << 5)
+            | (filter_bits(self.config.filter) << 2);
+        self.bus.write_reg(REG_CONFIG, config)?;
+        self.bus.write_reg(REG_CTRL_MEAS, self.ctrl_meas())
+    }
+
+    fn ctrl_meas(&self) -> u8 {
+        let mode = match self.config.mode {
+            BoschMode::Normal => 0b11,
+            BoschMode::Forced => 0b01,
+        };
+        (bosch::oversampling_bits(self.config.temperature_oversampling) << 5)
+            | (bosch::oversampling_bits(self.config.pressure_oversampling) << 2)
+            | mode
+    }
+
+    /// Triggers a single measurement and waits for its completion.
+    fn force_measurement(&mut self) -> Result<()> {
+        self.bus
+            .write_reg(REG_CTRL_MEAS, self.ctrl_meas())
+            .context("Cannot trigger BMP280 measurement")?;
+        sleep(bosch::measurement_time(
+            self.config.temperature_oversampling,
+            self.config.pressure_oversampling,
+            BoschOversampling::Skip,
+        ));
+        let start = Instant::now();
+        loop {
+            let mut status = [0];
+            self.bus
+                .read_regs(REG_STATUS, &mut status)
+                .context("Cannot read BMP280 status")?;
+            if status[0] & 0b1000 == 0 {
+                return Ok(());
+            }
+            if start.elapsed() > MEASURE_TIMEOUT {
+                bail!(anyhow!("BMP280 measurement did not complete"));
+            }
+            sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn read_sample(&mut self) -> Result<Sample> {
+        if self.config.mode == BoschMode::Forced {
+            self.force_measurement()?;
+        }
+        let mut buf = [0; 6];
+        self.bus
+            .read_regs(REG_PRESS_MSB, &mut buf)
+            .context("Cannot read sample from BMP280")?;
+        let p = ((buf[0] as i32) << 12) | ((buf[1] as i32) << 4) | ((buf[2] as i32) >> 4);
+        let t = ((buf[3] as i32) << 12) | ((buf[4] as i32) << 4) | ((buf[5] as i32) >> 4);
+        // Registers hold their reset value when the measurement was skipped
+        if t == 0x80000 {
+            bail!(anyhow!("BMP280 returned an invalid sample"));
+        }
+        Ok(self.calibration.compensate(t, p))
+    }
+
+    /// Values of the configured series, from a single sample.
+    fn measure(&mut self) -> Result<HashMap<String, f64>> {
+        let sample = self.read_sample()?;
+        let mut values = HashMap::new();
+        if let Some(s) = &self.config.temperature_series {
+            values.insert(s.clone(), sample.temperature);
+        }
+        if let Some(s) = &self.config.pressure_series {
+            values.insert(s.clone(), sample.pressure / 100.0);
+        }
+        Ok(values)
+    }
+}
+
+impl Sensor for Bmp280 {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        if !self.series().iter().any(|s| s == series) {
+            return Err(anyhow!("no series configured with name {series}"));
+        }
+        if let Some(value) = self.cache.take(series) {
+            return Ok(value);
+        }
+        let values = self.measure()?;
+        self.cache.store(values);
+        self.cache
+            .take(series)
+            .ok_or_else(|| anyhow!("no series configured with name {series}"))
+    }
+
+    fn series(&self) -> Vec<String> {
+        let mut ret = vec![];
+        if let Some(s) = &self.config.temperature_series {
+            ret.push(s.clone());
+        }
+        if let Some(s) = &self.config.pressure_series {
+            ret.push(s.clone());
+        }
+        ret
+    }
+}
+
+struct Sample {
+    /// Temperature in °C
+    temperature: f64,
+    /// Pressure in Pa
+    pressure: f64,
+}
+
+/// Trimming parameters stored in the sensor non-volatile memory
+struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p2: i16,
+    p3: i16,
+    p4: i16,
+    p5: i16,
+    p6: i16,
+    p7: i16,
+    p8: i16,
+    p9: i16,
+}
+
+impl From<[u8; 24]> for Calibration {
+    fn from(buf: [u8; 24]) -> Self {
+        let u = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+        let s = |i: usize| i16::from_le_bytes([buf[i], buf[i + 1]]);
+        Calibration {
+            t1: u(0),
+            t2: s(2),
+            t3: s(4),
+            p1: u(6),
+            p2: s(8),
+            p3: s(10),
+            p4: s(12),
+            p5: s(14),
+            p6: s(16),
+            p7: s(18),
+            p8: s(20),
+            p9: s(22),
+        }
+    }
+}
+
+impl Calibration {
+    /// Compensation formulas from the BMP280 datasheet (section 8.2)
+    fn compensate(&self, t: i32, p: i32) -> Sample {
+        let var1 = (((t >> 3) - ((self.t1 as i32) << 1)) * (self.t2 as i32)) >> 11;
+        let var2 = (((((t >> 4) - (self.t1 as i32)) * ((t >> 4) - (self.t1 as i32))) >> 12)
+            * (self.t3 as i32))
+            >> 14;
+        let t_fine = var1 + var2;
+        let temperature = ((t_fine * 5 + 128) >> 8) as f64 / 100.0;
+
+        let var1 = (t_fine as i64) - 128000;
+        let var2 = var1 * var1 * (self.p6 as i64);
+        let var2 = var2 + ((var1 * (self.p5 as i64)) << 17);
+        let var2 = var2 + ((self.p4 as i64) << 35);
+        let var1 = ((var1 * var1 * (self.p3 as i64)) >> 8) + ((var1 * (self.p2 as i64)) << 12);
+        let var1 = (((1i64 << 47) + var1) * (self.p1 as i64)) >> 33;
+        let pressure = if var1 == 0 {
+            0.0
+        } else {
+            let p = 1048576 - (p as i64);
+            let p = (((p << 31) - var2) * 3125) / var1;
+            let var1 = ((self.p9 as i64) * (p >> 13) * (p >> 13)) >> 25;
+            let var2 = ((self.p8 as i64) * p) >> 19;
+            let p = ((p + var1 + var2) >> 8) + ((self.p7 as i64) << 4);
+            p as f64 / 256.0
+        };
+
+        Sample {
+            temperature,
+            pressure,
+        }
+    }
+}
+
+fn validate(config: &Bmp280Config) -> Result<()> {
+    // The temperature is needed to compensate pressure readings
+    if config.temperature_oversampling == BoschOversampling::Skip {
+        bail!(anyhow!("BMP280 temperature oversampling cannot be \"skip\""));
+    }
+    bosch::check_oversampling(
+        Chip::Bmp280,
+        "pressure",
+        config.pressure_oversampling,
+        &config.pressure_series,
+    )
+}
+
+fn filter_bits(filter: BoschFilter) -> u8 {
+    match filter {
+        BoschFilter::Off => 0b000,
+        BoschFilter::X2 => 0b001,
+        BoschFilter::X4 => 0b010,
+        BoschFilter::X8 => 0b011,
+        BoschFilter::X16 => 0b100,
+    }
+}
+
+fn standby_bits(standby: Bmp280Standby) -> u8 {
+    match standby {
+        Bmp280Standby::Micros500 => 0b000,
+        Bmp280Standby::Micros62500 => 0b001,
+        Bmp280Standby::Millis125 => 0b010,
+        Bmp280Standby::Millis250 => 0b011,
+        Bmp280Standby::Millis500 => 0b100,
+        Bmp280Standby::Millis1000 => 0b101,
+        Bmp280Standby::Millis2000 => 0b110,
+        Bmp280Standby::Millis4000 => 0b111,
+    }
+}
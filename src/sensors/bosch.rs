@@ -0,0 +1,279 @@
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use crate::config::{
+    BoschAddress::{SdoGnd, SdoVddio},
+    BoschBusConfig, BoschChipSelect, BoschOversampling,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use bme280_multibus::{i2c::Address, Bme280Bus};
+use linux_embedded_hal::gpio_cdev::{Chip as GpioChip, LineHandle, LineRequestFlags};
+use linux_embedded_hal::spidev::{SpiModeFlags, Spidev, SpidevOptions, SpidevTransfer};
+use linux_embedded_hal::I2cdev;
+use thiserror::Error;
+
+const REG_CHIP_ID: u8 = 0xD0;
+/// Holds the SPI memory page selection bit of the BME680
+const REG_BME680_STATUS: u8 = 0x73;
+const MAX_SPI_FREQ: u32 = 10_000_000;
+
+/// Bosch environmental sensors sharing the same register interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    Bmp280,
+    Bme280,
+    Bme680,
+}
+
+impl Chip {
+    fn from_id(id: u8) -> Option<Chip> {
+        match id {
+            // 0x56 and 0x57 are used by BMP280 engineering samples
+            0x56..=0x58 => Some(Chip::Bmp280),
+            0x60 => Some(Chip::Bme280),
+            0x61 => Some(Chip::Bme680),
+            _ => None,
+        }
+    }
+
+    /// Name of the sensor type in the configuration file
+    fn config_name(&self) -> &'static str {
+        match self {
+            Chip::Bmp280 => "bmp280",
+            Chip::Bme280 => "bme280",
+            Chip::Bme680 => "bme680",
+        }
+    }
+}
+
+impl Display for Chip {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Chip::Bmp280 => write!(f, "BMP280"),
+            Chip::Bme280 => write!(f, "BME280"),
+            Chip::Bme680 => write!(f, "BME680"),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ChipIdError {
+    #[error("Expected a {expected} but found a {found}, use the \"{}\" sensor type instead", .found.config_name())]
+    WrongChip { expected: Chip, found: Chip },
+
+    #[error("Expected a {expected} but found an unknown chip (id {id:#04x})")]
+    UnknownChip { expected: Chip, id: u8 },
+}
+
+/// Bus a Bosch sensor is connected to
+pub enum Bus {
+    I2c(bme280_multibus::i2c::Bme280Bus<I2cdev>),
+    Spi(SpiBus),
+}
+
+impl Bus {
+    pub fn new(config: &BoschBusConfig, chip: Chip) -> Result<Bus> {
+        match config {
+            BoschBusConfig::I2c { path, address } => {
+                let addr = match address {
+                    SdoGnd => Address::SdoGnd,
+                    SdoVddio => Address::SdoVddio,
+                };
+                let i2c = I2cdev::new(path).context("Cannot open I2C bus")?;
+                Ok(Bus::I2c(bme280_multibus::i2c::Bme280Bus::new(i2c, addr)))
+            }
+            BoschBusConfig::Spi {
+                path,
+                chip_select,
+                speed_hz,
+            } => Ok(Bus::Spi(SpiBus::new(
+                path,
+                chip_select,
+                *speed_hz,
+                chip == Chip::Bme680,
+            )?)),
+        }
+    }
+
+    /// Reads the chip identifier and checks it matches the expected sensor.
+    pub fn check_chip_id(&mut self, expected: Chip) -> Result<()> {
+        let mut id = [0];
+        self.read_regs(REG_CHIP_ID, &mut id)
+            .with_context(|| format!("Cannot read {expected} chip id"))?;
+        match Chip::from_id(id[0]) {
+            Some(found) if found == expected => Ok(()),
+            Some(found) => Err(ChipIdError::WrongChip { expected, found }.into()),
+            None => Err(ChipIdError::UnknownChip { expected, id: id[0] }.into()),
+        }
+    }
+}
+
+impl Bme280Bus for Bus {
+    type Error = anyhow::Error;
+
+    fn read_regs(&mut self, reg: u8, buf: &mut [u8]) -> Result<()> {
+        match self {
+            Bus::I2c(bus) => Ok(bus.read_regs(reg, buf)?),
+            Bus::Spi(bus) => bus.read_regs(reg, buf),
+        }
+    }
+
+    fn write_reg(&mut self, reg: u8, data: u8) -> Result<()> {
+        match self {
+            Bus::I2c(bus) => Ok(bus.write_reg(reg, data)?),
+            Bus::Spi(bus) => bus.write_reg(reg, data),
+        }
+    }
+}
+
+/// SPI bus performing each register access in a single spidev transfer, so that the hardware
+/// chip select stays asserted during the whole access.
+pub struct SpiBus {
+    spi: Spidev,
+    /// GPIO line used as chip select, if not driven by the SPI controller
+    cs: Option<LineHandle>,
+    /// Whether the register map is split in two memory pages (BME680). Registers 0x80 to 0xFF
+    /// are on page 0, registers 0x00 to 0x7F on page 1.
+    paged: bool,
+    current_page: Option<u8>,
+}
+
+impl SpiBus {
+    fn new(
+        path: &str,
+        chip_select: &BoschChipSelect,
+        speed_hz: u32,
+        paged: bool,
+    ) -> Result<SpiBus> {
+        if speed_hz > MAX_SPI_FREQ {
+            bail!(anyhow!("SPI speed cannot exceed {MAX_SPI_FREQ} Hz"));
+        }
+        let mut spi = Spidev::open(path).context("Cannot open SPI bus")?;
+        spi.configure(
+            &SpidevOptions::new()
+                .mode(SpiModeFlags::SPI_MODE_0)
+                .bits_per_word(8)
+                .max_speed_hz(speed_hz)
+                .build(),
+        )
+        .context("Cannot configure SPI bus")?;
+        let cs = match chip_select {
+            BoschChipSelect::Hardware => None,
+            BoschChipSelect::Gpio { chip, line } => {
+                let handle = GpioChip::new(chip)
+                    .and_then(|mut c| c.get_line(*line))
+                    .and_then(|l| l.request(LineRequestFlags::OUTPUT, 1, "bosch-cs"))
+                    .with_context(|| format!("Cannot request GPIO line {line} of {chip}"))?;
+                Some(handle)
+            }
+        };
+        Ok(SpiBus {
+            spi,
+            cs,
+            paged,
+            current_page: None,
+        })
+    }
+
+    fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<()> {
+        if let Some(cs) = &self.cs {
+            cs.set_value(0).context("Cannot assert chip select")?;
+        }
+        let result = self
+            .spi
+            .transfer(&mut SpidevTransfer::read_write(tx, rx))
+            .context("SPI transfer failed");
+        if let Some(cs) = &self.cs {
+            cs.set_value(1).context("Cannot release chip select")?;
+        }
+        result
+    }
+
+    /// Selects the memory page holding `reg`, if needed.
+    fn select_page(&mut self, reg: u8) -> Result<()> {
+        if !self.paged || reg == REG_BME680_STATUS {
+            return Ok(());
+        }
+        let page = if reg & 0x80 != 0 { 0 } else { 1 };
+        if self.current_page != Some(page) {
+            // The status register is reachable from both pages
+            let tx = [REG_BME680_STATUS & 0x7F, page << 4];
+            let mut rx = [0; 2];
+            self.transfer(&tx, &mut rx)?;
+            self.current_page = Some(page);
+        }
+        Ok(())
+    }
+
+    fn read_regs(&mut self, reg: u8, buf: &mut [u8]) -> Result<()> {
+        self.select_page(reg)?;
+        // The register address is followed by dummy bytes clocking the data out
+        let mut tx = vec![0; buf.len() + 1];
+        tx[0] = reg | 0x80;
+        let mut rx = vec![0; buf.len() + 1];
+        self.transfer(&tx, &mut rx)?;
+        buf.copy_from_slice(&rx[1..]);
+        Ok(())
+    }
+
+    fn write_reg(&mut self, reg: u8, data: u8) -> Result<()> {
+        self.select_page(reg)?;
+        let tx = [reg & 0x7F, data];
+        let mut rx = [0; 2];
+        self.transfer(&tx, &mut rx)
+    }
+}
+
+/// Register value of an oversampling setting, common to all Bosch sensors
+pub fn oversampling_bits(os: BoschOversampling) -> u8 {
+    match os {
+        BoschOversampling::Skip => 0b000,
+        BoschOversampling::X1 => 0b001,
+        BoschOversampling::X2 => 0b010,
+        BoschOversampling::X4 => 0b011,
+        BoschOversampling::X8 => 0b100,
+        BoschOversampling::X16 => 0b101,
+    }
+}
+
+pub fn oversampling_factor(os: BoschOversampling) -> u64 {
+    match os {
+        BoschOversampling::Skip => 0,
+        BoschOversampling::X1 => 1,
+        BoschOversampling::X2 => 2,
+        BoschOversampling::X4 => 4,
+        BoschOversampling::X8 => 8,
+        BoschOversampling::X16 => 16,
+    }
+}
+
+/// Maximum measurement duration, as given in the BME280 datasheet (section 9.1). It also
+/// applies to the BMP280, which has no humidity measurement.
+pub fn measurement_time(
+    temperature: BoschOversampling,
+    pressure: BoschOversampling,
+    humidity: BoschOversampling,
+) -> Duration {
+    let mut micros = 1250 + 2300 * oversampling_factor(temperature);
+    for os in [pressure, humidity] {
+        if os != BoschOversampling::Skip {
+            micros += 2300 * oversampling_factor(os) + 575;
+        }
+    }
+    Duration::from_micros(micros)
+}
+
+/// Checks a quantity is measured when a series is associated to it.
+pub fn check_oversampling(
+    chip: Chip,
+    quantity: &str,
+    os: BoschOversampling,
+    series: &Option<String>,
+) -> Result<()> {
+    if os == BoschOversampling::Skip && series.is_some() {
+        bail!(anyhow!(
+            "{chip} {quantity} oversampling cannot be \"skip\" when a {quantity} series is configured"
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Values measured together by a sensor. Each value is handed out once, so that series sampled
+/// in the same cycle share a single measurement, while the next cycle triggers a new one.
+pub struct BatchCache<T = f64> {
+    values: HashMap<String, T>,
+    measured_at: Option<Instant>,
+    max_age: Duration,
+}
+
+impl<T> BatchCache<T> {
+    /// Values older than `max_age` are discarded.
+    pub fn new(max_age: Duration) -> BatchCache<T> {
+        BatchCache {
+            values: HashMap::new(),
+            measured_at: None,
+            max_age,
+        }
+    }
+
+    /// Takes the value of a series from the last measurement, if it is still fresh.
+    pub fn take(&mut self, series: &str) -> Option<T> {
+        match self.measured_at {
+            Some(t) if t.elapsed() <= self.max_age => self.values.remove(series),
+            _ => None,
+        }
+    }
+
+    pub fn store(&mut self, values: HashMap<String, T>) {
+        self.values = values;
+        self.measured_at = Some(Instant::now());
+    }
+}
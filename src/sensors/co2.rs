@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use nix::sys::termios::BaudRate;
+
+use crate::config::{Mhz19Config, SenseairS8Config};
+use crate::sensors::modbus;
+use crate::sensors::serial::{Parity, SerialPort};
+use crate::sensors::Sensor;
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+/// Read CO2 concentration command of the MH-Z19
+const MHZ19_READ: [u8; 9] = [0xFF, 0x01, 0x86, 0x00, 0x00, 0x00, 0x00, 0x00, 0x79];
+/// Modbus request reading the CO2 input register of the Senseair S8, with the "any sensor"
+/// address
+const S8_READ: [u8; 8] = [0xFE, 0x04, 0x00, 0x03, 0x00, 0x01, 0xD5, 0xC5];
+
+/// Winsen MH-Z19 CO2 sensor
+pub struct Mhz19 {
+    port: SerialPort,
+    series: String,
+}
+
+impl Mhz19 {
+    pub fn new(config: Mhz19Config) -> Result<Mhz19> {
+        Ok(Mhz19 {
+            port: SerialPort::open(&config.path, BaudRate::B9600, Parity::None)?,
+            series: config.co2_series,
+        })
+    }
+}
+
+impl Sensor for Mhz19 {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        if series != self.series {
+            return Err(anyhow!("no series configured with name {series}"));
+        }
+        self.port.discard_input()?;
+        self.port.write_all(&MHZ19_READ)?;
+        let mut resp = [0; 9];
+        self.port.read_exact(&mut resp, TIMEOUT)?;
+        parse_mhz19(&resp)
+    }
+
+    fn series(&self) -> Vec<String> {
+        vec![self.series.clone()]
+    }
+}
+
+fn parse_mhz19(resp: &[u8; 9]) -> Result<f64> {
+    if resp[..2] != [0xFF, 0x86] {
+        bail!(anyhow!("unexpected MH-Z19 response {resp:02x?}"));
+    }
+    let sum = resp[1..8].iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+    let checksum = 0xFFu8.wrapping_sub(sum).wrapping_add(1);
+    if checksum != resp[8] {
+        bail!(anyhow!(
+            "MH-Z19 checksum mismatch: expected {checksum:#04x}, received {:#04x}",
+            resp[8]
+        ));
+    }
+    Ok(u16::from_be_bytes([resp[2], resp[3]]) as f64)
+}
+
+/// Senseair S8 CO2 sensor, read over Modbus
+pub struct SenseairS8 {
+    port: SerialPort,
+    series: String,
+}
+
+impl SenseairS8 {
+    pub fn new(config: SenseairS8Config) -> Result<SenseairS8> {
+        Ok(SenseairS8 {
+            port: SerialPort::open(&config.path, BaudRate::B9600, Parity::None)?,
+            series: config.co2_series,
+        })
+    }
+}
+
+impl Sensor for SenseairS8 {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        if series != self.series {
+            return Err(anyhow!("no series configured with name {series}"));
+        }
+        self.port.discard_input()?;
+        self.port.write_all(&S8_READ)?;
+        let mut resp = [0; 7];
+        self.port.read_exact(&mut resp, TIMEOUT)?;
+        parse_s8(&resp)
+    }
+
+    fn series(&self) -> Vec<String> {
+        vec![self.series.clone()]
+    }
+}
+
+fn parse_s8(resp: &[u8; 7]) -> Result<f64> {
+    let crc = modbus::crc(&resp[..5]);
+    if crc != u16::from_le_bytes([resp[5], resp[6]]) {
+        bail!(anyhow!(
+            "Senseair S8 CRC mismatch: expected {crc:#06x}, received {:02x?}",
+            &resp[5..]
+        ));
+    }
+    if resp[..3] != [0xFE, 0x04, 0x02] {
+        bail!(anyhow!("unexpected Senseair S8 response {resp:02x?}"));
+    }
+    Ok(u16::from_be_bytes([resp[3], resp[4]]) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mhz19_response() {
+        let resp = [0xFF, 0x86, 0x02, 0x60, 0x47, 0x00, 0x00, 0x00, 0xD1];
+        assert_eq!(parse_mhz19(&resp).unwrap(), 608.0);
+
+        let mut bad = resp;
+        bad[8] = 0xD2;
+        assert!(parse_mhz19(&bad).is_err());
+        let mut bad = resp;
+        bad[1] = 0x87;
+        assert!(parse_mhz19(&bad).is_err());
+    }
+
+    #[test]
+    fn s8_response() {
+        let resp = [0xFE, 0x04, 0x02, 0x01, 0x90, 0xAC, 0xD8];
+        assert_eq!(parse_s8(&resp).unwrap(), 400.0);
+
+        let mut bad = resp;
+        bad[4] = 0x91;
+        assert!(parse_s8(&bad).is_err());
+        let mut bad = resp;
+        bad[6] = 0xD9;
+        assert!(parse_s8(&bad).is_err());
+    }
+}
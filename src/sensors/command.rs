@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::{Child, Command as Process, Stdio};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::config::{CommandConfig, CommandOutput, ValueType};
+use crate::record::Value;
+use crate::sensors::cache::BatchCache;
+use crate::sensors::Sensor;
+use anyhow::{anyhow, bail, Context, Result};
+
+/// Values printed by an external program on its standard output
+pub struct Command {
+    config: CommandConfig,
+    timeout: Duration,
+    cache: BatchCache<Value>,
+}
+
+impl Command {
+    pub fn new(config: CommandConfig) -> Result<Command> {
+        let timeout = humantime::parse_duration(&config.timeout)?;
+        if config.output != CommandOutput::Json && config.series.len() != 1 {
+            bail!(anyhow!(
+                "a command with a number or state output must feed exactly one series, use a json \
+                 output to feed several series"
+            ));
+        }
+        Ok(Command {
+            config,
+            timeout,
+            cache: BatchCache::new(Duration::from_secs(5)),
+        })
+    }
+
+    /// Runs the command and returns its standard output.
+    fn run(&self) -> Result<String> {
+        let mut child = Process::new(&self.config.command)
+            .args(&self.config.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Cannot run {}", self.config.command))?;
+        // Outputs are read in the background so that the command does not block on a full pipe
+        let stdout = read_in_background(child.stdout.take());
+        let stderr = read_in_background(child.stderr.take());
+
+        let status = wait_timeout(&mut child, self.timeout)?;
+        let stdout = stdout.join().unwrap_or_default();
+        let stderr = stderr.join().unwrap_or_default();
+        if !status.success() {
+            bail!(anyhow!(
+                "{} exited with {}: {}",
+                self.config.command,
+                status,
+                stderr.trim()
+            ));
+        }
+        Ok(stdout)
+    }
+
+    fn parse(&self, output: &str) -> Result<HashMap<String, Value>> {
+        match self.config.output {
+            CommandOutput::Number => {
+                let value: f64 = output.trim().parse().with_context(|| {
+                    format!("Failed to parse number from '{}'", output.escape_debug())
+                })?;
+                Ok(HashMap::from([(self.config.series[0].clone(), Value::Number(value))]))
+            }
+            CommandOutput::State => Ok(HashMap::from([(
+                self.config.series[0].clone(),
+                Value::State(output.trim().to_owned()),
+            )])),
+            CommandOutput::Json => serde_json::from_str(output).with_context(|| {
+                format!("Failed to parse JSON object from '{}'", output.escape_debug())
+            }),
+        }
+    }
+}
+
+impl Sensor for Command {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        match self.sample_value(series)?.into_type(ValueType::Number)? {
+            Value::Number(value) => Ok(value),
+            value => Err(anyhow!("{series} value {value} is not a number")),
+        }
+    }
+
+    fn sample_value(&mut self, series: &str) -> Result<Value> {
+        if !self.config.series.iter().any(|s| s == series) {
+            return Err(anyhow!("no series configured with name {series}"));
+        }
+        if let Some(value) = self.cache.take(series) {
+            return Ok(value);
+        }
+        let values = self.parse(&self.run()?)?;
+        self.cache.store(values);
+        self.cache
+            .take(series)
+            .ok_or_else(|| anyhow!("no value for {series} in {} output", self.config.command))
+    }
+
+    fn series(&self) -> Vec<String> {
+        self.config.series.clone()
+    }
+}
+
+fn read_in_background<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<String> {
+    spawn(move || {
+        let mut output = String::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_string(&mut output);
+        }
+        output
+    })
+}
+
+fn wait_timeout(child: &mut Child, timeout: Duration) -> Result<std::process::ExitStatus> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!(anyhow!("command timed out after {}", humantime::format_duration(timeout)));
+        }
+        sleep(Duration::from_millis(10));
+    }
+}
@@ -1,10 +1,14 @@
-use crate::config::Ds18b20Config;
+use crate::config::{Ds18b20BusConfig, Ds18b20Config, Ds18b20Settings};
+use crate::sensors::one_wire::{self, Family};
 use crate::sensors::Sensor;
-use anyhow::{anyhow, Context, Result};
-use std::fs;
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use thiserror::Error;
 
+/// DS18B20 probe, or any other thermometer handled by the w1_therm driver (DS18S20, DS1822)
 pub struct Ds18b20 {
+    w1_root: String,
     serial_number: String,
     temperature_series: Option<String>,
 }
@@ -12,10 +16,13 @@ pub struct Ds18b20 {
 impl Ds18b20 {
     pub fn new(config: Ds18b20Config) -> Result<Ds18b20> {
         let sn = match config.serial_number {
-            None => detect_ds18b20()?,
+            None => detect_ds18b20(&config.w1_root)?,
             Some(sn) => sn,
         };
+        one_wire::check_family(&sn, &Family::THERMOMETERS)?;
+        Settings::new(&config.settings)?.apply(&config.w1_root, &sn)?;
         Ok(Ds18b20 {
+            w1_root: config.w1_root,
             serial_number: sn,
             temperature_series: config.temperature_series,
         })
@@ -24,24 +31,14 @@ impl Ds18b20 {
 
 impl Sensor for Ds18b20 {
     fn sample(&mut self, series: &str) -> Result<f64> {
-        let ds18b20_file = format!("/sys/bus/w1/devices/{}/temperature", self.serial_number);
-
-        let raw_temperature = fs::read_to_string(&ds18b20_file)
-            .with_context(|| format!("Failed to read file at {}", &ds18b20_file))?;
-
-        let int_temperature: i32 = raw_temperature.trim().parse().with_context(|| {
-            format!(
-                "Failed to parse temperature from file content '{}'",
-                raw_temperature.escape_debug().to_string()
-            )
-        })?;
+        let temperature = one_wire::read_thermometer(&self.w1_root, &self.serial_number)?;
 
         if self
             .temperature_series
             .as_ref()
             .is_some_and(|s| s == series)
         {
-            Ok(int_temperature as f64 / 1000.0)
+            Ok(temperature)
         } else {
             Err(anyhow!("no series configured with name {series}"))
         }
@@ -55,6 +52,150 @@ impl Sensor for Ds18b20 {
     }
 }
 
+/// All the thermometer probes connected to the 1-Wire bus, each one recorded in its own series
+pub struct Ds18b20Bus {
+    w1_root: String,
+    settings: Settings,
+    /// Serial number of the probe associated to each series
+    serial_by_series: HashMap<String, String>,
+    /// Probes detected so far, to report newly attached ones
+    detected: HashSet<String>,
+}
+
+impl Ds18b20Bus {
+    pub fn new(config: Ds18b20BusConfig) -> Result<Ds18b20Bus> {
+        let mut serial_by_series = HashMap::new();
+        for (sn, series) in config.probes {
+            one_wire::check_family(&sn, &Family::THERMOMETERS)?;
+            if let Some(prev) = serial_by_series.insert(series.clone(), sn.clone()) {
+                return Err(anyhow!(
+                    "the \"{series}\" series is associated to {prev} and {sn} probes"
+                ));
+            }
+        }
+        let mut bus = Ds18b20Bus {
+            w1_root: config.w1_root,
+            settings: Settings::new(&config.settings)?,
+            serial_by_series,
+            detected: HashSet::new(),
+        };
+        for sn in bus.discover()? {
+            bus.settings.apply(&bus.w1_root, &sn)?;
+        }
+        for sn in bus.serial_by_series.values() {
+            if !bus.detected.contains(sn) {
+                println!("Warning: DS18B20 probe {sn} is not connected");
+            }
+        }
+        Ok(bus)
+    }
+
+    /// Lists the probes on the bus, reports the ones not seen before and returns the newly
+    /// detected probes associated to a series.
+    fn discover(&mut self) -> Result<Vec<String>> {
+        let probes = match list_ds18b20(&self.w1_root) {
+            Ok(probes) => probes,
+            Err(DetectSensorError::NoSensorDetected) => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        let mut new_probes = vec![];
+        for sn in probes {
+            if self.detected.insert(sn.clone()) {
+                match self.serial_by_series.iter().find(|(_, s)| **s == sn) {
+                    Some((series, _)) => {
+                        println!("DS18B20 probe {sn} detected, recorded in \"{series}\" series");
+                        new_probes.push(sn);
+                    }
+                    None => println!(
+                        "DS18B20 probe {sn} detected, but not associated to any series"
+                    ),
+                }
+            }
+        }
+        Ok(new_probes)
+    }
+}
+
+impl Sensor for Ds18b20Bus {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        match self.discover() {
+            Ok(new_probes) => {
+                for sn in new_probes {
+                    if let Err(e) = self.settings.apply(&self.w1_root, &sn) {
+                        println!("Warning: cannot configure DS18B20 probe {sn}: {e:#}");
+                    }
+                }
+            }
+            Err(e) => println!("Warning: cannot discover DS18B20 probes: {e}"),
+        }
+        let sn = self
+            .serial_by_series
+            .get(series)
+            .ok_or_else(|| anyhow!("no series configured with name {series}"))?;
+        one_wire::read_thermometer(&self.w1_root, sn)
+    }
+
+    fn series(&self) -> Vec<String> {
+        self.serial_by_series.keys().cloned().collect()
+    }
+}
+
+/// Resolution and conversion time applied to the probes
+struct Settings {
+    resolution: Option<u8>,
+    conversion_time: Option<Duration>,
+}
+
+impl Settings {
+    fn new(cfg: &Ds18b20Settings) -> Result<Settings> {
+        if let Some(r) = cfg.resolution {
+            if !(9..=12).contains(&r) {
+                bail!(anyhow!("DS18B20 resolution must be between 9 and 12 bits, got {r}"));
+            }
+        }
+        let conversion_time = match &cfg.conversion_time {
+            Some(t) => Some(humantime::parse_duration(t)?),
+            None => None,
+        };
+        Ok(Settings {
+            resolution: cfg.resolution,
+            conversion_time,
+        })
+    }
+
+    /// Writes the settings through the w1_therm sysfs attributes, then reads them back to
+    /// check they have been applied.
+    fn apply(&self, w1_root: &str, serial_number: &str) -> Result<()> {
+        if let Some(resolution) = self.resolution {
+            if Family::from_serial_number(serial_number) == Some(Family::Ds18s20) {
+                bail!(anyhow!(
+                    "the resolution of the {serial_number} DS18S20 probe cannot be changed"
+                ));
+            }
+            let resolution = resolution.to_string();
+            check_attribute(w1_root, serial_number, "resolution", &resolution)?;
+        }
+        if let Some(conversion_time) = self.conversion_time {
+            let millis = conversion_time.as_millis().to_string();
+            check_attribute(w1_root, serial_number, "conv_time", &millis)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_attribute(w1_root: &str, serial_number: &str, attribute: &str, value: &str) -> Result<()> {
+    one_wire::write_attribute(w1_root, serial_number, attribute, value)
+        .with_context(|| format!("Cannot set {attribute} of {serial_number} probe"))?;
+    let read = one_wire::read_attribute(w1_root, serial_number, attribute)?;
+    if read.trim() != value {
+        bail!(anyhow!(
+            "{attribute} of {serial_number} probe is {} instead of {value}",
+            read.trim()
+        ));
+    }
+    Ok(())
+}
+
 #[derive(Error, Debug)]
 pub enum DetectSensorError {
     #[error("Cannot list one wire directory content")]
@@ -67,24 +208,166 @@ pub enum DetectSensorError {
     NoSensorDetected,
 }
 
-pub fn detect_ds18b20() -> Result<String, DetectSensorError> {
-    let files: Vec<String> = fs::read_dir("/sys/bus/w1/devices")
-        .map_err(|e| DetectSensorError::MissingDirectory(e))?
-        .filter_map(|r| match r {
-            Err(_) => None,
-            Ok(d) => {
-                let name = d.file_name().to_string_lossy().to_string();
-                if name.starts_with("28-") {
-                    Some(name.into())
-                } else {
-                    None
-                }
-            }
-        })
-        .collect();
+pub fn detect_ds18b20(w1_root: &str) -> Result<String, DetectSensorError> {
+    let files = list_ds18b20(w1_root)?;
     match files.len() {
-        0 => Err(DetectSensorError::NoSensorDetected),
         1 => Ok(files.into_iter().next().unwrap()),
         _ => Err(DetectSensorError::SeveralSensorsDetected(files)),
     }
 }
+
+/// Lists the serial numbers of the thermometer probes connected to the 1-Wire bus.
+pub fn list_ds18b20(w1_root: &str) -> Result<Vec<String>, DetectSensorError> {
+    let files = one_wire::list_devices(w1_root, &Family::THERMOMETERS)?;
+    match files.len() {
+        0 => Err(DetectSensorError::NoSensorDetected),
+        _ => Ok(files),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("raspi-ds18b20-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn add_probe(dir: &Path, serial_number: &str, millis: i32) {
+        let probe = dir.join(serial_number);
+        fs::create_dir_all(&probe).unwrap();
+        let data = "72 01 4b 46 7f ff 0e 10 57";
+        let content = format!("{data} : crc=57 YES\n{data} t={millis}\n");
+        fs::write(probe.join("w1_slave"), content).unwrap();
+    }
+
+    fn bus_config(dir: &Path, probes: &[(&str, &str)]) -> Ds18b20BusConfig {
+        Ds18b20BusConfig {
+            w1_root: dir.to_str().unwrap().to_owned(),
+            probes: probes
+                .iter()
+                .map(|(sn, series)| (sn.to_string(), series.to_string()))
+                .collect(),
+            settings: Ds18b20Settings {
+                resolution: None,
+                conversion_time: None,
+            },
+        }
+    }
+
+    #[test]
+    fn detection() {
+        let dir = test_dir("detection");
+        let root = dir.to_str().unwrap();
+        fs::create_dir(dir.join("w1_bus_master1")).unwrap();
+        assert!(matches!(
+            list_ds18b20(root),
+            Err(DetectSensorError::NoSensorDetected)
+        ));
+        add_probe(&dir, "28-0316a2790bff", 21500);
+        assert_eq!(detect_ds18b20(root).unwrap(), "28-0316a2790bff");
+        add_probe(&dir, "10-000802b4c8e2", 19000);
+        assert_eq!(
+            list_ds18b20(root).unwrap(),
+            ["10-000802b4c8e2", "28-0316a2790bff"]
+        );
+        assert!(matches!(
+            detect_ds18b20(root),
+            Err(DetectSensorError::SeveralSensorsDetected(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(
+            list_ds18b20(root),
+            Err(DetectSensorError::MissingDirectory(_))
+        ));
+    }
+
+    #[test]
+    fn bus() {
+        let dir = test_dir("bus");
+        fs::create_dir(dir.join("w1_bus_master1")).unwrap();
+        add_probe(&dir, "28-0316a2790bff", 21500);
+        add_probe(&dir, "28-0000075e1a3c", -4250);
+        let config = bus_config(
+            &dir,
+            &[
+                ("28-0316a2790bff", "outdoor"),
+                ("28-3c01d607c0aa", "cellar"),
+            ],
+        );
+        let mut bus = Ds18b20Bus::new(config).unwrap();
+        let mut series = bus.series();
+        series.sort();
+        assert_eq!(series, ["cellar", "outdoor"]);
+        assert_eq!(
+            bus.detected,
+            HashSet::from(["28-0316a2790bff".to_owned(), "28-0000075e1a3c".to_owned()])
+        );
+
+        assert_eq!(bus.sample("outdoor").unwrap(), 21.5);
+        // The cellar probe is not connected yet
+        assert!(bus.sample("cellar").is_err());
+        assert!(bus.sample("indoor").is_err());
+
+        // Only the new probes associated to a series are returned, once
+        add_probe(&dir, "28-3c01d607c0aa", 12750);
+        add_probe(&dir, "28-0000075e2b4d", 20000);
+        assert_eq!(bus.discover().unwrap(), ["28-3c01d607c0aa"]);
+        assert!(bus.detected.contains("28-0000075e2b4d"));
+        assert!(bus.discover().unwrap().is_empty());
+        assert_eq!(bus.sample("cellar").unwrap(), 12.75);
+
+        let config = bus_config(
+            &dir,
+            &[
+                ("28-0316a2790bff", "outdoor"),
+                ("28-3c01d607c0aa", "outdoor"),
+            ],
+        );
+        assert!(Ds18b20Bus::new(config).is_err());
+        let config = bus_config(&dir, &[("26-000000e8c5f3", "battery")]);
+        assert!(Ds18b20Bus::new(config).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn settings() {
+        let dir = test_dir("settings");
+        let root = dir.to_str().unwrap();
+        add_probe(&dir, "28-0316a2790bff", 21500);
+        add_probe(&dir, "10-000802b4c8e2", 19000);
+        let settings = Settings::new(&Ds18b20Settings {
+            resolution: Some(10),
+            conversion_time: Some("188ms".to_owned()),
+        })
+        .unwrap();
+        settings.apply(root, "28-0316a2790bff").unwrap();
+        let probe = dir.join("28-0316a2790bff");
+        assert_eq!(fs::read_to_string(probe.join("resolution")).unwrap(), "10");
+        assert_eq!(fs::read_to_string(probe.join("conv_time")).unwrap(), "188");
+        assert!(settings.apply(root, "10-000802b4c8e2").is_err());
+
+        // The driver ignores the written resolution
+        fs::remove_file(probe.join("resolution")).unwrap();
+        std::os::unix::fs::symlink("/dev/null", probe.join("resolution")).unwrap();
+        let error = settings.apply(root, "28-0316a2790bff").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "resolution of 28-0316a2790bff probe is  instead of 10"
+        );
+
+        let resolution = |resolution| Ds18b20Settings {
+            resolution: Some(resolution),
+            conversion_time: None,
+        };
+        assert!(Settings::new(&resolution(8)).is_err());
+        assert!(Settings::new(&resolution(13)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
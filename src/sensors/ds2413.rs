@@ -0,0 +1,65 @@
+use std::fs;
+use std::path::Path;
+
+use crate::config::Ds2413Config;
+use crate::sensors::one_wire::{self, Family};
+use crate::sensors::Sensor;
+use anyhow::{anyhow, bail, Context, Result};
+
+/// DS2413 dual channel switch, read through the w1_ds2413 driver
+pub struct Ds2413 {
+    config: Ds2413Config,
+}
+
+impl Ds2413 {
+    pub fn new(config: Ds2413Config) -> Result<Ds2413> {
+        one_wire::check_family(&config.serial_number, &[Family::Ds2413])?;
+        Ok(Ds2413 { config })
+    }
+
+    /// Reads the PIO status byte
+    fn read_state(&self) -> Result<u8> {
+        let path = Path::new(&self.config.w1_root)
+            .join(&self.config.serial_number)
+            .join("state");
+        let content =
+            fs::read(&path).with_context(|| format!("Failed to read file at {}", path.display()))?;
+        let state = match content.as_slice() {
+            [state] => *state,
+            _ => bail!(anyhow!("Malformed DS2413 state {:02x?}", content)),
+        };
+        parse_state(state)
+    }
+}
+
+impl Sensor for Ds2413 {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        if self.config.pio_a_series.as_ref().is_some_and(|s| s == series) {
+            Ok((self.read_state()? & 0b0001) as f64)
+        } else if self.config.pio_b_series.as_ref().is_some_and(|s| s == series) {
+            Ok(((self.read_state()? & 0b0100) >> 2) as f64)
+        } else {
+            Err(anyhow!("no series configured with name {series}"))
+        }
+    }
+
+    fn series(&self) -> Vec<String> {
+        let mut ret = vec![];
+        if let Some(s) = &self.config.pio_a_series {
+            ret.push(s.clone());
+        }
+        if let Some(s) = &self.config.pio_b_series {
+            ret.push(s.clone());
+        }
+        ret
+    }
+}
+
+/// Checks the status byte integrity: its upper nibble is the complement of the lower one,
+/// which holds the PIO A pin state, PIO A latch state, PIO B pin state and PIO B latch state.
+fn parse_state(state: u8) -> Result<u8> {
+    if state >> 4 != !state & 0x0F {
+        bail!(anyhow!("DS2413 state {state:#04x} failed integrity check"));
+    }
+    Ok(state & 0x0F)
+}
@@ -0,0 +1,63 @@
+use crate::config::Ds2438Config;
+use crate::sensors::one_wire::{self, Family};
+use crate::sensors::Sensor;
+use anyhow::{anyhow, bail, Result};
+
+/// DS2438 battery monitor, read through the w1_ds2438 driver
+pub struct Ds2438 {
+    config: Ds2438Config,
+}
+
+impl Ds2438 {
+    pub fn new(config: Ds2438Config) -> Result<Ds2438> {
+        one_wire::check_family(&config.serial_number, &[Family::Ds2438])?;
+        if let (Some(series), None) = (&config.current_series, config.sense_resistor) {
+            bail!(anyhow!(
+                "a sense resistor value is required by the \"{series}\" current series"
+            ));
+        }
+        Ok(Ds2438 { config })
+    }
+
+    fn read(&self, attribute: &str) -> Result<i64> {
+        let content =
+            one_wire::read_attribute(&self.config.w1_root, &self.config.serial_number, attribute)?;
+        one_wire::parse_int(&content)
+    }
+}
+
+impl Sensor for Ds2438 {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        if self.config.temperature_series.as_ref().is_some_and(|s| s == series) {
+            // Raw register value, 1/256 °C per bit
+            Ok(self.read("temperature")? as f64 / 256.0)
+        } else if self.config.vad_series.as_ref().is_some_and(|s| s == series) {
+            // 10 mV per bit
+            Ok(self.read("vad")? as f64 / 100.0)
+        } else if self.config.vdd_series.as_ref().is_some_and(|s| s == series) {
+            Ok(self.read("vdd")? as f64 / 100.0)
+        } else if self.config.current_series.as_ref().is_some_and(|s| s == series) {
+            // Voltage across the sense resistor, 0.2441 mV per bit
+            let resistor = self.config.sense_resistor.unwrap();
+            Ok(self.read("iad")? as f64 / (4096.0 * resistor))
+        } else {
+            Err(anyhow!("no series configured with name {series}"))
+        }
+    }
+
+    fn series(&self) -> Vec<String> {
+        let mut ret = vec![];
+        for s in [
+            &self.config.temperature_series,
+            &self.config.vad_series,
+            &self.config.vdd_series,
+            &self.config.current_series,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            ret.push(s.clone());
+        }
+        ret
+    }
+}
@@ -0,0 +1,109 @@
+use crate::config::FileConfig;
+use crate::sensors::Sensor;
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use std::fs;
+
+/// Number read from any file, optionally extracted with a regular expression
+pub struct File {
+    config: FileConfig,
+    regex: Option<Regex>,
+}
+
+impl File {
+    pub fn new(config: FileConfig) -> Result<File> {
+        let regex = match &config.regex {
+            Some(r) => Some(Regex::new(r).with_context(|| format!("Invalid regex '{r}'"))?),
+            None => None,
+        };
+        Ok(File { config, regex })
+    }
+
+    fn extract<'a>(&self, content: &'a str) -> Result<&'a str> {
+        let Some(regex) = &self.regex else {
+            return Ok(content.trim());
+        };
+        let captures = regex.captures(content).ok_or_else(|| {
+            anyhow!(
+                "No match for regex '{}' in '{}'",
+                regex,
+                content.escape_debug()
+            )
+        })?;
+        let m = captures.get(1).or_else(|| captures.get(0)).unwrap();
+        Ok(m.as_str().trim())
+    }
+}
+
+impl Sensor for File {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        if self.config.series != series {
+            return Err(anyhow!("no series configured with name {series}"));
+        }
+        let content = fs::read_to_string(&self.config.path)
+            .with_context(|| format!("Failed to read file at {}", &self.config.path))?;
+        let raw = self.extract(&content)?;
+        let value: f64 = raw.parse().with_context(|| {
+            format!("Failed to parse number from '{}'", raw.escape_debug())
+        })?;
+        Ok(value * self.config.scale + self.config.offset)
+    }
+
+    fn series(&self) -> Vec<String> {
+        vec![self.config.series.clone()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn file(path: &str, regex: Option<&str>, scale: f64, offset: f64) -> Result<File> {
+        File::new(FileConfig {
+            path: path.to_owned(),
+            regex: regex.map(str::to_owned),
+            scale,
+            offset,
+            series: "value".to_owned(),
+        })
+    }
+
+    #[test]
+    fn extraction() {
+        let whole = file("", None, 1.0, 0.0).unwrap();
+        assert_eq!(whole.extract(" 42.5\n").unwrap(), "42.5");
+
+        let group = file("", Some(r"temp=\s*(-?[\d.]+)"), 1.0, 0.0).unwrap();
+        assert_eq!(group.extract("id=3 temp= -4.25 C\n").unwrap(), "-4.25");
+
+        // Without any capture group, the whole match
+        let whole_match = file("", Some(r"-?\d+\.\d+"), 1.0, 0.0).unwrap();
+        assert_eq!(whole_match.extract("level: 0.75 (ok)").unwrap(), "0.75");
+
+        assert_eq!(
+            group
+                .extract("id=3\nhumidity=54\n")
+                .unwrap_err()
+                .to_string(),
+            r"No match for regex 'temp=\s*(-?[\d.]+)' in 'id=3\nhumidity=54\n'"
+        );
+        assert!(file("", Some("temp=("), 1.0, 0.0).is_err());
+    }
+
+    #[test]
+    fn sample() {
+        let path = env::temp_dir().join(format!("raspi-file-{}", std::process::id()));
+        fs::write(&path, "voltage: 1234 mV\n").unwrap();
+        let path = path.to_str().unwrap();
+        let mut sensor = file(path, Some(r"voltage: (\d+)"), 0.001, -0.5).unwrap();
+        assert_eq!(sensor.series(), ["value"]);
+        assert!((sensor.sample("value").unwrap() - 0.734).abs() < 1e-9);
+        assert!(sensor.sample("other").is_err());
+
+        fs::write(path, "voltage: n/a\n").unwrap();
+        assert!(sensor.sample("value").is_err());
+        fs::remove_file(path).unwrap();
+        assert!(sensor.sample("value").is_err());
+    }
+}
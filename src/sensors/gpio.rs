@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use linux_embedded_hal::gpio_cdev::{
+    Chip, EventRequestFlags, LineEventHandle, LineHandle, LineRequestFlags,
+};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+
+use crate::config::{GpioConfig, GpioEdge, GpioMode};
+use crate::sensors::Sensor;
+
+const CONSUMER: &str = "raspi";
+
+/// Input read from a GPIO line
+pub enum Gpio {
+    Counter(Counter),
+    State(State),
+}
+
+impl Gpio {
+    pub fn new(config: GpioConfig) -> Result<Gpio> {
+        let debounce = match &config.debounce {
+            Some(d) => humantime::parse_duration(d)?,
+            None => Duration::ZERO,
+        };
+        let mut flags = LineRequestFlags::INPUT;
+        if config.active_low {
+            flags |= LineRequestFlags::ACTIVE_LOW;
+        }
+        let line = Chip::new(&config.chip)
+            .and_then(|mut c| c.get_line(config.line))
+            .with_context(|| format!("Cannot get GPIO line {} of {}", config.line, config.chip))?;
+        let busy = || {
+            format!(
+                "Cannot request GPIO line {} of {}",
+                config.line, config.chip
+            )
+        };
+        match config.mode {
+            GpioMode::Counter {
+                edge,
+                factor,
+                count_series,
+                rate_series,
+            } => {
+                let edge = match edge {
+                    GpioEdge::Rising => EventRequestFlags::RISING_EDGE,
+                    GpioEdge::Falling => EventRequestFlags::FALLING_EDGE,
+                    GpioEdge::Both => EventRequestFlags::BOTH_EDGES,
+                };
+                let events = line.events(flags, edge, CONSUMER).with_context(busy)?;
+                Ok(Gpio::Counter(Counter::new(
+                    events,
+                    debounce,
+                    factor,
+                    count_series,
+                    rate_series,
+                )))
+            }
+            GpioMode::State { state_series } => {
+                let handle = line.request(flags, 0, CONSUMER).with_context(busy)?;
+                Ok(Gpio::State(State {
+                    handle,
+                    debounce,
+                    series: state_series,
+                }))
+            }
+        }
+    }
+}
+
+impl Sensor for Gpio {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        match self {
+            Gpio::Counter(counter) => counter.sample(series),
+            Gpio::State(state) => state.sample(series),
+        }
+    }
+
+    fn series(&self) -> Vec<String> {
+        match self {
+            Gpio::Counter(counter) => [&counter.count_series, &counter.rate_series]
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
+            Gpio::State(state) => vec![state.series.clone()],
+        }
+    }
+}
+
+/// Pulses counted by a background thread, so that none is missed between two samples
+pub struct Counter {
+    pulses: Arc<Mutex<Pulses>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    factor: f64,
+    count_series: Option<String>,
+    rate_series: Option<String>,
+    /// Pulse count and time of the previous sample of each series
+    previous: HashMap<String, (u64, Instant)>,
+}
+
+#[derive(Default)]
+struct Pulses {
+    count: u64,
+    /// Error that stopped the counting
+    error: Option<String>,
+}
+
+impl Counter {
+    fn new(
+        events: LineEventHandle,
+        debounce: Duration,
+        factor: f64,
+        count_series: Option<String>,
+        rate_series: Option<String>,
+    ) -> Counter {
+        let pulses = Arc::new(Mutex::new(Pulses::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let pulses = pulses.clone();
+            let stop = stop.clone();
+            spawn(move || {
+                if let Err(e) = count(events, debounce, &pulses, &stop) {
+                    println!("Warning: GPIO pulse counting stopped: {e:#}");
+                    pulses.lock().unwrap().error = Some(format!("{e:#}"));
+                }
+            })
+        };
+        let now = Instant::now();
+        let previous = [&count_series, &rate_series]
+            .into_iter()
+            .flatten()
+            .map(|s| (s.clone(), (0, now)))
+            .collect();
+        Counter {
+            pulses,
+            stop,
+            thread: Some(thread),
+            factor,
+            count_series,
+            rate_series,
+            previous,
+        }
+    }
+
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        let count = {
+            let pulses = self.pulses.lock().unwrap();
+            if let Some(e) = &pulses.error {
+                bail!(anyhow!("GPIO pulse counting stopped: {e}"));
+            }
+            pulses.count
+        };
+        let (prev_count, prev_time) = self
+            .previous
+            .get_mut(series)
+            .ok_or_else(|| anyhow!("no series configured with name {series}"))?;
+        let now = Instant::now();
+        let pulses = (count - *prev_count) as f64 * self.factor;
+        let elapsed = (now - *prev_time).as_secs_f64();
+        *prev_count = count;
+        *prev_time = now;
+
+        if self.count_series.as_ref().is_some_and(|s| s == series) {
+            Ok(pulses)
+        } else if elapsed > 0.0 {
+            Ok(pulses / elapsed)
+        } else {
+            Ok(0.0)
+        }
+    }
+}
+
+impl Drop for Counter {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Counts the edges reported by the kernel until `stop` is set. Edges closer than `debounce` to
+/// the previous counted one are ignored.
+fn count(
+    mut events: LineEventHandle,
+    debounce: Duration,
+    pulses: &Mutex<Pulses>,
+    stop: &AtomicBool,
+) -> Result<()> {
+    let debounce = debounce.as_nanos() as u64;
+    let mut last_edge: Option<u64> = None;
+    while !stop.load(Ordering::Relaxed) {
+        // Wake up regularly to check whether the sensor has been dropped
+        let mut fds = [PollFd::new(events.as_raw_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, 500) {
+            Ok(0) | Err(Errno::EINTR) => continue,
+            Ok(_) => {}
+            Err(e) => return Err(e).context("Cannot wait for GPIO events"),
+        }
+        let event = events.get_event().context("Cannot read GPIO event")?;
+        let t = event.timestamp();
+        if last_edge.is_some_and(|last| t.saturating_sub(last) < debounce) {
+            continue;
+        }
+        last_edge = Some(t);
+        pulses.lock().unwrap().count += 1;
+    }
+    Ok(())
+}
+
+/// Level of the line
+pub struct State {
+    handle: LineHandle,
+    debounce: Duration,
+    series: String,
+}
+
+impl State {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        if series != self.series {
+            return Err(anyhow!("no series configured with name {series}"));
+        }
+        let read = || self.handle.get_value().context("Cannot read GPIO line");
+        let mut value = read()?;
+        // The level must be stable for the debounce duration
+        if !self.debounce.is_zero() {
+            for _ in 0..10 {
+                sleep(self.debounce);
+                let next = read()?;
+                if next == value {
+                    return Ok(value as f64);
+                }
+                value = next;
+            }
+            bail!(anyhow!(
+                "GPIO line level not stable for {}",
+                humantime::format_duration(self.debounce)
+            ));
+        }
+        Ok(value as f64)
+    }
+}
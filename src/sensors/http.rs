@@ -0,0 +1,242 @@
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::{HeaderMap, AGE, CACHE_CONTROL, ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
+
+/// HTTP client shared by all the sensors, so that connections are reused. Request timeouts are
+/// set per sensor.
+pub fn client() -> &'static Client {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        Client::builder()
+            .connect_timeout(Duration::from_secs(10))
+            .build()
+            .expect("Cannot create HTTP client")
+    })
+}
+
+/// Last response of an API, reused while it is fresh according to its Cache-Control header,
+/// revalidated with its ETag, and refetched at most once every `min_interval`.
+pub struct CachedResponse {
+    min_interval: Duration,
+    body: Option<String>,
+    /// Time the current body was first received, as a UNIX timestamp
+    received_at: Option<u64>,
+    etag: Option<String>,
+    expires_at: Option<Instant>,
+    last_call: Option<Instant>,
+}
+
+impl CachedResponse {
+    pub fn new(min_interval: Duration) -> CachedResponse {
+        CachedResponse {
+            min_interval,
+            body: None,
+            received_at: None,
+            etag: None,
+            expires_at: None,
+            last_call: None,
+        }
+    }
+
+    /// Returns the body of the response, sending the request only when the cached body is
+    /// stale and the rate limit allows it.
+    pub fn get(&mut self, request: RequestBuilder) -> Result<&str> {
+        let now = Instant::now();
+        let fresh = self.expires_at.is_some_and(|t| now < t);
+        let next_call = self.last_call.map(|t| t + self.min_interval);
+        let limited = next_call.is_some_and(|t| now < t);
+        if self.body.is_some() && (fresh || limited) {
+            return Ok(self.body.as_deref().unwrap_or_default());
+        }
+        if let (true, Some(t)) = (limited, next_call) {
+            bail!(anyhow!(
+                "rate limited, next call allowed in {}",
+                humantime::format_duration(Duration::from_secs((t - now).as_secs()))
+            ));
+        }
+
+        let request = match (&self.body, &self.etag) {
+            (Some(_), Some(etag)) => request.header(IF_NONE_MATCH, etag),
+            _ => request,
+        };
+        self.last_call = Some(now);
+        let resp = request.send()?;
+        let status = resp.status();
+        if status == StatusCode::NOT_MODIFIED && self.body.is_some() {
+            self.expires_at = expiry(resp.headers(), now);
+        } else if status.is_success() {
+            let headers = resp.headers().clone();
+            let body = resp.text().context("Cannot read response body")?;
+            self.etag = headers
+                .get(ETAG)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned);
+            self.expires_at = expiry(&headers, now);
+            // An unchanged body keeps its original reception time
+            if self.body.as_ref() != Some(&body) {
+                self.received_at = Some(
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs(),
+                );
+                self.body = Some(body);
+            }
+        } else {
+            bail!(anyhow!("received an error response: {}", status));
+        }
+        Ok(self.body.as_deref().unwrap_or_default())
+    }
+
+    /// Time the body returned by `get` was received, as a UNIX timestamp
+    pub fn received_at(&self) -> Option<u64> {
+        self.received_at
+    }
+}
+
+/// Expiry time of a response, from the max-age directive of its Cache-Control header minus its
+/// Age header.
+fn expiry(headers: &HeaderMap, received_at: Instant) -> Option<Instant> {
+    let cache_control = headers.get(CACHE_CONTROL)?.to_str().ok()?;
+    let mut max_age = None;
+    for directive in cache_control.split(',').map(str::trim) {
+        if directive.eq_ignore_ascii_case("no-cache") || directive.eq_ignore_ascii_case("no-store")
+        {
+            return None;
+        }
+        if let Some((name, value)) = directive.split_once('=') {
+            if name.trim().eq_ignore_ascii_case("max-age") {
+                max_age = value.trim().trim_matches('"').parse::<u64>().ok();
+            }
+        }
+    }
+    let age = headers
+        .get(AGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    Some(received_at + Duration::from_secs(max_age?.saturating_sub(age)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread::{spawn, JoinHandle};
+
+    fn headers(cache_control: &str, age: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CACHE_CONTROL, HeaderValue::from_str(cache_control).unwrap());
+        if let Some(age) = age {
+            headers.insert(AGE, HeaderValue::from_str(age).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn expiry_time() {
+        let now = Instant::now();
+        let after = |secs| Some(now + Duration::from_secs(secs));
+        assert_eq!(expiry(&headers("max-age=60", None), now), after(60));
+        assert_eq!(
+            expiry(&headers("public, max-age=\"30\"", None), now),
+            after(30)
+        );
+        assert_eq!(
+            expiry(&headers("max-age=600", Some("120")), now),
+            after(480)
+        );
+        assert_eq!(expiry(&headers("max-age=60", Some("90")), now), after(0));
+        assert_eq!(expiry(&headers("max-age=60, no-cache", None), now), None);
+        assert_eq!(expiry(&headers("No-Store", None), now), None);
+        assert_eq!(expiry(&headers("private", None), now), None);
+        assert_eq!(expiry(&HeaderMap::new(), now), None);
+    }
+
+    /// Server answering `count` requests on a local port, one connection each
+    fn server(
+        count: usize,
+        respond: impl Fn(usize, &str) -> String + Send + 'static,
+    ) -> (u16, JoinHandle<Result<()>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let thread = spawn(move || {
+            for i in 0..count {
+                let (mut stream, _) = listener.accept()?;
+                stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+                let mut request = vec![];
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let len = stream.read(&mut buf)?;
+                    if len == 0 {
+                        bail!(anyhow!("connection closed before the end of the request"));
+                    }
+                    request.extend(&buf[..len]);
+                }
+                let response = respond(i, &String::from_utf8_lossy(&request).to_lowercase());
+                stream.write_all(response.as_bytes())?;
+            }
+            Ok(())
+        });
+        (port, thread)
+    }
+
+    fn response(status: &str, headers: &[&str], body: &str) -> String {
+        let mut response = format!("HTTP/1.1 {status}\r\nconnection: close\r\n");
+        for header in headers {
+            response.push_str(&format!("{header}\r\n"));
+        }
+        response.push_str(&format!("content-length: {}\r\n\r\n{body}", body.len()));
+        response
+    }
+
+    #[test]
+    fn revalidation() {
+        let (port, thread) = server(2, |i, request| match i {
+            0 => {
+                assert!(!request.contains("if-none-match"));
+                response(
+                    "200 OK",
+                    &["etag: \"v1\"", "cache-control: no-cache"],
+                    "hello",
+                )
+            }
+            _ => {
+                assert!(request.contains("if-none-match: \"v1\"\r\n"));
+                response("304 Not Modified", &["cache-control: max-age=60"], "")
+            }
+        });
+        let url = format!("http://127.0.0.1:{port}/data");
+        let mut cache = CachedResponse::new(Duration::ZERO);
+        assert_eq!(cache.get(client().get(&url)).unwrap(), "hello");
+        let received_at = cache.received_at();
+        assert!(received_at.is_some());
+        // Not modified, then fresh for a minute without any request
+        assert_eq!(cache.get(client().get(&url)).unwrap(), "hello");
+        assert_eq!(cache.get(client().get(&url)).unwrap(), "hello");
+        assert_eq!(cache.received_at(), received_at);
+        thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn rate_limit() {
+        let (port, thread) = server(1, |_, _| response("429 Too Many Requests", &[], ""));
+        let url = format!("http://127.0.0.1:{port}/data");
+        let mut cache = CachedResponse::new(Duration::from_secs(600));
+        assert_eq!(
+            cache.get(client().get(&url)).unwrap_err().to_string(),
+            "received an error response: 429 Too Many Requests"
+        );
+        // Without any body to return, the next call fails before sending the request
+        let error = cache.get(client().get(&url)).unwrap_err().to_string();
+        assert!(error.starts_with("rate limited, next call allowed in 9m"));
+        assert_eq!(cache.received_at(), None);
+        thread.join().unwrap().unwrap();
+    }
+}
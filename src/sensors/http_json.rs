@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::config::{HttpAuth, HttpJsonConfig, HttpMethod};
+use crate::sensors::cache::BatchCache;
+use crate::sensors::http;
+use crate::sensors::Sensor;
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+
+/// Values extracted from the JSON response of an HTTP API
+pub struct HttpJson {
+    config: HttpJsonConfig,
+    url: String,
+    timeout: Duration,
+    cache: BatchCache,
+}
+
+impl HttpJson {
+    pub fn new(config: HttpJsonConfig) -> Result<HttpJson> {
+        let timeout = humantime::parse_duration(&config.timeout)?;
+        let url = expand_url(&config.url, &config.params)?;
+        reqwest::Url::parse(&url).with_context(|| format!("Invalid URL {url}"))?;
+        for (series, field) in &config.series {
+            if !field.pointer.is_empty() && !field.pointer.starts_with('/') {
+                bail!(anyhow!(
+                    "the JSON pointer of \"{series}\" series must start with '/', got \"{}\"",
+                    field.pointer
+                ));
+            }
+        }
+        Ok(HttpJson {
+            config,
+            url,
+            timeout,
+            cache: BatchCache::new(Duration::from_secs(5)),
+        })
+    }
+
+    fn fetch(&self) -> Result<Value> {
+        let mut request = match self.config.method {
+            HttpMethod::Get => http::client().get(&self.url),
+            HttpMethod::Post => http::client().post(&self.url),
+        }
+        .timeout(self.timeout);
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+        if let Some(body) = &self.config.body {
+            request = request.body(body.clone());
+        }
+        request = match &self.config.auth {
+            None => request,
+            Some(HttpAuth::Basic { username, password }) => {
+                request.basic_auth(username, password.as_ref())
+            }
+            Some(HttpAuth::Bearer { token }) => request.bearer_auth(token),
+        };
+        let resp = request
+            .send()
+            .with_context(|| format!("Request to {} failed", self.config.url))?;
+        let status = resp.status();
+        if !status.is_success() {
+            bail!(anyhow!("received an error response: {}", status));
+        }
+        Ok(resp.json()?)
+    }
+
+    /// Extracts the value of every series from the response.
+    fn extract(&self, json: &Value) -> HashMap<String, f64> {
+        let mut values = HashMap::new();
+        for (series, field) in &self.config.series {
+            match json.pointer(&field.pointer).and_then(to_number) {
+                Some(value) => {
+                    values.insert(series.clone(), value * field.scale + field.offset);
+                }
+                None => println!(
+                    "Warning: no number at {} in {} response",
+                    field.pointer, self.config.url
+                ),
+            }
+        }
+        values
+    }
+}
+
+impl Sensor for HttpJson {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        if !self.config.series.contains_key(series) {
+            return Err(anyhow!("no series configured with name {series}"));
+        }
+        if let Some(value) = self.cache.take(series) {
+            return Ok(value);
+        }
+        let values = self.extract(&self.fetch()?);
+        self.cache.store(values);
+        self.cache
+            .take(series)
+            .ok_or_else(|| anyhow!("no value for {series} in {} response", self.config.url))
+    }
+
+    fn series(&self) -> Vec<String> {
+        self.config.series.keys().cloned().collect()
+    }
+}
+
+/// Replaces the `{name}` placeholders of the URL by their parameter value.
+fn expand_url(template: &str, params: &HashMap<String, String>) -> Result<String> {
+    let mut url = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("unclosed placeholder in URL {template}"))?;
+        let name = &rest[start + 1..start + end];
+        let value = params
+            .get(name)
+            .ok_or_else(|| anyhow!("no parameter for {{{name}}} placeholder in URL {template}"))?;
+        url.push_str(&rest[..start]);
+        url.push_str(value);
+        rest = &rest[start + end + 1..];
+    }
+    url.push_str(rest);
+    Ok(url)
+}
+
+fn to_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn url_template() {
+        let params = HashMap::from([
+            ("station".to_owned(), "LFPG".to_owned()),
+            ("key".to_owned(), "s3cr3t".to_owned()),
+        ]);
+        assert_eq!(
+            expand_url("https://api.example.com/{station}/obs?key={key}", &params).unwrap(),
+            "https://api.example.com/LFPG/obs?key=s3cr3t"
+        );
+        assert_eq!(
+            expand_url("https://api.example.com/latest", &params).unwrap(),
+            "https://api.example.com/latest"
+        );
+        assert_eq!(
+            expand_url("https://api.example.com/{sensor}", &params)
+                .unwrap_err()
+                .to_string(),
+            "no parameter for {sensor} placeholder in URL https://api.example.com/{sensor}"
+        );
+        assert_eq!(
+            expand_url("https://api.example.com/{station", &params)
+                .unwrap_err()
+                .to_string(),
+            "unclosed placeholder in URL https://api.example.com/{station"
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(to_number(&json!(21.5)), Some(21.5));
+        assert_eq!(to_number(&json!(-3)), Some(-3.0));
+        assert_eq!(to_number(&json!(" 1013.2 ")), Some(1013.2));
+        assert_eq!(to_number(&json!(true)), Some(1.0));
+        assert_eq!(to_number(&json!(false)), Some(0.0));
+        assert_eq!(to_number(&json!("n/a")), None);
+        assert_eq!(to_number(&json!(null)), None);
+        assert_eq!(to_number(&json!([1])), None);
+    }
+
+    #[test]
+    fn extraction() {
+        let config: HttpJsonConfig = serde_json::from_value(json!({
+            "url": "http://127.0.0.1/{path}",
+            "params": {"path": "status"},
+            "series": {
+                "temperature": {"pointer": "/sensors/0/temp", "scale": 0.1, "offset": -2.0},
+                "pressure": {"pointer": "/pressure"},
+                "online": {"pointer": "/online"},
+                "missing": {"pointer": "/sensors/1/temp"}
+            }
+        }))
+        .unwrap();
+        let sensor = HttpJson::new(config).unwrap();
+        assert_eq!(sensor.url, "http://127.0.0.1/status");
+        let response = json!({
+            "sensors": [{"temp": 215}],
+            "pressure": "1013.5",
+            "online": true
+        });
+        assert_eq!(
+            sensor.extract(&response),
+            HashMap::from([
+                ("temperature".to_owned(), 19.5),
+                ("pressure".to_owned(), 1013.5),
+                ("online".to_owned(), 1.0),
+            ])
+        );
+
+        let config: HttpJsonConfig = serde_json::from_value(json!({
+            "url": "http://127.0.0.1/status",
+            "series": {"temperature": {"pointer": "sensors/0/temp"}}
+        }))
+        .unwrap();
+        assert!(HttpJson::new(config).is_err());
+    }
+}
@@ -0,0 +1,330 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use regex::Regex;
+
+use crate::config::MetarConfig;
+use crate::sensors::cache::BatchCache;
+use crate::sensors::http::{self, CachedResponse};
+use crate::sensors::weather::{self, WeatherField, WeatherValues};
+use crate::sensors::Sensor;
+
+const FIELDS: [WeatherField; 9] = [
+    WeatherField::Temperature,
+    WeatherField::DewPoint,
+    WeatherField::Humidity,
+    WeatherField::Pressure,
+    WeatherField::WindSpeed,
+    WeatherField::WindDirection,
+    WeatherField::WindGust,
+    WeatherField::Clouds,
+    WeatherField::Visibility,
+];
+
+/// Weather observed at an airport, from its METAR reports
+pub struct Metar {
+    config: MetarConfig,
+    timeout: Duration,
+    parser: Parser,
+    response: CachedResponse,
+    cache: BatchCache,
+}
+
+impl Metar {
+    pub fn new(cfg: MetarConfig) -> Result<Metar> {
+        weather::check_fields(&cfg.weather, &FIELDS, "METAR")?;
+        let timeout = humantime::parse_duration(&cfg.timeout)?;
+        if cfg.max_calls_per_day == 0 {
+            bail!(anyhow!("max_calls_per_day must be at least 1"));
+        }
+        let min_interval = Duration::from_secs(24 * 3600) / cfg.max_calls_per_day;
+        Ok(Metar {
+            config: cfg,
+            timeout,
+            parser: Parser::new(),
+            response: CachedResponse::new(min_interval),
+            cache: BatchCache::new(Duration::from_secs(5)),
+        })
+    }
+
+    fn fetch(&mut self) -> Result<WeatherValues> {
+        let url = format!("{}/metar", self.config.base_url.trim_end_matches('/'));
+        let request = http::client()
+            .get(url)
+            .query(&[("ids", self.config.station.as_str()), ("format", "raw")])
+            .timeout(self.timeout);
+        let body = self.response.get(request)?;
+        let report = body
+            .lines()
+            .map(str::trim)
+            .find(|l| !l.is_empty())
+            .ok_or_else(|| anyhow!("no METAR report for {} station", self.config.station))?;
+        self.parser
+            .parse(report)
+            .with_context(|| format!("Cannot parse METAR report '{report}'"))
+    }
+}
+
+impl Sensor for Metar {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        if !self.series().iter().any(|s| s == series) {
+            return Err(anyhow!("no series configured with name {series}"));
+        }
+        if let Some(value) = self.cache.take(series) {
+            return Ok(value);
+        }
+        let values = self.fetch()?;
+        self.cache
+            .store(weather::series_values(&self.config.weather, &values));
+        self.cache.take(series).ok_or_else(|| {
+            anyhow!(
+                "no value for {series} in the METAR report of {} station",
+                self.config.station
+            )
+        })
+    }
+
+    fn series(&self) -> Vec<String> {
+        weather::series(&self.config.weather)
+    }
+}
+
+const KNOT: f64 = 0.514444;
+const STATUTE_MILE: f64 = 1609.344;
+const INCH_OF_MERCURY: f64 = 33.8639;
+
+/// Parser of the main body of METAR reports, such as
+/// `METAR LFPG 181030Z 24012G25KT 200V260 9999 FEW030 BKN045 12/08 Q1013 NOSIG`
+struct Parser {
+    wind: Regex,
+    visibility: Regex,
+    visibility_sm: Regex,
+    clouds: Regex,
+    temperature: Regex,
+    pressure: Regex,
+}
+
+impl Parser {
+    fn new() -> Parser {
+        let re = |r| Regex::new(r).expect("invalid METAR regex");
+        Parser {
+            wind: re(r"^(\d{3}|VRB)(\d{2,3})(?:G(\d{2,3}))?(KT|MPS|KMH)$"),
+            visibility: re(r"^(\d{4})(?:NDV|N|NE|E|SE|S|SW|W|NW)?$"),
+            visibility_sm: re(r"^[PM]?(?:(\d+) )?(?:(\d+)/(\d+)|(\d+))SM$"),
+            clouds: re(r"^(FEW|SCT|BKN|OVC|VV)(\d{3}|///)"),
+            temperature: re(r"^(M?\d{2})/(M?\d{2})?$"),
+            pressure: re(r"^([QA])(\d{4})$"),
+        }
+    }
+
+    fn parse(&self, report: &str) -> Result<WeatherValues> {
+        let mut values = WeatherValues::new();
+        let mut clouds: Option<f64> = None;
+        let tokens: Vec<&str> = report
+            .trim_end_matches('=')
+            .split_whitespace()
+            .take_while(|t| !matches!(*t, "RMK" | "TEMPO" | "BECMG" | "NOSIG"))
+            .collect();
+        let mut i = 0;
+        while i < tokens.len() {
+            let token = tokens[i];
+            // Visibilities in statute miles may have a whole part, e.g. "1 1/2SM"
+            let pair = tokens.get(i + 1).map(|next| format!("{token} {next}"));
+            if let Some(c) = self.wind.captures(token) {
+                let unit = match &c[4] {
+                    "KT" => KNOT,
+                    "KMH" => 1.0 / 3.6,
+                    _ => 1.0,
+                };
+                let speed: f64 = c[2].parse()?;
+                values.insert(WeatherField::WindSpeed, speed * unit);
+                if let Some(gust) = c.get(3) {
+                    values.insert(WeatherField::WindGust, gust.as_str().parse::<f64>()? * unit);
+                }
+                // The direction is unknown when the wind is variable or calm
+                if &c[1] != "VRB" && speed > 0.0 {
+                    values.insert(WeatherField::WindDirection, c[1].parse()?);
+                }
+            } else if token == "CAVOK" {
+                values.insert(WeatherField::Visibility, 10000.0);
+                clouds = Some(clouds.unwrap_or(0.0));
+            } else if let Some(c) = self.visibility.captures(token) {
+                // Only the first visibility group is the prevailing visibility
+                // 9999 means 10 km or more
+                let meters: f64 = c[1].parse()?;
+                let meters = if meters >= 9999.0 { 10000.0 } else { meters };
+                values.entry(WeatherField::Visibility).or_insert(meters);
+            } else if let Some((c, whole_token)) = pair
+                .as_deref()
+                .and_then(|p| Some((self.visibility_sm.captures(p)?, true)))
+                .or_else(|| Some((self.visibility_sm.captures(token)?, false)))
+            {
+                let whole: f64 = c.get(1).map_or(Ok(0.0), |w| w.as_str().parse())?;
+                let miles = match (c.get(2), c.get(3), c.get(4)) {
+                    (Some(n), Some(d), _) => {
+                        let d: f64 = d.as_str().parse()?;
+                        if d == 0.0 {
+                            bail!(anyhow!("invalid visibility group '{}'", &c[0]));
+                        }
+                        n.as_str().parse::<f64>()? / d
+                    }
+                    (_, _, Some(m)) => m.as_str().parse()?,
+                    _ => 0.0,
+                };
+                values.insert(WeatherField::Visibility, (whole + miles) * STATUTE_MILE);
+                if whole_token {
+                    i += 1;
+                }
+            } else if let Some(c) = self.clouds.captures(token) {
+                // Middle of the okta range of each cloud cover
+                let cover = match &c[1] {
+                    "FEW" => 1.5 / 8.0,
+                    "SCT" => 3.5 / 8.0,
+                    "BKN" => 6.0 / 8.0,
+                    _ => 1.0,
+                } * 100.0;
+                clouds = Some(clouds.map_or(cover, |c| c.max(cover)));
+            } else if matches!(token, "SKC" | "CLR" | "NSC" | "NCD") {
+                clouds = Some(clouds.unwrap_or(0.0));
+            } else if let Some(c) = self.temperature.captures(token) {
+                let temperature = parse_temperature(&c[1])?;
+                values.insert(WeatherField::Temperature, temperature);
+                if let Some(dew_point) = c.get(2) {
+                    let dew_point = parse_temperature(dew_point.as_str())?;
+                    values.insert(WeatherField::DewPoint, dew_point);
+                    values.insert(
+                        WeatherField::Humidity,
+                        weather::relative_humidity(temperature, dew_point),
+                    );
+                }
+            } else if let Some(c) = self.pressure.captures(token) {
+                let value: f64 = c[2].parse()?;
+                let hpa = match &c[1] {
+                    "A" => value / 100.0 * INCH_OF_MERCURY,
+                    _ => value,
+                };
+                values.insert(WeatherField::Pressure, hpa);
+            }
+            i += 1;
+        }
+        if let Some(clouds) = clouds {
+            values.insert(WeatherField::Clouds, clouds);
+        }
+        if values.is_empty() {
+            bail!(anyhow!("no weather data found"));
+        }
+        Ok(values)
+    }
+}
+
+/// Parses a temperature in °C, negative ones being prefixed with "M"
+fn parse_temperature(raw: &str) -> Result<f64> {
+    let value: f64 = raw.trim_start_matches('M').parse()?;
+    Ok(if raw.starts_with('M') { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_values(report: &str, expected: &[(WeatherField, f64)]) {
+        let values = Parser::new().parse(report).unwrap();
+        for (field, value) in expected {
+            let actual = values
+                .get(field)
+                .unwrap_or_else(|| panic!("{field:?} missing in {report}"));
+            assert!(
+                (actual - value).abs() < 1e-6,
+                "{field:?} of {report}: {actual}"
+            );
+        }
+        assert_eq!(values.len(), expected.len(), "{report}: {values:?}");
+    }
+
+    #[test]
+    fn cavok_negative_temperatures() {
+        assert_values(
+            "METAR EFHK 181020Z 24012G25KT 200V260 CAVOK M05/M07 Q1013 NOSIG=",
+            &[
+                (WeatherField::WindSpeed, 12.0 * KNOT),
+                (WeatherField::WindGust, 25.0 * KNOT),
+                (WeatherField::WindDirection, 240.0),
+                (WeatherField::Visibility, 10000.0),
+                (WeatherField::Clouds, 0.0),
+                (WeatherField::Temperature, -5.0),
+                (WeatherField::DewPoint, -7.0),
+                (
+                    WeatherField::Humidity,
+                    weather::relative_humidity(-5.0, -7.0),
+                ),
+                (WeatherField::Pressure, 1013.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn auto_statute_miles() {
+        assert_values(
+            "METAR KJFK 181051Z AUTO VRB03KT 1 1/2SM BR BKN008 OVC015 12/11 A2992 RMK AO2 SLP132",
+            &[
+                (WeatherField::WindSpeed, 3.0 * KNOT),
+                (WeatherField::Visibility, 1.5 * STATUTE_MILE),
+                (WeatherField::Clouds, 100.0),
+                (WeatherField::Temperature, 12.0),
+                (WeatherField::DewPoint, 11.0),
+                (
+                    WeatherField::Humidity,
+                    weather::relative_humidity(12.0, 11.0),
+                ),
+                (WeatherField::Pressure, 29.92 * INCH_OF_MERCURY),
+            ],
+        );
+        assert_values(
+            "METAR KORD 181051Z 27015G25KT 10SM FEW035 SCT250 20/M02 A3001",
+            &[
+                (WeatherField::WindSpeed, 15.0 * KNOT),
+                (WeatherField::WindGust, 25.0 * KNOT),
+                (WeatherField::WindDirection, 270.0),
+                (WeatherField::Visibility, 10.0 * STATUTE_MILE),
+                (WeatherField::Clouds, 3.5 / 8.0 * 100.0),
+                (WeatherField::Temperature, 20.0),
+                (WeatherField::DewPoint, -2.0),
+                (
+                    WeatherField::Humidity,
+                    weather::relative_humidity(20.0, -2.0),
+                ),
+                (WeatherField::Pressure, 30.01 * INCH_OF_MERCURY),
+            ],
+        );
+    }
+
+    #[test]
+    fn prevailing_visibility() {
+        assert_values(
+            "METAR LFPG 181030Z 00000KT 4000 1500NE FEW030 12/08 Q1020 TEMPO 2000",
+            &[
+                (WeatherField::WindSpeed, 0.0),
+                (WeatherField::Visibility, 4000.0),
+                (WeatherField::Clouds, 1.5 / 8.0 * 100.0),
+                (WeatherField::Temperature, 12.0),
+                (WeatherField::DewPoint, 8.0),
+                (
+                    WeatherField::Humidity,
+                    weather::relative_humidity(12.0, 8.0),
+                ),
+                (WeatherField::Pressure, 1020.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn invalid_reports() {
+        let parser = Parser::new();
+        assert!(parser.parse("METAR LFPG 181030Z NIL=").is_err());
+        assert!(parser.parse("").is_err());
+        let err = parser
+            .parse("METAR KJFK 181051Z 27015KT 1/0SM 12/11 A2992")
+            .unwrap_err();
+        assert!(err.to_string().contains("1/0SM"), "{err}");
+    }
+}
@@ -0,0 +1,607 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use nix::sys::termios::BaudRate;
+
+use crate::config::{
+    ModbusBaudRate, ModbusConfig, ModbusDataType, ModbusParity, ModbusRegister, ModbusRegisterType,
+    ModbusTransport, ModbusWordOrder,
+};
+use crate::sensors::cache::BatchCache;
+use crate::sensors::serial::{Parity, SerialPort};
+use crate::sensors::Sensor;
+
+/// Maximum number of registers read by a single request
+const MAX_REGISTERS: u16 = 125;
+
+/// Device read over Modbus. The registers of all the series are read in a single cycle, adjacent
+/// ones being grouped into a single request.
+pub struct Modbus {
+    transport: Transport,
+    unit_id: u8,
+    timeout: Duration,
+    series: HashMap<String, ModbusRegister>,
+    blocks: Vec<Block>,
+    cache: BatchCache,
+}
+
+/// Consecutive registers read by a single request
+struct Block {
+    register_type: ModbusRegisterType,
+    address: u16,
+    count: u16,
+    series: Vec<String>,
+}
+
+impl Modbus {
+    pub fn new(config: ModbusConfig) -> Result<Modbus> {
+        let timeout = humantime::parse_duration(&config.timeout)?;
+        for (series, register) in &config.series {
+            if register
+                .address
+                .checked_add(register_count(register.data_type) - 1)
+                .is_none()
+            {
+                bail!(anyhow!(
+                    "the register of \"{series}\" series exceeds the address space"
+                ));
+            }
+        }
+        let transport = match config.transport {
+            ModbusTransport::Tcp { host, port } => Transport::Tcp {
+                address: format!("{host}:{port}"),
+                stream: None,
+                transaction_id: 0,
+            },
+            ModbusTransport::Rtu {
+                path,
+                baud_rate,
+                parity,
+            } => {
+                if !(1..=247).contains(&config.unit_id) {
+                    bail!(anyhow!(
+                        "Modbus RTU unit identifier must be between 1 and 247, got {}",
+                        config.unit_id
+                    ));
+                }
+                let (speed, bauds) = match baud_rate {
+                    ModbusBaudRate::B1200 => (BaudRate::B1200, 1200),
+                    ModbusBaudRate::B2400 => (BaudRate::B2400, 2400),
+                    ModbusBaudRate::B4800 => (BaudRate::B4800, 4800),
+                    ModbusBaudRate::B9600 => (BaudRate::B9600, 9600),
+                    ModbusBaudRate::B19200 => (BaudRate::B19200, 19200),
+                    ModbusBaudRate::B38400 => (BaudRate::B38400, 38400),
+                    ModbusBaudRate::B57600 => (BaudRate::B57600, 57600),
+                    ModbusBaudRate::B115200 => (BaudRate::B115200, 115200),
+                };
+                let parity = match parity {
+                    ModbusParity::None => Parity::None,
+                    ModbusParity::Even => Parity::Even,
+                    ModbusParity::Odd => Parity::Odd,
+                };
+                // Frames are separated by 3.5 characters of 11 bits, or 1.75ms above 19200 bauds
+                let frame_gap = if bauds > 19200 {
+                    Duration::from_micros(1750)
+                } else {
+                    Duration::from_secs(1) * 35 * 11 / (10 * bauds)
+                };
+                Transport::Rtu {
+                    port: SerialPort::open(&path, speed, parity)?,
+                    frame_gap,
+                }
+            }
+        };
+        Ok(Modbus {
+            transport,
+            unit_id: config.unit_id,
+            timeout,
+            blocks: blocks(&config.series),
+            series: config.series,
+            cache: BatchCache::new(Duration::from_secs(5)),
+        })
+    }
+}
+
+impl Sensor for Modbus {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        if !self.series.contains_key(series) {
+            return Err(anyhow!("no series configured with name {series}"));
+        }
+        if let Some(value) = self.cache.take(series) {
+            return Ok(value);
+        }
+        let mut values = HashMap::new();
+        for block in &self.blocks {
+            let registers = self
+                .transport
+                .read_registers(self.unit_id, block, self.timeout)
+                .map_err(|e| {
+                    anyhow!(
+                        "Cannot read {} {} registers from address {}: {e:#}",
+                        block.count,
+                        match block.register_type {
+                            ModbusRegisterType::Holding => "holding",
+                            ModbusRegisterType::Input => "input",
+                        },
+                        block.address
+                    )
+                })?;
+            for name in &block.series {
+                let register = &self.series[name];
+                let start = (register.address - block.address) as usize;
+                values.insert(name.clone(), decode(register, &registers[start..]));
+            }
+        }
+        self.cache.store(values);
+        self.cache
+            .take(series)
+            .ok_or_else(|| anyhow!("no value for {series}"))
+    }
+
+    fn series(&self) -> Vec<String> {
+        self.series.keys().cloned().collect()
+    }
+}
+
+/// Groups the registers of the series into blocks of consecutive registers.
+fn blocks(series: &HashMap<String, ModbusRegister>) -> Vec<Block> {
+    let mut registers: Vec<_> = series.iter().collect();
+    registers.sort_by_key(|(_, r)| (r.register_type == ModbusRegisterType::Input, r.address));
+    let mut blocks: Vec<Block> = vec![];
+    for (name, register) in registers {
+        let count = register_count(register.data_type);
+        // Computed on 32 bits, the last register being at the end of the address space
+        let end = register.address as u32 + count as u32;
+        match blocks.last_mut() {
+            Some(block)
+                if block.register_type == register.register_type
+                    && register.address as u32 <= block.address as u32 + block.count as u32
+                    && end - block.address as u32 <= MAX_REGISTERS as u32 =>
+            {
+                block.count = block.count.max((end - block.address as u32) as u16);
+                block.series.push(name.clone());
+            }
+            _ => blocks.push(Block {
+                register_type: register.register_type,
+                address: register.address,
+                count,
+                series: vec![name.clone()],
+            }),
+        }
+    }
+    blocks
+}
+
+fn register_count(data_type: ModbusDataType) -> u16 {
+    match data_type {
+        ModbusDataType::U16 | ModbusDataType::I16 => 1,
+        ModbusDataType::U32 | ModbusDataType::I32 | ModbusDataType::F32 => 2,
+    }
+}
+
+/// Converts the registers starting at the one of the series to its value.
+fn decode(register: &ModbusRegister, registers: &[u16]) -> f64 {
+    let double = || {
+        let (high, low) = match register.word_order {
+            ModbusWordOrder::HighWordFirst => (registers[0], registers[1]),
+            ModbusWordOrder::LowWordFirst => (registers[1], registers[0]),
+        };
+        (high as u32) << 16 | low as u32
+    };
+    let value = match register.data_type {
+        ModbusDataType::U16 => registers[0] as f64,
+        ModbusDataType::I16 => registers[0] as i16 as f64,
+        ModbusDataType::U32 => double() as f64,
+        ModbusDataType::I32 => double() as i32 as f64,
+        ModbusDataType::F32 => f32::from_bits(double()) as f64,
+    };
+    value * register.scale + register.offset
+}
+
+enum Transport {
+    Tcp {
+        address: String,
+        /// Connection, opened again after an error
+        stream: Option<TcpStream>,
+        transaction_id: u16,
+    },
+    Rtu {
+        port: SerialPort,
+        frame_gap: Duration,
+    },
+}
+
+impl Transport {
+    fn read_registers(
+        &mut self,
+        unit_id: u8,
+        block: &Block,
+        timeout: Duration,
+    ) -> Result<Vec<u16>> {
+        let function = match block.register_type {
+            ModbusRegisterType::Holding => 0x03,
+            ModbusRegisterType::Input => 0x04,
+        };
+        let [address_high, address_low] = block.address.to_be_bytes();
+        let [count_high, count_low] = block.count.to_be_bytes();
+        let request = [function, address_high, address_low, count_high, count_low];
+        let response = self.request(unit_id, &request, timeout)?;
+
+        if response.len() >= 2 && response[0] == function | 0x80 {
+            bail!(anyhow!("{}", exception(response[1])));
+        }
+        let len = 2 * block.count as usize;
+        if response.len() != 2 + len || response[0] != function || response[1] as usize != len {
+            bail!(anyhow!("unexpected response {response:02x?}"));
+        }
+        Ok(response[2..]
+            .chunks(2)
+            .map(|w| u16::from_be_bytes([w[0], w[1]]))
+            .collect())
+    }
+
+    /// Sends a request PDU and returns the response PDU.
+    fn request(&mut self, unit_id: u8, pdu: &[u8], timeout: Duration) -> Result<Vec<u8>> {
+        match self {
+            Transport::Tcp {
+                address,
+                stream,
+                transaction_id,
+            } => {
+                *transaction_id = transaction_id.wrapping_add(1);
+                let result = tcp_request(address, stream, *transaction_id, unit_id, pdu, timeout);
+                if result.is_err() {
+                    *stream = None;
+                }
+                result
+            }
+            Transport::Rtu { port, frame_gap } => {
+                rtu_request(port, *frame_gap, unit_id, pdu, timeout)
+            }
+        }
+    }
+}
+
+fn tcp_request(
+    address: &str,
+    stream: &mut Option<TcpStream>,
+    transaction_id: u16,
+    unit_id: u8,
+    pdu: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    let stream = match stream {
+        Some(stream) => stream,
+        None => stream.insert(connect(address, timeout)?),
+    };
+    let mut frame = transaction_id.to_be_bytes().to_vec();
+    frame.extend([0, 0]);
+    frame.extend((pdu.len() as u16 + 1).to_be_bytes());
+    frame.push(unit_id);
+    frame.extend(pdu);
+    stream
+        .write_all(&frame)
+        .with_context(|| format!("Cannot send request to {address}"))?;
+
+    let mut header = [0; 7];
+    stream
+        .read_exact(&mut header)
+        .with_context(|| format!("No response from {address}"))?;
+    let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+    if header[2..4] != [0, 0] || !(2..=254).contains(&len) {
+        bail!(anyhow!("invalid Modbus TCP header {header:02x?}"));
+    }
+    let mut response = vec![0; len - 1];
+    stream
+        .read_exact(&mut response)
+        .with_context(|| format!("Incomplete response from {address}"))?;
+    if u16::from_be_bytes([header[0], header[1]]) != transaction_id || header[6] != unit_id {
+        bail!(anyhow!("response does not match the request"));
+    }
+    Ok(response)
+}
+
+fn connect(address: &str, timeout: Duration) -> Result<TcpStream> {
+    let mut error = anyhow!("{address} does not resolve to any address");
+    for addr in address
+        .to_socket_addrs()
+        .with_context(|| format!("Cannot resolve {address}"))?
+    {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                stream.set_nodelay(true)?;
+                return Ok(stream);
+            }
+            Err(e) => error = anyhow!(e).context(format!("Cannot connect to {address}")),
+        }
+    }
+    Err(error)
+}
+
+fn rtu_request(
+    port: &mut SerialPort,
+    frame_gap: Duration,
+    unit_id: u8,
+    pdu: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    sleep(frame_gap);
+    port.discard_input()?;
+    let mut frame = vec![unit_id];
+    frame.extend(pdu);
+    frame.extend(crc(&frame).to_le_bytes());
+    port.write_all(&frame)?;
+
+    // Unit identifier and function code, then either an exception code or the byte count
+    let mut response = vec![0; 3];
+    port.read_exact(&mut response, timeout)?;
+    let rest = if response[1] & 0x80 != 0 {
+        2
+    } else {
+        response[2] as usize + 2
+    };
+    response.resize(3 + rest, 0);
+    port.read_exact(&mut response[3..], timeout)?;
+
+    let (data, checksum) = response.split_at(response.len() - 2);
+    if crc(data) != u16::from_le_bytes([checksum[0], checksum[1]]) {
+        bail!(anyhow!("CRC mismatch in response {response:02x?}"));
+    }
+    if data[0] != unit_id {
+        bail!(anyhow!(
+            "response from unit {} instead of {unit_id}",
+            data[0]
+        ));
+    }
+    Ok(data[1..].to_vec())
+}
+
+fn exception(code: u8) -> String {
+    let description = match code {
+        0x01 => "illegal function",
+        0x02 => "illegal data address",
+        0x03 => "illegal data value",
+        0x04 => "server device failure",
+        0x05 => "acknowledge",
+        0x06 => "server device busy",
+        0x0A => "gateway path unavailable",
+        0x0B => "gateway target device failed to respond",
+        _ => "unknown exception",
+    };
+    format!("Modbus exception {code:#04x}: {description}")
+}
+
+/// CRC-16/MODBUS of a frame
+pub fn crc(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF;
+    for b in data {
+        crc ^= *b as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread::{spawn, JoinHandle};
+
+    fn register(
+        address: u16,
+        register_type: ModbusRegisterType,
+        data_type: ModbusDataType,
+    ) -> ModbusRegister {
+        ModbusRegister {
+            address,
+            register_type,
+            data_type,
+            word_order: ModbusWordOrder::HighWordFirst,
+            scale: 1.0,
+            offset: 0.0,
+        }
+    }
+
+    /// Blocks as (input registers, address, count, series)
+    fn layout(registers: &[(&str, ModbusRegister)]) -> Vec<(bool, u16, u16, Vec<String>)> {
+        let series = registers
+            .iter()
+            .map(|(name, register)| (name.to_string(), register.clone()))
+            .collect();
+        blocks(&series)
+            .into_iter()
+            .map(|b| {
+                let input = b.register_type == ModbusRegisterType::Input;
+                (input, b.address, b.count, b.series)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn register_blocks() {
+        use ModbusDataType::*;
+        use ModbusRegisterType::*;
+        let registers = [
+            ("voltage", register(0, Holding, U16)),
+            ("energy", register(1, Holding, U32)),
+            ("power", register(3, Holding, I16)),
+            ("frequency", register(10, Holding, F32)),
+            ("current", register(4, Input, U16)),
+            ("state", register(0, Input, U16)),
+        ];
+        assert_eq!(
+            layout(&registers),
+            [
+                (
+                    false,
+                    0,
+                    4,
+                    vec!["voltage".into(), "energy".into(), "power".into()]
+                ),
+                (false, 10, 2, vec!["frequency".into()]),
+                (true, 0, 1, vec!["state".into()]),
+                (true, 4, 1, vec!["current".into()]),
+            ]
+        );
+
+        // Overlapping registers
+        let registers = [
+            ("total", register(20, Holding, U32)),
+            ("low", register(21, Holding, U16)),
+        ];
+        assert_eq!(
+            layout(&registers),
+            [(false, 20, 2, vec!["total".into(), "low".into()])]
+        );
+
+        // A block is limited to MAX_REGISTERS, a double register not being split
+        let names: Vec<String> = (0..124).map(|i| format!("r{i:03}")).collect();
+        let mut registers: Vec<_> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), register(200 + i as u16, Holding, U16)))
+            .collect();
+        registers.push(("last", register(324, Holding, U32)));
+        let split = layout(&registers);
+        assert_eq!(split.len(), 2);
+        assert_eq!((split[0].1, split[0].2, split[0].3.len()), (200, 124, 124));
+        assert_eq!(split[1], (false, 324, 2, vec!["last".into()]));
+        registers.pop();
+        registers.push(("last", register(324, Holding, U16)));
+        registers.push(("next", register(325, Holding, U16)));
+        let split = layout(&registers);
+        assert_eq!((split[0].1, split[0].2, split[0].3.len()), (200, 125, 125));
+        assert_eq!(split[1], (false, 325, 1, vec!["next".into()]));
+
+        // The last register of the address space
+        let registers = [("end", register(u16::MAX - 1, Holding, U32))];
+        assert_eq!(
+            layout(&registers),
+            [(false, u16::MAX - 1, 2, vec!["end".into()])]
+        );
+    }
+
+    #[test]
+    fn decode_values() {
+        use ModbusDataType::*;
+        let value = |data_type, word_order, registers: &[u16]| {
+            let mut register = register(0, ModbusRegisterType::Holding, data_type);
+            register.word_order = word_order;
+            decode(&register, registers)
+        };
+        let high = ModbusWordOrder::HighWordFirst;
+        let low = ModbusWordOrder::LowWordFirst;
+        assert_eq!(value(U16, high, &[0xFFFF]), 65535.0);
+        assert_eq!(value(I16, high, &[0xFFFF]), -1.0);
+        assert_eq!(value(U32, high, &[0x0001, 0x0002]), 65538.0);
+        assert_eq!(value(U32, low, &[0x0001, 0x0002]), 131073.0);
+        assert_eq!(value(U32, high, &[0xFFFF, 0xFFFE]), 4294967294.0);
+        assert_eq!(value(I32, high, &[0xFFFF, 0xFFFE]), -2.0);
+        assert_eq!(value(I32, low, &[0xFFFE, 0xFFFF]), -2.0);
+        assert_eq!(value(F32, high, &[0x4148, 0x0000]), 12.5);
+        assert_eq!(value(F32, low, &[0x0000, 0x4148]), 12.5);
+        assert_eq!(value(F32, high, &[0xC148, 0x0000]), -12.5);
+
+        let mut temperature = register(0, ModbusRegisterType::Input, I16);
+        temperature.scale = 0.5;
+        temperature.offset = -40.0;
+        assert_eq!(decode(&temperature, &[100]), 10.0);
+        assert_eq!(decode(&temperature, &[(-20i16) as u16]), -50.0);
+    }
+
+    /// Server answering `count` requests over a single connection on a local port
+    fn server(
+        count: usize,
+        respond: impl Fn(&[u8]) -> Vec<u8> + Send + 'static,
+    ) -> (u16, JoinHandle<Result<()>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let thread = spawn(move || {
+            let (mut stream, _) = listener.accept()?;
+            stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+            for _ in 0..count {
+                let mut request = vec![0; 7];
+                stream.read_exact(&mut request)?;
+                let len = u16::from_be_bytes([request[4], request[5]]) as usize;
+                request.resize(6 + len, 0);
+                stream.read_exact(&mut request[7..])?;
+                stream.write_all(&respond(&request))?;
+            }
+            Ok(())
+        });
+        (port, thread)
+    }
+
+    #[test]
+    fn tcp() {
+        let (port, thread) = server(3, |request| {
+            let transaction = u16::from_be_bytes([request[0], request[1]]);
+            let pdu = match transaction {
+                1 => {
+                    assert_eq!(request[2..], [0, 0, 0, 6, 17, 0x03, 0x00, 0x10, 0x00, 0x02]);
+                    vec![0x03, 0x04, 0x00, 0x2A, 0xFF, 0xFF]
+                }
+                2 => vec![0x83, 0x02],
+                _ => vec![0x03, 0x04, 0x00, 0x2A, 0xFF, 0xFF],
+            };
+            // The third response answers another transaction
+            let answered = if transaction == 3 { 4 } else { transaction };
+            let mut response = answered.to_be_bytes().to_vec();
+            response.extend([0, 0]);
+            response.extend((pdu.len() as u16 + 1).to_be_bytes());
+            response.push(request[6]);
+            response.extend(pdu);
+            response
+        });
+        let mut transport = Transport::Tcp {
+            address: format!("127.0.0.1:{port}"),
+            stream: None,
+            transaction_id: 0,
+        };
+        let block = Block {
+            register_type: ModbusRegisterType::Holding,
+            address: 0x10,
+            count: 2,
+            series: vec![],
+        };
+        let timeout = Duration::from_secs(2);
+        assert_eq!(
+            transport.read_registers(17, &block, timeout).unwrap(),
+            [42, 0xFFFF]
+        );
+        assert_eq!(
+            transport
+                .read_registers(17, &block, timeout)
+                .unwrap_err()
+                .to_string(),
+            "Modbus exception 0x02: illegal data address"
+        );
+        assert_eq!(
+            transport
+                .read_registers(17, &block, timeout)
+                .unwrap_err()
+                .to_string(),
+            "response does not match the request"
+        );
+        // The connection is dropped after a transport error
+        assert!(matches!(transport, Transport::Tcp { stream: None, .. }));
+        thread.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn crc_vector() {
+        assert_eq!(crc(b"123456789"), 0x4B37);
+    }
+}
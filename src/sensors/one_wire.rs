@@ -0,0 +1,215 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+/// 1-Wire device families supported by the kernel drivers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    Ds18s20,
+    Ds1822,
+    Ds18b20,
+    Ds2438,
+    Ds2413,
+}
+
+impl Family {
+    /// Families handled by the w1_therm driver
+    pub const THERMOMETERS: [Family; 3] = [Family::Ds18s20, Family::Ds1822, Family::Ds18b20];
+
+    /// Family code, prefixing the device serial numbers
+    fn code(&self) -> &'static str {
+        match self {
+            Family::Ds18s20 => "10",
+            Family::Ds1822 => "22",
+            Family::Ds18b20 => "28",
+            Family::Ds2438 => "26",
+            Family::Ds2413 => "3a",
+        }
+    }
+
+    pub fn from_serial_number(serial_number: &str) -> Option<Family> {
+        let (code, _) = serial_number.split_once('-')?;
+        [
+            Family::Ds18s20,
+            Family::Ds1822,
+            Family::Ds18b20,
+            Family::Ds2438,
+            Family::Ds2413,
+        ]
+        .into_iter()
+        .find(|f| f.code().eq_ignore_ascii_case(code))
+    }
+}
+
+/// Checks a device serial number belongs to one of the expected families.
+pub fn check_family(serial_number: &str, expected: &[Family]) -> Result<Family> {
+    match Family::from_serial_number(serial_number) {
+        Some(family) if expected.contains(&family) => Ok(family),
+        Some(family) => bail!(anyhow!(
+            "{serial_number} is a {family:?} device, expected one of {expected:?}"
+        )),
+        None => bail!(anyhow!("{serial_number} is not a supported 1-Wire device")),
+    }
+}
+
+/// Lists the serial numbers of the devices of the given families, sorted.
+pub fn list_devices(w1_root: &str, families: &[Family]) -> std::io::Result<Vec<String>> {
+    let mut devices: Vec<String> = fs::read_dir(w1_root)?
+        .filter_map(|r| match r {
+            Err(_) => None,
+            Ok(d) => {
+                let name = d.file_name().to_string_lossy().to_string();
+                if Family::from_serial_number(&name).is_some_and(|f| families.contains(&f)) {
+                    Some(name)
+                } else {
+                    None
+                }
+            }
+        })
+        .collect();
+    devices.sort();
+    Ok(devices)
+}
+
+/// Reads a text attribute of a device.
+pub fn read_attribute(w1_root: &str, serial_number: &str, attribute: &str) -> Result<String> {
+    let path = Path::new(w1_root).join(serial_number).join(attribute);
+    fs::read_to_string(&path).with_context(|| format!("Failed to read file at {}", path.display()))
+}
+
+/// Writes a text attribute of a device.
+pub fn write_attribute(
+    w1_root: &str,
+    serial_number: &str,
+    attribute: &str,
+    value: &str,
+) -> Result<()> {
+    let path = Path::new(w1_root).join(serial_number).join(attribute);
+    fs::write(&path, value).with_context(|| format!("Failed to write file at {}", path.display()))
+}
+
+/// Reads the temperature of a w1_therm device, in °C.
+pub fn read_thermometer(w1_root: &str, serial_number: &str) -> Result<f64> {
+    let content = read_attribute(w1_root, serial_number, "w1_slave")?;
+    parse_w1_slave(&content)
+}
+
+/// Parses the content of a w1_therm `w1_slave` file, such as:
+/// ```text
+/// 72 01 4b 46 7f ff 0e 10 57 : crc=57 YES
+/// 72 01 4b 46 7f ff 0e 10 57 t=23125
+/// ```
+pub fn parse_w1_slave(content: &str) -> Result<f64> {
+    let mut lines = content.lines();
+    let (crc_line, data_line) = match (lines.next(), lines.next()) {
+        (Some(crc), Some(data)) => (crc, data),
+        _ => bail!(anyhow!(
+            "Malformed w1_slave content '{}'",
+            content.escape_debug()
+        )),
+    };
+    match crc_line.split_whitespace().last() {
+        Some("YES") => {}
+        Some("NO") => bail!(anyhow!("CRC check failed: '{}'", crc_line)),
+        _ => bail!(anyhow!("Malformed w1_slave CRC line '{}'", crc_line)),
+    }
+    let raw = data_line
+        .rsplit_once("t=")
+        .map(|(_, t)| t.trim())
+        .ok_or_else(|| anyhow!("Malformed w1_slave data line '{}'", data_line))?;
+    let millis: i32 = raw
+        .parse()
+        .with_context(|| format!("Failed to parse temperature from '{}'", raw.escape_debug()))?;
+    Ok(millis as f64 / 1000.0)
+}
+
+/// Parses an attribute holding a single integer.
+pub fn parse_int(content: &str) -> Result<i64> {
+    content.trim().parse().with_context(|| {
+        format!(
+            "Failed to parse integer from file content '{}'",
+            content.escape_debug()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("raspi-one-wire-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn w1_slave() {
+        let content =
+            "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        assert_eq!(parse_w1_slave(content).unwrap(), 23.125);
+        let negative =
+            "5e ff 4b 46 7f ff 02 10 c1 : crc=c1 YES\n5e ff 4b 46 7f ff 02 10 c1 t=-10125\n";
+        assert_eq!(parse_w1_slave(negative).unwrap(), -10.125);
+        // The power-on value has a valid CRC, and is indistinguishable from an actual 85 °C
+        let power_on =
+            "50 05 4b 46 7f ff 0c 10 1c : crc=1c YES\n50 05 4b 46 7f ff 0c 10 1c t=85000\n";
+        assert_eq!(parse_w1_slave(power_on).unwrap(), 85.0);
+
+        let crc_error =
+            "72 01 4b 46 7f ff 0e 10 57 : crc=00 NO\n72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        assert!(parse_w1_slave(crc_error)
+            .unwrap_err()
+            .to_string()
+            .starts_with("CRC check failed"));
+        let no_temperature =
+            "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n72 01 4b 46 7f ff 0e 10 57\n";
+        assert!(parse_w1_slave(no_temperature)
+            .unwrap_err()
+            .to_string()
+            .starts_with("Malformed w1_slave data line"));
+        assert!(parse_w1_slave("72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n").is_err());
+        assert!(parse_w1_slave("72 01 4b 46 7f ff 0e 10 57\nt=23125\n").is_err());
+    }
+
+    #[test]
+    fn families() {
+        assert_eq!(
+            check_family("28-0316a2790bff", &Family::THERMOMETERS).unwrap(),
+            Family::Ds18b20
+        );
+        assert_eq!(
+            check_family("10-000802b4c8e2", &Family::THERMOMETERS).unwrap(),
+            Family::Ds18s20
+        );
+        assert!(check_family("26-000000e8c5f3", &Family::THERMOMETERS).is_err());
+        assert!(check_family("w1_bus_master1", &Family::THERMOMETERS).is_err());
+
+        let dir = test_dir("families");
+        for name in [
+            "28-0316a2790bff",
+            "10-000802b4c8e2",
+            "28-0000075e1a3c",
+            "26-000000e8c5f3",
+            "w1_bus_master1",
+        ] {
+            fs::create_dir(dir.join(name)).unwrap();
+        }
+        let root = dir.to_str().unwrap();
+        assert_eq!(
+            list_devices(root, &[Family::Ds18b20]).unwrap(),
+            ["28-0000075e1a3c", "28-0316a2790bff"]
+        );
+        assert_eq!(
+            list_devices(root, &Family::THERMOMETERS).unwrap(),
+            ["10-000802b4c8e2", "28-0000075e1a3c", "28-0316a2790bff"]
+        );
+        assert!(list_devices(root, &[Family::Ds2413]).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(list_devices(root, &[Family::Ds18b20]).is_err());
+    }
+}
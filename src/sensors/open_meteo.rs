@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+
+use crate::config::OpenMeteoConfig;
+use crate::sensors::cache::BatchCache;
+use crate::sensors::http::{self, CachedResponse};
+use crate::sensors::weather::{self, WeatherField, WeatherValues};
+use crate::sensors::Sensor;
+
+const FIELDS: [WeatherField; 11] = [
+    WeatherField::Temperature,
+    WeatherField::FeelsLike,
+    WeatherField::DewPoint,
+    WeatherField::Humidity,
+    WeatherField::Pressure,
+    WeatherField::WindSpeed,
+    WeatherField::WindDirection,
+    WeatherField::WindGust,
+    WeatherField::Clouds,
+    WeatherField::Precipitation,
+    WeatherField::Visibility,
+];
+
+/// Current weather from the Open-Meteo API
+pub struct OpenMeteo {
+    config: OpenMeteoConfig,
+    timeout: Duration,
+    response: CachedResponse,
+    cache: BatchCache,
+}
+
+impl OpenMeteo {
+    pub fn new(cfg: OpenMeteoConfig) -> Result<OpenMeteo> {
+        weather::check_fields(&cfg.weather, &FIELDS, "Open-Meteo")?;
+        let timeout = humantime::parse_duration(&cfg.timeout)?;
+        if cfg.max_calls_per_day == 0 {
+            bail!(anyhow!("max_calls_per_day must be at least 1"));
+        }
+        let min_interval = Duration::from_secs(24 * 3600) / cfg.max_calls_per_day;
+        Ok(OpenMeteo {
+            config: cfg,
+            timeout,
+            response: CachedResponse::new(min_interval),
+            cache: BatchCache::new(Duration::from_secs(5)),
+        })
+    }
+
+    fn fetch(&mut self) -> Result<WeatherValues> {
+        let url = format!("{}/forecast", self.config.base_url.trim_end_matches('/'));
+        let request = http::client()
+            .get(url)
+            .query(&[
+                ("latitude", self.config.lat.to_string()),
+                ("longitude", self.config.lon.to_string()),
+                ("current", CURRENT_VARIABLES.to_owned()),
+                ("wind_speed_unit", "ms".to_owned()),
+            ])
+            .timeout(self.timeout);
+        let body = self.response.get(request)?;
+        parse(body)
+    }
+}
+
+impl Sensor for OpenMeteo {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        if !self.series().iter().any(|s| s == series) {
+            return Err(anyhow!("no series configured with name {series}"));
+        }
+        if let Some(value) = self.cache.take(series) {
+            return Ok(value);
+        }
+        let values = self.fetch()?;
+        self.cache
+            .store(weather::series_values(&self.config.weather, &values));
+        self.cache
+            .take(series)
+            .ok_or_else(|| anyhow!("no value for {series} in Open-Meteo response"))
+    }
+
+    fn series(&self) -> Vec<String> {
+        weather::series(&self.config.weather)
+    }
+}
+
+const CURRENT_VARIABLES: &str = "temperature_2m,apparent_temperature,dew_point_2m,\
+relative_humidity_2m,pressure_msl,wind_speed_10m,wind_direction_10m,wind_gust_10m,cloud_cover,\
+precipitation,visibility";
+
+/// Parses the response of the forecast endpoint, requested with the `CURRENT_VARIABLES` and
+/// wind speeds in m/s.
+fn parse(body: &str) -> Result<WeatherValues> {
+    let json: ForecastApi =
+        serde_json::from_str(body).context("Cannot parse Open-Meteo response")?;
+    let c = json.current;
+    // Precipitation is summed over the update interval (15 minutes), scale it to an hour
+    let precipitation = match c.interval {
+        Some(interval) if interval > 0 => c.precipitation.map(|p| p * 3600.0 / interval as f64),
+        _ => c.precipitation,
+    };
+    Ok([
+        (WeatherField::Temperature, c.temperature_2m),
+        (WeatherField::FeelsLike, c.apparent_temperature),
+        (WeatherField::DewPoint, c.dew_point_2m),
+        (WeatherField::Humidity, c.relative_humidity_2m),
+        (WeatherField::Pressure, c.pressure_msl),
+        (WeatherField::WindSpeed, c.wind_speed_10m),
+        (WeatherField::WindDirection, c.wind_direction_10m),
+        (WeatherField::WindGust, c.wind_gust_10m),
+        (WeatherField::Clouds, c.cloud_cover),
+        (WeatherField::Precipitation, precipitation),
+        (WeatherField::Visibility, c.visibility),
+    ]
+    .into_iter()
+    .filter_map(|(field, value)| Some((field, value?)))
+    .collect())
+}
+
+#[derive(Deserialize, Debug)]
+struct ForecastApi {
+    current: Current,
+}
+
+#[derive(Deserialize, Debug)]
+struct Current {
+    interval: Option<u64>,
+    temperature_2m: Option<f64>,
+    apparent_temperature: Option<f64>,
+    dew_point_2m: Option<f64>,
+    relative_humidity_2m: Option<f64>,
+    pressure_msl: Option<f64>,
+    wind_speed_10m: Option<f64>,
+    wind_direction_10m: Option<f64>,
+    wind_gust_10m: Option<f64>,
+    cloud_cover: Option<f64>,
+    precipitation: Option<f64>,
+    visibility: Option<f64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_current() {
+        let values = parse(include_str!("testdata/open_meteo_current.json")).unwrap();
+        let expected = [
+            (WeatherField::Temperature, 14.2),
+            (WeatherField::FeelsLike, 12.9),
+            (WeatherField::DewPoint, 9.8),
+            (WeatherField::Humidity, 75.0),
+            (WeatherField::Pressure, 1013.4),
+            (WeatherField::WindSpeed, 3.6),
+            (WeatherField::WindDirection, 243.0),
+            (WeatherField::WindGust, 8.2),
+            (WeatherField::Clouds, 88.0),
+            // 0.2 mm over 15 minutes
+            (WeatherField::Precipitation, 0.8),
+            (WeatherField::Visibility, 24140.0),
+        ];
+        assert_eq!(values.len(), expected.len());
+        for (field, value) in expected {
+            assert!(
+                (values[&field] - value).abs() < 1e-9,
+                "{field:?}: {}",
+                values[&field]
+            );
+        }
+    }
+
+    #[test]
+    fn parse_missing_values() {
+        let values = parse(r#"{"current": {"time": "2024-10-18T10:30", "temperature_2m": 14.2, "visibility": null}}"#).unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[&WeatherField::Temperature], 14.2);
+        assert!(
+            parse(r#"{"error": true, "reason": "Latitude must be in range of -90 to 90°."}"#)
+                .is_err()
+        );
+    }
+}
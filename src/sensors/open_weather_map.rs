@@ -1,66 +1,371 @@
-use anyhow::{anyhow, bail, Result};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::blocking::RequestBuilder;
 use serde::Deserialize;
 
 use crate::config::OpenWeatherMapConfig;
+use crate::record::{Forecast, Record};
+use crate::sensors::cache::BatchCache;
+use crate::sensors::http::{self, CachedResponse};
+use crate::sensors::weather::{self, WeatherField, WeatherValues};
 use crate::sensors::Sensor;
 
+const FIELDS: [WeatherField; 10] = [
+    WeatherField::Temperature,
+    WeatherField::FeelsLike,
+    WeatherField::Humidity,
+    WeatherField::Pressure,
+    WeatherField::WindSpeed,
+    WeatherField::WindDirection,
+    WeatherField::WindGust,
+    WeatherField::Clouds,
+    WeatherField::Precipitation,
+    WeatherField::Visibility,
+];
+
 pub struct OpenWeatherMap {
     config: OpenWeatherMapConfig,
+    timeout: Duration,
+    /// Last API responses, reused until they expire or while the call budget is exhausted
+    response: CachedResponse,
+    forecast_response: CachedResponse,
+    /// Values of the last API response, shared by the series sampled in the same cycle
+    cache: BatchCache,
 }
 
 impl OpenWeatherMap {
-    pub fn new(cfg: OpenWeatherMapConfig) -> OpenWeatherMap {
-        OpenWeatherMap { config: cfg }
+    pub fn new(cfg: OpenWeatherMapConfig) -> Result<OpenWeatherMap> {
+        weather::check_fields(&cfg.weather, &FIELDS, "OpenWeatherMap")?;
+        if let Some(forecast) = &cfg.forecast {
+            weather::check_fields(forecast, &FIELDS, "OpenWeatherMap forecast")?;
+        }
+        let timeout = humantime::parse_duration(&cfg.timeout)?;
+        if cfg.max_calls_per_day == 0 {
+            bail!(anyhow!("max_calls_per_day must be at least 1"));
+        }
+        let endpoints = if cfg.forecast.is_some() { 2 } else { 1 };
+        let min_interval = Duration::from_secs(24 * 3600) * endpoints / cfg.max_calls_per_day;
+        Ok(OpenWeatherMap {
+            config: cfg,
+            timeout,
+            response: CachedResponse::new(min_interval),
+            forecast_response: CachedResponse::new(min_interval),
+            cache: BatchCache::new(Duration::from_secs(5)),
+        })
+    }
+
+    fn request(&self, endpoint: &str) -> RequestBuilder {
+        let url = format!("{}/{endpoint}", self.config.base_url.trim_end_matches('/'));
+        http::client()
+            .get(url)
+            .query(&[
+                ("lat", self.config.lat.to_string()),
+                ("lon", self.config.lon.to_string()),
+                ("appid", self.config.api_key.clone()),
+                ("units", "metric".to_owned()),
+            ])
+            .timeout(self.timeout)
+    }
+
+    fn fetch(&mut self) -> Result<WeatherApi> {
+        let request = self.request("weather");
+        let body = self.response.get(request)?;
+        serde_json::from_str(body).context("Cannot parse OpenWeatherMap response")
+    }
+
+    /// Series configured for the fields specific to OpenWeatherMap, along with the field value
+    /// in the response
+    fn extra_fields(&self, json: Option<&WeatherApi>) -> Vec<(&Option<String>, Option<f64>)> {
+        let c = &self.config;
+        vec![
+            (&c.temperature_min_series, json.map(|j| j.main.temp_min)),
+            (&c.temperature_max_series, json.map(|j| j.main.temp_max)),
+            (&c.rain_1h_series, json.map(rain_1h)),
+            (&c.snow_1h_series, json.map(snow_1h)),
+            (&c.sunrise_series, json.map(|j| j.sys.sunrise)),
+            (&c.sunset_series, json.map(|j| j.sys.sunset)),
+        ]
     }
 }
 
 impl Sensor for OpenWeatherMap {
     fn sample(&mut self, series: &str) -> Result<f64> {
-        let url = format!(
-            "https://api.openweathermap.org/data/2.5/weather?lat={}&lon={}&appid={}&units=metric",
-            self.config.lat, self.config.lon, self.config.api_key
-        );
-        let resp = reqwest::blocking::get(url)?;
-        let status = resp.status();
-        if !status.is_success() {
-            bail!(anyhow!("received an error response: {}", status));
+        if !self.series().iter().any(|s| s == series) {
+            return Err(anyhow!("no series configured with name {series}"));
         }
-        let json: WeatherApi = resp.json()?;
-        if self
-            .config
-            .temperature_series
-            .as_ref()
-            .is_some_and(|s| s == series)
-        {
-            Ok(json.main.temp)
-        } else {
-            Err(anyhow!("no series configured with name {series}"))
+        if let Some(value) = self.cache.take(series) {
+            return Ok(value);
         }
+        let json = self.fetch()?;
+        let mut values = weather::series_values(&self.config.weather, &weather_values(&json));
+        values.extend(
+            self.extra_fields(Some(&json))
+                .into_iter()
+                .filter_map(|(series, value)| Some((series.clone()?, value?))),
+        );
+        self.cache.store(values);
+        self.cache
+            .take(series)
+            .ok_or_else(|| anyhow!("no value for {series} in OpenWeatherMap response"))
     }
 
     fn series(&self) -> Vec<String> {
-        match &self.config.temperature_series {
-            None => {
-                vec![]
-            }
-            Some(name) => {
-                vec![name.clone()]
-            }
+        let mut series = weather::series(&self.config.weather);
+        series.extend(
+            self.extra_fields(None)
+                .into_iter()
+                .filter_map(|(series, _)| series.clone()),
+        );
+        series
+    }
+
+    fn forecast_series(&self) -> Vec<String> {
+        self.config
+            .forecast
+            .as_ref()
+            .map(weather::series)
+            .unwrap_or_default()
+    }
+
+    fn forecast(&mut self, series: &str) -> Result<Forecast> {
+        let Some(forecast_cfg) = &self.config.forecast else {
+            return Err(anyhow!("no forecast series configured with name {series}"));
+        };
+        if !weather::series(forecast_cfg).iter().any(|s| s == series) {
+            return Err(anyhow!("no forecast series configured with name {series}"));
         }
+        let request = self.request("forecast");
+        let body = self.forecast_response.get(request)?;
+        let json: ForecastApi =
+            serde_json::from_str(body).context("Cannot parse OpenWeatherMap forecast")?;
+        let issued_at = self.forecast_response.received_at().unwrap_or_default();
+        let records = json
+            .list
+            .iter()
+            .filter_map(|step| {
+                let values = weather::series_values(forecast_cfg, &forecast_values(step));
+                Some(Record {
+                    timestamp: step.dt,
+                    value: *values.get(series)?,
+                })
+            })
+            .collect();
+        Ok(Forecast { issued_at, records })
     }
 }
 
+fn weather_values(json: &WeatherApi) -> WeatherValues {
+    [
+        (WeatherField::Temperature, Some(json.main.temp)),
+        (WeatherField::FeelsLike, Some(json.main.feels_like)),
+        (WeatherField::Humidity, Some(json.main.humidity)),
+        (WeatherField::Pressure, Some(json.main.pressure)),
+        (WeatherField::WindSpeed, Some(json.wind.speed)),
+        (WeatherField::WindDirection, Some(json.wind.deg)),
+        (WeatherField::WindGust, json.wind.gust),
+        (WeatherField::Clouds, Some(json.clouds.all)),
+        (
+            WeatherField::Precipitation,
+            Some(rain_1h(json) + snow_1h(json)),
+        ),
+        (WeatherField::Visibility, json.visibility),
+    ]
+    .into_iter()
+    .filter_map(|(field, value)| Some((field, value?)))
+    .collect()
+}
+
+fn forecast_values(step: &ForecastStep) -> WeatherValues {
+    // Precipitation is summed over the 3 hours of the step
+    let precipitation = [&step.rain, &step.snow]
+        .into_iter()
+        .filter_map(|p| p.as_ref()?.three_hours)
+        .sum::<f64>()
+        / 3.0;
+    [
+        (WeatherField::Temperature, Some(step.main.temp)),
+        (WeatherField::FeelsLike, Some(step.main.feels_like)),
+        (WeatherField::Humidity, Some(step.main.humidity)),
+        (WeatherField::Pressure, Some(step.main.pressure)),
+        (WeatherField::WindSpeed, Some(step.wind.speed)),
+        (WeatherField::WindDirection, Some(step.wind.deg)),
+        (WeatherField::WindGust, step.wind.gust),
+        (WeatherField::Clouds, Some(step.clouds.all)),
+        (WeatherField::Precipitation, Some(precipitation)),
+        (WeatherField::Visibility, step.visibility),
+    ]
+    .into_iter()
+    .filter_map(|(field, value)| Some((field, value?)))
+    .collect()
+}
+
+// The rain and snow objects are only present when it rains or snows
+fn rain_1h(json: &WeatherApi) -> f64 {
+    json.rain.as_ref().and_then(|r| r.one_hour).unwrap_or(0.0)
+}
+
+fn snow_1h(json: &WeatherApi) -> f64 {
+    json.snow.as_ref().and_then(|s| s.one_hour).unwrap_or(0.0)
+}
+
 #[derive(Deserialize, Debug)]
 struct WeatherApi {
     main: Measures,
+    wind: Wind,
+    clouds: Clouds,
+    rain: Option<Precipitation>,
+    snow: Option<Precipitation>,
+    visibility: Option<f64>,
+    sys: Sys,
 }
 
 #[derive(Deserialize, Debug)]
 struct Measures {
     temp: f64,
-    // feels_like: f64,
-    // temp_min: f64,
-    // temp_max: f64,
-    // pressure: f64,
-    // humidity: f64,
+    feels_like: f64,
+    temp_min: f64,
+    temp_max: f64,
+    pressure: f64,
+    humidity: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Wind {
+    speed: f64,
+    deg: f64,
+    gust: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Clouds {
+    all: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Precipitation {
+    #[serde(rename = "1h")]
+    one_hour: Option<f64>,
+    #[serde(rename = "3h")]
+    three_hours: Option<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Sys {
+    sunrise: f64,
+    sunset: f64,
+}
+
+#[derive(Deserialize, Debug)]
+struct ForecastApi {
+    list: Vec<ForecastStep>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ForecastStep {
+    dt: u64,
+    main: Measures,
+    wind: Wind,
+    clouds: Clouds,
+    rain: Option<Precipitation>,
+    snow: Option<Precipitation>,
+    visibility: Option<f64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_values(values: &WeatherValues, expected: &[(WeatherField, f64)]) {
+        assert_eq!(values.len(), expected.len());
+        for (field, value) in expected {
+            assert!(
+                (values[field] - value).abs() < 1e-9,
+                "{field:?}: {}",
+                values[field]
+            );
+        }
+    }
+
+    #[test]
+    fn parse_weather() {
+        let json: WeatherApi =
+            serde_json::from_str(include_str!("testdata/open_weather_map_weather.json")).unwrap();
+        assert_values(
+            &weather_values(&json),
+            &[
+                (WeatherField::Temperature, 12.64),
+                (WeatherField::FeelsLike, 12.03),
+                (WeatherField::Humidity, 84.0),
+                (WeatherField::Pressure, 1009.0),
+                (WeatherField::WindSpeed, 5.14),
+                (WeatherField::WindDirection, 230.0),
+                (WeatherField::WindGust, 9.26),
+                (WeatherField::Clouds, 100.0),
+                (WeatherField::Precipitation, 0.42),
+                (WeatherField::Visibility, 9000.0),
+            ],
+        );
+        assert_eq!(rain_1h(&json), 0.42);
+        assert_eq!(snow_1h(&json), 0.0);
+        assert_eq!(json.main.temp_min, 11.69);
+        assert_eq!(json.main.temp_max, 13.42);
+        assert_eq!(json.sys.sunrise, 1729232113.0);
+        assert_eq!(json.sys.sunset, 1729270480.0);
+    }
+
+    #[test]
+    fn parse_weather_without_optional_values() {
+        let json: WeatherApi = serde_json::from_str(
+            r#"{
+                "main": {"temp": -1.5, "feels_like": -4.2, "temp_min": -2.0, "temp_max": -1.0, "pressure": 1021, "humidity": 91},
+                "wind": {"speed": 1.2, "deg": 40},
+                "snow": {"1h": 0.25},
+                "clouds": {"all": 75},
+                "sys": {"sunrise": 1729232113, "sunset": 1729270480}
+            }"#,
+        )
+        .unwrap();
+        let values = weather_values(&json);
+        assert!(!values.contains_key(&WeatherField::WindGust));
+        assert!(!values.contains_key(&WeatherField::Visibility));
+        assert_eq!(values[&WeatherField::Precipitation], 0.25);
+        assert_eq!(rain_1h(&json), 0.0);
+        assert_eq!(snow_1h(&json), 0.25);
+    }
+
+    #[test]
+    fn parse_forecast() {
+        let json: ForecastApi =
+            serde_json::from_str(include_str!("testdata/open_weather_map_forecast.json")).unwrap();
+        let steps: Vec<_> = json
+            .list
+            .iter()
+            .map(|s| (s.dt, forecast_values(s)))
+            .collect();
+        assert_eq!(
+            steps.iter().map(|(dt, _)| *dt).collect::<Vec<_>>(),
+            [1729252800, 1729263600, 1729274400]
+        );
+        assert_values(
+            &steps[0].1,
+            &[
+                (WeatherField::Temperature, 13.1),
+                (WeatherField::FeelsLike, 12.52),
+                (WeatherField::Humidity, 82.0),
+                (WeatherField::Pressure, 1009.0),
+                (WeatherField::WindSpeed, 4.87),
+                (WeatherField::WindDirection, 228.0),
+                (WeatherField::WindGust, 8.9),
+                (WeatherField::Clouds, 100.0),
+                (WeatherField::Precipitation, 0.0),
+                (WeatherField::Visibility, 10000.0),
+            ],
+        );
+        // Hourly precipitation from the rain and snow of the 3 hours of the step
+        assert!((steps[1].1[&WeatherField::Precipitation] - 0.49).abs() < 1e-9);
+        assert!((steps[2].1[&WeatherField::Precipitation] - 0.4).abs() < 1e-9);
+        assert!(!steps[2].1.contains_key(&WeatherField::WindGust));
+        assert!(!steps[2].1.contains_key(&WeatherField::Visibility));
+    }
 }
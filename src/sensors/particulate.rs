@@ -0,0 +1,420 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use nix::sys::termios::BaudRate;
+
+use crate::config::{Pms5003Config, Sds011Config};
+use crate::sensors::serial::{Parity, SerialPort};
+use crate::sensors::Sensor;
+
+/// Readings older than this are not returned
+const MAX_AGE: Duration = Duration::from_secs(10);
+/// Series sampled within this duration belong to the same sampling cycle
+const CYCLE: Duration = Duration::from_secs(5);
+/// The sensor is not put to sleep for shorter durations
+const MIN_SLEEP: Duration = Duration::from_secs(10);
+
+/// Particulate matter concentrations, in µg/m³
+#[derive(Clone, Copy, Debug)]
+struct Reading {
+    pm1: Option<f64>,
+    pm2_5: f64,
+    pm10: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Model {
+    Pms5003,
+    Sds011,
+}
+
+impl Model {
+    fn frame_len(&self) -> usize {
+        match self {
+            Model::Pms5003 => 32,
+            Model::Sds011 => 10,
+        }
+    }
+
+    fn command(&self, wake: bool) -> Vec<u8> {
+        match self {
+            Model::Pms5003 => {
+                let mut cmd = vec![0x42, 0x4D, 0xE4, 0x00, wake as u8];
+                let sum: u16 = cmd.iter().map(|b| *b as u16).sum();
+                cmd.extend(sum.to_be_bytes());
+                cmd
+            }
+            Model::Sds011 => {
+                // Set sleep and work command, addressed to all the sensors
+                let mut cmd = vec![0xAA, 0xB4, 0x06, 0x01, wake as u8];
+                cmd.extend([0; 10]);
+                cmd.extend([0xFF, 0xFF]);
+                let sum: u32 = cmd[2..].iter().map(|b| *b as u32).sum();
+                cmd.extend([sum as u8, 0xAB]);
+                cmd
+            }
+        }
+    }
+
+    /// Parses the first valid frame of the buffer and removes it, along with the bytes
+    /// preceding it. Returns `None` if the buffer holds no complete frame.
+    fn parse(&self, buf: &mut Vec<u8>) -> Option<Reading> {
+        let header: &[u8] = match self {
+            Model::Pms5003 => &[0x42, 0x4D],
+            Model::Sds011 => &[0xAA, 0xC0],
+        };
+        loop {
+            let Some(start) = buf.windows(2).position(|w| w == header) else {
+                // Only the last byte may start a header
+                buf.drain(..buf.len().saturating_sub(1));
+                return None;
+            };
+            buf.drain(..start);
+            if buf.len() < self.frame_len() {
+                return None;
+            }
+            let frame = &buf[..self.frame_len()];
+            let reading = match self {
+                Model::Pms5003 => parse_pms5003(frame),
+                Model::Sds011 => parse_sds011(frame),
+            };
+            match reading {
+                Ok(reading) => {
+                    buf.drain(..self.frame_len());
+                    return Some(reading);
+                }
+                Err(e) => {
+                    println!("Warning: invalid {self:?} frame {frame:02x?}: {e}");
+                    buf.drain(..1);
+                }
+            }
+        }
+    }
+}
+
+fn parse_pms5003(frame: &[u8]) -> Result<Reading> {
+    let word = |i: usize| u16::from_be_bytes([frame[i], frame[i + 1]]);
+    if word(2) != 28 {
+        bail!(anyhow!("unexpected frame length {}", word(2)));
+    }
+    let sum: u16 = frame[..30].iter().map(|b| *b as u16).sum();
+    if sum != word(30) {
+        bail!(anyhow!(
+            "checksum mismatch: expected {sum:#06x}, received {:#06x}",
+            word(30)
+        ));
+    }
+    // Concentrations under atmospheric environment, after the "standard particle" ones
+    Ok(Reading {
+        pm1: Some(word(10) as f64),
+        pm2_5: word(12) as f64,
+        pm10: word(14) as f64,
+    })
+}
+
+fn parse_sds011(frame: &[u8]) -> Result<Reading> {
+    if frame[9] != 0xAB {
+        bail!(anyhow!("missing frame tail"));
+    }
+    let sum = frame[2..8].iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+    if sum != frame[8] {
+        bail!(anyhow!(
+            "checksum mismatch: expected {sum:#04x}, received {:#04x}",
+            frame[8]
+        ));
+    }
+    let word = |i: usize| u16::from_le_bytes([frame[i], frame[i + 1]]);
+    Ok(Reading {
+        pm1: None,
+        pm2_5: word(2) as f64 / 10.0,
+        pm10: word(4) as f64 / 10.0,
+    })
+}
+
+/// State shared with the thread reading the serial port
+#[derive(Default)]
+struct Shared {
+    latest: Option<(Instant, Reading)>,
+    /// Error that stopped the reading thread
+    error: Option<String>,
+    /// Start of the last sampling cycle, and duration between the two last cycles
+    last_cycle: Option<Instant>,
+    interval: Option<Duration>,
+    /// Set when a sample finds the sensor asleep
+    wake_requested: bool,
+}
+
+/// Particulate matter sensor streaming its measures on a serial port. The stream is read by a
+/// background thread, which also puts the sensor to sleep between sampling cycles.
+pub struct Particulate {
+    model: Model,
+    shared: Arc<Mutex<Shared>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    pm1_series: Option<String>,
+    pm2_5_series: Option<String>,
+    pm10_series: Option<String>,
+}
+
+impl Particulate {
+    pub fn pms5003(config: Pms5003Config) -> Result<Particulate> {
+        Particulate::new(
+            Model::Pms5003,
+            &config.path,
+            &config.warmup,
+            config.pm1_series,
+            config.pm2_5_series,
+            config.pm10_series,
+        )
+    }
+
+    pub fn sds011(config: Sds011Config) -> Result<Particulate> {
+        Particulate::new(
+            Model::Sds011,
+            &config.path,
+            &config.warmup,
+            None,
+            config.pm2_5_series,
+            config.pm10_series,
+        )
+    }
+
+    fn new(
+        model: Model,
+        path: &str,
+        warmup: &Option<String>,
+        pm1_series: Option<String>,
+        pm2_5_series: Option<String>,
+        pm10_series: Option<String>,
+    ) -> Result<Particulate> {
+        let warmup = match warmup {
+            Some(w) => Some(humantime::parse_duration(w)?),
+            None => None,
+        };
+        let port = SerialPort::open(path, BaudRate::B9600, Parity::None)?;
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let shared = shared.clone();
+            let stop = stop.clone();
+            spawn(move || {
+                if let Err(e) = read(model, port, warmup, &shared, &stop) {
+                    println!("Warning: {model:?} reading stopped: {e:#}");
+                    shared.lock().unwrap().error = Some(format!("{e:#}"));
+                }
+            })
+        };
+        Ok(Particulate {
+            model,
+            shared,
+            stop,
+            thread: Some(thread),
+            pm1_series,
+            pm2_5_series,
+            pm10_series,
+        })
+    }
+}
+
+impl Sensor for Particulate {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        let is = |s: &Option<String>| s.as_ref().is_some_and(|s| s == series);
+        if !is(&self.pm1_series) && !is(&self.pm2_5_series) && !is(&self.pm10_series) {
+            return Err(anyhow!("no series configured with name {series}"));
+        }
+        let mut shared = self.shared.lock().unwrap();
+        if let Some(e) = &shared.error {
+            bail!(anyhow!("{:?} reading stopped: {e}", self.model));
+        }
+        let now = Instant::now();
+        match shared.last_cycle {
+            Some(t) if now - t < CYCLE => {}
+            previous => {
+                shared.interval = previous.map(|t| now - t);
+                shared.last_cycle = Some(now);
+            }
+        }
+        let reading = match shared.latest {
+            Some((t, reading)) if now - t < MAX_AGE => reading,
+            _ => {
+                shared.wake_requested = true;
+                bail!(anyhow!(
+                    "no recent reading from {:?}, it may be waking up",
+                    self.model
+                ));
+            }
+        };
+
+        if is(&self.pm1_series) {
+            reading
+                .pm1
+                .ok_or_else(|| anyhow!("{:?} does not measure PM1.0", self.model))
+        } else if is(&self.pm2_5_series) {
+            Ok(reading.pm2_5)
+        } else {
+            Ok(reading.pm10)
+        }
+    }
+
+    fn series(&self) -> Vec<String> {
+        [&self.pm1_series, &self.pm2_5_series, &self.pm10_series]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+}
+
+impl Drop for Particulate {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Reads the frames sent by the sensor until `stop` is set. With a warmup duration, the sensor
+/// sleeps once the reading of a cycle has been taken, and wakes up `warmup` before the next one.
+fn read(
+    model: Model,
+    mut port: SerialPort,
+    warmup: Option<Duration>,
+    shared: &Mutex<Shared>,
+    stop: &AtomicBool,
+) -> Result<()> {
+    port.write_all(&model.command(true))?;
+    let mut awake_since = Instant::now();
+    let mut buf = vec![];
+    while !stop.load(Ordering::Relaxed) {
+        let wake_at = match (warmup, &*shared.lock().unwrap()) {
+            (
+                Some(warmup),
+                Shared {
+                    last_cycle: Some(last),
+                    interval: Some(interval),
+                    ..
+                },
+            ) if *last > awake_since => (*last + *interval).checked_sub(warmup),
+            _ => None,
+        };
+        if let Some(wake_at) = wake_at.filter(|t| *t > Instant::now() + MIN_SLEEP) {
+            port.write_all(&model.command(false))?;
+            while Instant::now() < wake_at && !stop.load(Ordering::Relaxed) {
+                if std::mem::take(&mut shared.lock().unwrap().wake_requested) {
+                    break;
+                }
+                sleep(Duration::from_millis(200));
+            }
+            port.discard_input()?;
+            buf.clear();
+            port.write_all(&model.command(true))?;
+            awake_since = Instant::now();
+            continue;
+        }
+
+        let mut chunk = [0; 64];
+        let len = port.read(&mut chunk, Duration::from_millis(500))?;
+        buf.extend_from_slice(&chunk[..len]);
+        while let Some(reading) = model.parse(&mut buf) {
+            shared.lock().unwrap().latest = Some((Instant::now(), reading));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// PM1.0, PM2.5 and PM10 of 6, 9 and 11 µg/m³ as standard particles, and of 5, 8 and
+    /// 10 µg/m³ under atmospheric environment
+    const PMS5003_FRAME: [u8; 32] = [
+        0x42, 0x4D, 0x00, 0x1C, 0x00, 0x06, 0x00, 0x09, 0x00, 0x0B, 0x00, 0x05, 0x00, 0x08, 0x00,
+        0x0A, 0x04, 0xB0, 0x01, 0x5E, 0x00, 0x3C, 0x00, 0x08, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00,
+        0x02, 0x36,
+    ];
+    /// PM2.5 of 123.6 µg/m³ and PM10 of 261.8 µg/m³
+    const SDS011_FRAME: [u8; 10] = [0xAA, 0xC0, 0xD4, 0x04, 0x3A, 0x0A, 0xA1, 0x60, 0x1D, 0xAB];
+
+    #[test]
+    fn pms5003_frame() {
+        let reading = parse_pms5003(&PMS5003_FRAME).unwrap();
+        assert_eq!(reading.pm1, Some(5.0));
+        assert_eq!(reading.pm2_5, 8.0);
+        assert_eq!(reading.pm10, 10.0);
+
+        let mut frame = PMS5003_FRAME;
+        frame[31] ^= 1;
+        assert!(parse_pms5003(&frame).is_err());
+        let mut frame = PMS5003_FRAME;
+        frame[3] = 0x14;
+        assert!(parse_pms5003(&frame).is_err());
+    }
+
+    #[test]
+    fn sds011_frame() {
+        let reading = parse_sds011(&SDS011_FRAME).unwrap();
+        assert_eq!(reading.pm1, None);
+        assert_eq!(reading.pm2_5, 123.6);
+        assert_eq!(reading.pm10, 261.8);
+
+        let mut frame = SDS011_FRAME;
+        frame[8] = 0x1E;
+        assert!(parse_sds011(&frame).is_err());
+        let mut frame = SDS011_FRAME;
+        frame[9] = 0x00;
+        assert!(parse_sds011(&frame).is_err());
+    }
+
+    #[test]
+    fn leading_garbage() {
+        let mut buf = vec![0x00, 0x42, 0x13, 0xAA];
+        buf.extend(SDS011_FRAME);
+        buf.extend([0xAA, 0xC0, 0x01]);
+        let reading = Model::Sds011.parse(&mut buf).unwrap();
+        assert_eq!(reading.pm2_5, 123.6);
+        // The beginning of the next frame is kept
+        assert!(Model::Sds011.parse(&mut buf).is_none());
+        assert_eq!(buf, [0xAA, 0xC0, 0x01]);
+    }
+
+    #[test]
+    fn bad_checksum_skipped() {
+        let mut bad = PMS5003_FRAME;
+        bad[20] ^= 0x10;
+        let mut buf = bad.to_vec();
+        buf.extend(PMS5003_FRAME);
+        let reading = Model::Pms5003.parse(&mut buf).unwrap();
+        assert_eq!(reading.pm2_5, 8.0);
+        assert!(buf.is_empty());
+
+        let mut buf = bad.to_vec();
+        assert!(Model::Pms5003.parse(&mut buf).is_none());
+        assert_eq!(buf, [0x36]);
+    }
+
+    #[test]
+    fn garbage_dropped() {
+        let mut buf = vec![0x13, 0x37, 0x00, 0x42];
+        assert!(Model::Pms5003.parse(&mut buf).is_none());
+        assert_eq!(buf, [0x42]);
+        buf.extend(&PMS5003_FRAME[1..]);
+        assert_eq!(Model::Pms5003.parse(&mut buf).unwrap().pm1, Some(5.0));
+    }
+
+    #[test]
+    fn split_frame() {
+        let mut buf = vec![0xFF];
+        buf.extend(&PMS5003_FRAME[..20]);
+        assert!(Model::Pms5003.parse(&mut buf).is_none());
+        assert_eq!(buf, PMS5003_FRAME[..20]);
+        buf.extend(&PMS5003_FRAME[20..]);
+        let reading = Model::Pms5003.parse(&mut buf).unwrap();
+        assert_eq!(reading.pm10, 10.0);
+        assert!(buf.is_empty());
+    }
+}
@@ -0,0 +1,94 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::termios::{self, BaudRate, ControlFlags, FlushArg, SetArg};
+
+#[derive(Clone, Copy)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// Serial port in raw mode, 8 data bits, 1 stop bit
+pub struct SerialPort {
+    file: File,
+    path: String,
+}
+
+impl SerialPort {
+    pub fn open(path: &str, baud_rate: BaudRate, parity: Parity) -> Result<SerialPort> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(nix::libc::O_NOCTTY)
+            .open(path)
+            .with_context(|| format!("Cannot open serial port {path}"))?;
+        let fd = file.as_raw_fd();
+        let mut tio =
+            termios::tcgetattr(fd).with_context(|| format!("{path} is not a serial port"))?;
+        termios::cfmakeraw(&mut tio);
+        termios::cfsetspeed(&mut tio, baud_rate)?;
+        match parity {
+            Parity::None => {}
+            Parity::Even => tio.control_flags |= ControlFlags::PARENB,
+            Parity::Odd => tio.control_flags |= ControlFlags::PARENB | ControlFlags::PARODD,
+        }
+        termios::tcsetattr(fd, SetArg::TCSANOW, &tio)
+            .with_context(|| format!("Cannot configure serial port {path}"))?;
+        Ok(SerialPort {
+            file,
+            path: path.to_owned(),
+        })
+    }
+
+    pub fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        self.file
+            .write_all(data)
+            .with_context(|| format!("Cannot write to {}", self.path))
+    }
+
+    /// Discards the received bytes not read yet.
+    pub fn discard_input(&self) -> Result<()> {
+        termios::tcflush(self.file.as_raw_fd(), FlushArg::TCIFLUSH)?;
+        Ok(())
+    }
+
+    /// Reads the available bytes, waiting at most `timeout` for some to arrive. Returns 0 when
+    /// nothing has been received.
+    pub fn read(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        let mut fds = [PollFd::new(self.file.as_raw_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, timeout.as_millis() as i32) {
+            Ok(0) | Err(Errno::EINTR) => Ok(0),
+            Ok(_) => self
+                .file
+                .read(buf)
+                .with_context(|| format!("Cannot read from {}", self.path)),
+            Err(e) => Err(e).with_context(|| format!("Cannot wait for data on {}", self.path)),
+        }
+    }
+
+    /// Fills the buffer, failing if it takes more than `timeout`.
+    pub fn read_exact(&mut self, buf: &mut [u8], timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut len = 0;
+        while len < buf.len() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                bail!(anyhow!(
+                    "timed out waiting for data on {}, received {len} of {} bytes",
+                    self.path,
+                    buf.len()
+                ));
+            }
+            len += self.read(&mut buf[len..], remaining)?;
+        }
+        Ok(())
+    }
+}
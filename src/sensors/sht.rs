@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::config::{
+    Sht3xAddress, Sht3xConfig, Sht4xAddress, Sht4xConfig, Sht4xHeaterDuration, Sht4xHeaterPower,
+    ShtRepeatability,
+};
+use crate::sensors::cache::BatchCache;
+use crate::sensors::Sensor;
+use anyhow::{anyhow, bail, Context, Result};
+use embedded_hal::blocking::i2c::{Read, Write};
+use linux_embedded_hal::I2cdev;
+
+const SHT3X_SOFT_RESET: [u8; 2] = [0x30, 0xA2];
+const SHT3X_HEATER_ENABLE: [u8; 2] = [0x30, 0x6D];
+const SHT3X_HEATER_DISABLE: [u8; 2] = [0x30, 0x66];
+const SHT4X_SOFT_RESET: u8 = 0x94;
+
+/// Sensirion SHT3x temperature and humidity sensor
+pub struct Sht3x {
+    config: Sht3xConfig,
+    i2c: I2cdev,
+    address: u8,
+}
+
+impl Sht3x {
+    pub fn new(config: Sht3xConfig) -> Result<Sht3x> {
+        let address = match config.address {
+            Sht3xAddress::AddrLow => 0x44,
+            Sht3xAddress::AddrHigh => 0x45,
+        };
+        let mut i2c = I2cdev::new(&config.path).context("Cannot open I2C bus")?;
+        i2c.write(address, &SHT3X_SOFT_RESET)
+            .context("Cannot reset SHT3x")?;
+        sleep(Duration::from_millis(2));
+        let heater = if config.heater {
+            SHT3X_HEATER_ENABLE
+        } else {
+            SHT3X_HEATER_DISABLE
+        };
+        i2c.write(address, &heater)
+            .context("Cannot configure SHT3x heater")?;
+        Ok(Sht3x {
+            config,
+            i2c,
+            address,
+        })
+    }
+}
+
+impl Sensor for Sht3x {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        // Single shot measurement, clock stretching disabled
+        let (command, duration) = match self.config.repeatability {
+            ShtRepeatability::High => ([0x24, 0x00], Duration::from_micros(15500)),
+            ShtRepeatability::Medium => ([0x24, 0x0B], Duration::from_micros(6500)),
+            ShtRepeatability::Low => ([0x24, 0x16], Duration::from_micros(4000)),
+        };
+        self.i2c
+            .write(self.address, &command)
+            .context("Cannot trigger SHT3x measurement")?;
+        sleep(duration);
+        let (t, rh) = read_measurement(&mut self.i2c, self.address)
+            .context("Cannot read sample from SHT3x")?;
+
+        if self.config.temperature_series.as_ref().is_some_and(|s| s == series) {
+            Ok(-45.0 + 175.0 * t as f64 / 65535.0)
+        } else if self.config.humidity_series.as_ref().is_some_and(|s| s == series) {
+            Ok(100.0 * rh as f64 / 65535.0)
+        } else {
+            Err(anyhow!("no series configured with name {series}"))
+        }
+    }
+
+    fn series(&self) -> Vec<String> {
+        series(&self.config.temperature_series, &self.config.humidity_series)
+    }
+}
+
+/// Sensirion SHT4x temperature and humidity sensor
+pub struct Sht4x {
+    config: Sht4xConfig,
+    i2c: I2cdev,
+    address: u8,
+    cache: BatchCache,
+}
+
+impl Sht4x {
+    pub fn new(config: Sht4xConfig) -> Result<Sht4x> {
+        let address = match config.address {
+            Sht4xAddress::A => 0x44,
+            Sht4xAddress::B => 0x45,
+            Sht4xAddress::C => 0x46,
+        };
+        let mut i2c = I2cdev::new(&config.path).context("Cannot open I2C bus")?;
+        i2c.write(address, &[SHT4X_SOFT_RESET])
+            .context("Cannot reset SHT4x")?;
+        sleep(Duration::from_millis(1));
+        Ok(Sht4x {
+            config,
+            i2c,
+            address,
+            cache: BatchCache::new(Duration::from_secs(5)),
+        })
+    }
+
+    /// Fires the heater. The sensor performs a measurement at the end of the pulse, which is
+    /// discarded as it is skewed by the heating.
+    fn heat(&mut self) -> Result<()> {
+        let Some(heater) = &self.config.heater else {
+            return Ok(());
+        };
+        let (command, duration) = match (heater.power, heater.duration) {
+            (Sht4xHeaterPower::High, Sht4xHeaterDuration::Long) => (0x39, 1100),
+            (Sht4xHeaterPower::High, Sht4xHeaterDuration::Short) => (0x32, 110),
+            (Sht4xHeaterPower::Medium, Sht4xHeaterDuration::Long) => (0x2F, 1100),
+            (Sht4xHeaterPower::Medium, Sht4xHeaterDuration::Short) => (0x24, 110),
+            (Sht4xHeaterPower::Low, Sht4xHeaterDuration::Long) => (0x1E, 1100),
+            (Sht4xHeaterPower::Low, Sht4xHeaterDuration::Short) => (0x15, 110),
+        };
+        self.i2c
+            .write(self.address, &[command])
+            .context("Cannot trigger SHT4x heater")?;
+        sleep(Duration::from_millis(duration));
+        read_measurement(&mut self.i2c, self.address).context("SHT4x heater pulse failed")?;
+        Ok(())
+    }
+
+    /// Measures the temperature and humidity, then fires the heater once for both.
+    fn measure(&mut self) -> Result<HashMap<String, f64>> {
+        let (command, duration) = match self.config.repeatability {
+            ShtRepeatability::High => (0xFD, Duration::from_micros(8300)),
+            ShtRepeatability::Medium => (0xF6, Duration::from_micros(4500)),
+            ShtRepeatability::Low => (0xE0, Duration::from_micros(1600)),
+        };
+        self.i2c
+            .write(self.address, &[command])
+            .context("Cannot trigger SHT4x measurement")?;
+        sleep(duration);
+        let (t, rh) = read_measurement(&mut self.i2c, self.address)
+            .context("Cannot read sample from SHT4x")?;
+        self.heat()?;
+
+        let mut values = HashMap::new();
+        if let Some(s) = &self.config.temperature_series {
+            values.insert(s.clone(), -45.0 + 175.0 * t as f64 / 65535.0);
+        }
+        if let Some(s) = &self.config.humidity_series {
+            values.insert(
+                s.clone(),
+                (-6.0 + 125.0 * rh as f64 / 65535.0).clamp(0.0, 100.0),
+            );
+        }
+        Ok(values)
+    }
+}
+
+impl Sensor for Sht4x {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        if !self.series().iter().any(|s| s == series) {
+            return Err(anyhow!("no series configured with name {series}"));
+        }
+        if let Some(value) = self.cache.take(series) {
+            return Ok(value);
+        }
+        let values = self.measure()?;
+        self.cache.store(values);
+        self.cache
+            .take(series)
+            .ok_or_else(|| anyhow!("no series configured with name {series}"))
+    }
+
+    fn series(&self) -> Vec<String> {
+        series(&self.config.temperature_series, &self.config.humidity_series)
+    }
+}
+
+fn series(temperature: &Option<String>, humidity: &Option<String>) -> Vec<String> {
+    let mut ret = vec![];
+    if let Some(s) = temperature {
+        ret.push(s.clone());
+    }
+    if let Some(s) = humidity {
+        ret.push(s.clone());
+    }
+    ret
+}
+
+/// Reads the raw temperature and humidity words, both followed by their CRC.
+fn read_measurement(i2c: &mut I2cdev, address: u8) -> Result<(u16, u16)> {
+    let mut buf = [0; 6];
+    i2c.read(address, &mut buf)?;
+    for word in buf.chunks(3) {
+        let crc = crc8(&word[..2]);
+        if crc != word[2] {
+            bail!(anyhow!(
+                "CRC mismatch: expected {crc:#04x}, received {:#04x}",
+                word[2]
+            ));
+        }
+    }
+    Ok((
+        u16::from_be_bytes([buf[0], buf[1]]),
+        u16::from_be_bytes([buf[3], buf[4]]),
+    ))
+}
+
+/// CRC-8 used by Sensirion sensors: polynomial 0x31, initialization 0xFF
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0xFF;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc() {
+        // Example of the datasheets
+        assert_eq!(crc8(&[0xBE, 0xEF]), 0x92);
+        assert_eq!(crc8(&[0x00, 0x00]), 0x81);
+        // A word followed by its CRC checks to 0
+        assert_eq!(crc8(&[0xBE, 0xEF, 0x92]), 0x00);
+    }
+}
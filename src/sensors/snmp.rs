@@ -0,0 +1,1365 @@
+use std::collections::HashMap;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::ops::Range;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use aes::Aes128;
+use anyhow::{anyhow, bail, Context, Result};
+use cbc::cipher::block_padding::NoPadding;
+use cbc::cipher::{AsyncStreamCipher, BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use des::Des;
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use sha1::{Digest, Sha1};
+
+use crate::config::{SnmpAuthProtocol, SnmpConfig, SnmpObject, SnmpPrivacyProtocol, SnmpVersion};
+use crate::sensors::cache::BatchCache;
+use crate::sensors::Sensor;
+
+/// Maximum number of objects read by a single request, keeping responses small
+const MAX_OBJECTS: usize = 20;
+/// Largest message accepted
+const MAX_MESSAGE_SIZE: usize = 65507;
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const COUNTER32: u8 = 0x41;
+const GAUGE32: u8 = 0x42;
+const TIME_TICKS: u8 = 0x43;
+const COUNTER64: u8 = 0x46;
+const NO_SUCH_OBJECT: u8 = 0x80;
+const NO_SUCH_INSTANCE: u8 = 0x81;
+const END_OF_MIB_VIEW: u8 = 0x82;
+const GET_REQUEST: u8 = 0xA0;
+const RESPONSE: u8 = 0xA2;
+const REPORT: u8 = 0xA8;
+
+const FLAG_AUTH: u8 = 0x01;
+const FLAG_PRIV: u8 = 0x02;
+const FLAG_REPORTABLE: u8 = 0x04;
+/// usmStatsNotInTimeWindows and usmStatsUnknownEngineIDs
+const NOT_IN_TIME_WINDOW: &[u32] = &[1, 3, 6, 1, 6, 3, 15, 1, 1, 2, 0];
+const UNKNOWN_ENGINE_ID: &[u32] = &[1, 3, 6, 1, 6, 3, 15, 1, 1, 4, 0];
+
+/// Device polled over SNMP
+pub struct Snmp {
+    session: Session,
+    series: HashMap<String, SnmpObject>,
+    oids: Vec<(String, Vec<u32>)>,
+    cache: BatchCache<Value>,
+    polled_at: Instant,
+    /// Value and reading time of the counters at their previous sample
+    counters: HashMap<String, (u64, Instant)>,
+}
+
+impl Snmp {
+    pub fn new(config: SnmpConfig) -> Result<Snmp> {
+        let mut oids = vec![];
+        for (series, object) in &config.series {
+            let oid = parse_oid(&object.oid)
+                .with_context(|| format!("Invalid OID for \"{series}\" series"))?;
+            oids.push((series.clone(), oid));
+        }
+        let security = match config.version {
+            SnmpVersion::V2c { community } => Security::Community(community.into_bytes()),
+            SnmpVersion::V3 {
+                username,
+                auth,
+                privacy,
+            } => {
+                for password in auth.iter().map(|a| &a.password) {
+                    check_password(password)?;
+                }
+                for password in privacy.iter().map(|p| &p.password) {
+                    check_password(password)?;
+                }
+                let auth = auth.map(|a| (a.protocol, password_to_key(a.protocol, &a.password)));
+                let privacy = match (&auth, privacy) {
+                    (_, None) => None,
+                    (Some((protocol, _)), Some(p)) => {
+                        Some((p.protocol, password_to_key(*protocol, &p.password)))
+                    }
+                    (None, Some(_)) => bail!(anyhow!("SNMPv3 privacy requires authentication")),
+                };
+                Security::Usm(Usm {
+                    username: username.into_bytes(),
+                    auth,
+                    privacy,
+                    engine: None,
+                    salt: seed(),
+                })
+            }
+        };
+        let address = format!("{}:{}", config.host, config.port);
+        Ok(Snmp {
+            session: Session {
+                address,
+                socket: None,
+                timeout: humantime::parse_duration(&config.timeout)?,
+                request_id: seed() as i32 & i32::MAX,
+                security,
+            },
+            series: config.series,
+            oids,
+            cache: BatchCache::new(Duration::from_secs(5)),
+            polled_at: Instant::now(),
+            counters: HashMap::new(),
+        })
+    }
+
+    /// Reads all the objects.
+    fn poll(&mut self) -> Result<()> {
+        let mut values = HashMap::new();
+        for chunk in self.oids.chunks(MAX_OBJECTS) {
+            let oids: Vec<_> = chunk.iter().map(|(_, oid)| oid.clone()).collect();
+            let bindings = self.session.get(&oids)?;
+            for ((series, oid), (received_oid, value)) in chunk.iter().zip(bindings) {
+                if received_oid != *oid {
+                    bail!(anyhow!(
+                        "response for {} instead of {}",
+                        format_oid(&received_oid),
+                        format_oid(oid)
+                    ));
+                }
+                values.insert(series.clone(), value);
+            }
+        }
+        self.cache.store(values);
+        self.polled_at = Instant::now();
+        Ok(())
+    }
+
+    /// Rate of a counter since its previous sample. Counters smaller than their previous value
+    /// are assumed to have wrapped when `modulus` is set, to have been reset otherwise.
+    fn rate(&mut self, series: &str, value: u64, modulus: Option<u64>) -> Result<f64> {
+        let previous = self
+            .counters
+            .insert(series.to_owned(), (value, self.polled_at));
+        let (prev, t) = previous.ok_or_else(|| {
+            anyhow!("first reading of the {series} counter, its rate is given from the next sample")
+        })?;
+        let delta = match modulus {
+            _ if value >= prev => value - prev,
+            Some(modulus) => value + modulus - prev,
+            None => bail!(anyhow!("the {series} counter has been reset")),
+        };
+        let elapsed = (self.polled_at - t).as_secs_f64();
+        if elapsed > 0.0 {
+            Ok(delta as f64 / elapsed)
+        } else {
+            Ok(0.0)
+        }
+    }
+}
+
+impl Sensor for Snmp {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        let (scale, offset) = self
+            .series
+            .get(series)
+            .map(|o| (o.scale, o.offset))
+            .ok_or_else(|| anyhow!("no series configured with name {series}"))?;
+        let value = match self.cache.take(series) {
+            Some(value) => value,
+            None => {
+                self.poll()?;
+                self.cache
+                    .take(series)
+                    .ok_or_else(|| anyhow!("no value for {series}"))?
+            }
+        };
+        let number = match value {
+            Value::Integer(v) => v as f64,
+            Value::Gauge32(v) => v as f64,
+            Value::TimeTicks(v) => v as f64 / 100.0,
+            Value::Counter32(v) => self.rate(series, v as u64, Some(1 << 32))?,
+            Value::Counter64(v) => self.rate(series, v, None)?,
+            Value::OctetString(s) => String::from_utf8_lossy(&s)
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("{:?} is not a number", String::from_utf8_lossy(&s)))?,
+            Value::Exception(e) => bail!(anyhow!("{e}")),
+        };
+        Ok(number * scale + offset)
+    }
+
+    fn series(&self) -> Vec<String> {
+        self.series.keys().cloned().collect()
+    }
+}
+
+/// Value of an object
+enum Value {
+    Integer(i64),
+    OctetString(Vec<u8>),
+    Counter32(u32),
+    Gauge32(u32),
+    TimeTicks(u32),
+    Counter64(u64),
+    /// Missing or unsupported value
+    Exception(String),
+}
+
+impl Value {
+    fn decode(tag: u8, content: &[u8]) -> Result<Value> {
+        let unsigned32 = || -> Result<u32> {
+            u32::try_from(decode_unsigned(content)?).map_err(|_| anyhow!("value out of range"))
+        };
+        Ok(match tag {
+            INTEGER => Value::Integer(decode_integer(content)?),
+            OCTET_STRING => Value::OctetString(content.to_vec()),
+            COUNTER32 => Value::Counter32(unsigned32()?),
+            GAUGE32 => Value::Gauge32(unsigned32()?),
+            TIME_TICKS => Value::TimeTicks(unsigned32()?),
+            COUNTER64 => Value::Counter64(decode_unsigned(content)?),
+            NO_SUCH_OBJECT => Value::Exception("no such object".to_owned()),
+            NO_SUCH_INSTANCE => Value::Exception("no such instance".to_owned()),
+            END_OF_MIB_VIEW => Value::Exception("end of MIB view".to_owned()),
+            tag => Value::Exception(format!("unsupported value type {tag:#04x}")),
+        })
+    }
+}
+
+struct Session {
+    address: String,
+    /// Socket connected to the agent, opened on first use
+    socket: Option<UdpSocket>,
+    timeout: Duration,
+    request_id: i32,
+    security: Security,
+}
+
+enum Security {
+    Community(Vec<u8>),
+    Usm(Usm),
+}
+
+/// User-based security model of SNMPv3
+struct Usm {
+    username: Vec<u8>,
+    /// Protocols and keys derived from the passwords, before their localization to the engine
+    auth: Option<(SnmpAuthProtocol, Vec<u8>)>,
+    privacy: Option<(SnmpPrivacyProtocol, Vec<u8>)>,
+    /// Authoritative engine of the agent, once discovered
+    engine: Option<Engine>,
+    salt: u64,
+}
+
+struct Engine {
+    id: Vec<u8>,
+    boots: u32,
+    time: u32,
+    synced_at: Instant,
+    auth_key: Vec<u8>,
+    privacy_key: Vec<u8>,
+}
+
+impl Engine {
+    fn time(&self) -> u32 {
+        self.time
+            .saturating_add(self.synced_at.elapsed().as_secs() as u32)
+    }
+}
+
+impl Session {
+    /// Reads objects, returning their bindings in the order of the request.
+    fn get(&mut self, oids: &[Vec<u32>]) -> Result<Vec<(Vec<u32>, Value)>> {
+        let pdu = match &self.security {
+            Security::Community(_) => self.get_v2c(oids)?,
+            Security::Usm(_) => self.get_v3(oids)?,
+        };
+        decode_bindings(&pdu)
+    }
+
+    fn next_request_id(&mut self) -> i32 {
+        self.request_id = self.request_id.wrapping_add(1) & i32::MAX;
+        self.request_id
+    }
+
+    fn get_v2c(&mut self, oids: &[Vec<u32>]) -> Result<Vec<u8>> {
+        let request_id = self.next_request_id();
+        let Security::Community(community) = &self.security else {
+            unreachable!()
+        };
+        let message = tlv(
+            SEQUENCE,
+            &[
+                integer(1),
+                tlv(OCTET_STRING, community),
+                get_request(request_id, oids),
+            ]
+            .concat(),
+        );
+        self.exchange(&message, |response| {
+            let mut message = Reader::new(response).sequence()?;
+            if message.integer()? != 1 {
+                bail!(anyhow!("not an SNMPv2c message"));
+            }
+            message.read(OCTET_STRING)?;
+            let (tag, pdu) = message.read_any()?;
+            if tag != RESPONSE || pdu_request_id(pdu)? != request_id {
+                return Ok(None);
+            }
+            Ok(Some(pdu.to_vec()))
+        })
+    }
+
+    fn get_v3(&mut self, oids: &[Vec<u32>]) -> Result<Vec<u8>> {
+        // A report about the engine ID or time updates the engine, and the request is resent
+        for _ in 0..3 {
+            let Security::Usm(usm) = &mut self.security else {
+                unreachable!()
+            };
+            if usm.engine.is_none() {
+                self.discover()?;
+                continue;
+            }
+            let request_id = self.next_request_id();
+            let pdu = get_request(request_id, oids);
+            let (message, msg_id) = self.v3_message(pdu)?;
+            let (tag, pdu) = self.exchange_v3(&message, msg_id)?;
+            if tag == RESPONSE {
+                if pdu_request_id(&pdu)? != request_id {
+                    bail!(anyhow!("response does not match the request"));
+                }
+                return Ok(pdu);
+            }
+            if tag != REPORT {
+                bail!(anyhow!("unexpected PDU type {tag:#04x}"));
+            }
+            let report = decode_bindings(&pdu)?;
+            match report.first().map(|(oid, _)| oid.as_slice()) {
+                Some(NOT_IN_TIME_WINDOW) => continue,
+                Some(UNKNOWN_ENGINE_ID) => {
+                    let Security::Usm(usm) = &mut self.security else {
+                        unreachable!()
+                    };
+                    usm.engine = None;
+                }
+                Some(oid) => bail!(anyhow!("agent reported {}", report_name(oid))),
+                None => bail!(anyhow!("agent sent an empty report")),
+            }
+        }
+        bail!(anyhow!(
+            "cannot synchronize with the SNMP engine of the agent"
+        ))
+    }
+
+    /// Learns the engine ID, boots and time of the agent from the report it sends to an empty
+    /// unauthenticated request.
+    fn discover(&mut self) -> Result<()> {
+        let msg_id = self.next_request_id();
+        let request_id = self.next_request_id();
+        let security_parameters = usm_parameters(&[], 0, 0, &[], &[], &[]);
+        let message = v3_message(
+            msg_id,
+            FLAG_REPORTABLE,
+            &security_parameters,
+            &scoped_pdu(&[], &get_request(request_id, &[])),
+        );
+        let parameters = self.exchange(&message, |response| {
+            let parsed = V3Message::decode(response)?;
+            if parsed.msg_id != msg_id as i64 {
+                return Ok(None);
+            }
+            Ok(Some((parsed.engine_id.to_vec(), parsed.boots, parsed.time)))
+        })?;
+        let (id, boots, time) = parameters;
+        if id.is_empty() {
+            bail!(anyhow!("agent did not report its engine ID"));
+        }
+        let Security::Usm(usm) = &mut self.security else {
+            unreachable!()
+        };
+        let auth_key = match &usm.auth {
+            Some((protocol, key)) => localize_key(*protocol, key, &id),
+            None => vec![],
+        };
+        let privacy_key = match (&usm.auth, &usm.privacy) {
+            (Some((protocol, _)), Some((_, key))) => localize_key(*protocol, key, &id),
+            _ => vec![],
+        };
+        usm.engine = Some(Engine {
+            id,
+            boots,
+            time,
+            synced_at: Instant::now(),
+            auth_key,
+            privacy_key,
+        });
+        Ok(())
+    }
+
+    /// Builds an authenticated and encrypted message as configured, returning it with its ID.
+    fn v3_message(&mut self, pdu: Vec<u8>) -> Result<(Vec<u8>, i32)> {
+        let msg_id = self.next_request_id();
+        let Security::Usm(usm) = &mut self.security else {
+            unreachable!()
+        };
+        let engine = usm.engine.as_ref().expect("engine not discovered");
+        let (boots, time) = (engine.boots, engine.time());
+        let scoped = scoped_pdu(&engine.id, &pdu);
+        let mut flags = FLAG_REPORTABLE;
+        let (data, privacy_parameters) = match &usm.privacy {
+            Some((protocol, _)) => {
+                flags |= FLAG_PRIV;
+                usm.salt = usm.salt.wrapping_add(1);
+                let (encrypted, salt) = encrypt(
+                    *protocol,
+                    &engine.privacy_key,
+                    boots,
+                    time,
+                    usm.salt,
+                    scoped,
+                )?;
+                (tlv(OCTET_STRING, &encrypted), salt)
+            }
+            None => (scoped, vec![]),
+        };
+        let auth_placeholder = match usm.auth {
+            Some(_) => {
+                flags |= FLAG_AUTH;
+                vec![0; 12]
+            }
+            None => vec![],
+        };
+        let security_parameters = usm_parameters(
+            &engine.id,
+            boots,
+            time,
+            &usm.username,
+            &auth_placeholder,
+            &privacy_parameters,
+        );
+        let mut message = v3_message(msg_id, flags, &security_parameters, &data);
+        if let Some((protocol, _)) = usm.auth {
+            let range = V3Message::decode(&message)?.auth_parameters;
+            let digest = authenticate(protocol, &engine.auth_key, &message);
+            message[range].copy_from_slice(&digest);
+        }
+        Ok((message, msg_id))
+    }
+
+    /// Sends a message and returns the tag and content of the PDU received in response, after
+    /// checking its authentication and decrypting it.
+    fn exchange_v3(&mut self, message: &[u8], msg_id: i32) -> Result<(u8, Vec<u8>)> {
+        let response = self.exchange(message, |response| {
+            let parsed = V3Message::decode(response)?;
+            Ok((parsed.msg_id == msg_id as i64).then(|| response.to_vec()))
+        })?;
+        let parsed = V3Message::decode(&response)?;
+        let Security::Usm(usm) = &mut self.security else {
+            unreachable!()
+        };
+        let engine = usm.engine.as_mut().expect("engine not discovered");
+        let authenticated = parsed.flags & FLAG_AUTH != 0;
+        if let (Some((protocol, _)), true) = (&usm.auth, authenticated) {
+            let mut zeroed = response.clone();
+            zeroed[parsed.auth_parameters.clone()].fill(0);
+            if authenticate(*protocol, &engine.auth_key, &zeroed)
+                != response[parsed.auth_parameters]
+            {
+                bail!(anyhow!("response authentication failed"));
+            }
+            // Authenticated messages carry the current boots and time of the engine
+            engine.boots = parsed.boots;
+            engine.time = parsed.time;
+            engine.synced_at = Instant::now();
+        }
+        let scoped = if parsed.flags & FLAG_PRIV != 0 {
+            let Some((protocol, _)) = usm.privacy else {
+                bail!(anyhow!(
+                    "encrypted response while privacy is not configured"
+                ));
+            };
+            let encrypted = Reader::new(parsed.data).read(OCTET_STRING)?;
+            decrypt(
+                protocol,
+                &engine.privacy_key,
+                parsed.boots,
+                parsed.time,
+                parsed.privacy_parameters,
+                encrypted,
+            )?
+        } else {
+            parsed.data.to_vec()
+        };
+        // Trailing bytes are the padding of DES encryption
+        let mut scoped = Reader::new(&scoped).sequence()?;
+        scoped.read(OCTET_STRING)?;
+        scoped.read(OCTET_STRING)?;
+        let (tag, pdu) = scoped.read_any()?;
+        // Reports about authentication failures are not authenticated themselves
+        if usm.auth.is_some() && !authenticated && tag != REPORT {
+            bail!(anyhow!("unauthenticated response"));
+        }
+        Ok((tag, pdu.to_vec()))
+    }
+
+    /// Sends a message, then waits for a response accepted by `accept`. Other responses, such as
+    /// late ones to previous requests, are ignored.
+    fn exchange<T>(
+        &mut self,
+        message: &[u8],
+        accept: impl Fn(&[u8]) -> Result<Option<T>>,
+    ) -> Result<T> {
+        let socket = match &self.socket {
+            Some(socket) => socket,
+            None => {
+                let socket = connect(&self.address)?;
+                self.socket.insert(socket)
+            }
+        };
+        socket
+            .send(message)
+            .with_context(|| format!("Cannot send request to {}", self.address))?;
+        let deadline = Instant::now() + self.timeout;
+        let mut buf = vec![0; MAX_MESSAGE_SIZE];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                bail!(anyhow!("no response from {}", self.address));
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            let len = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        || e.kind() == std::io::ErrorKind::TimedOut =>
+                {
+                    continue
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("No response from {}", self.address))
+                }
+            };
+            if let Some(response) = accept(&buf[..len])
+                .with_context(|| format!("Invalid response from {}", self.address))?
+            {
+                return Ok(response);
+            }
+        }
+    }
+}
+
+fn connect(address: &str) -> Result<UdpSocket> {
+    let addr = address
+        .to_socket_addrs()
+        .with_context(|| format!("Cannot resolve {address}"))?
+        .next()
+        .ok_or_else(|| anyhow!("{address} does not resolve to any address"))?;
+    let local = if addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(local).context("Cannot open UDP socket")?;
+    socket
+        .connect(addr)
+        .with_context(|| format!("Cannot connect to {address}"))?;
+    Ok(socket)
+}
+
+/// SNMPv3 message, as received
+struct V3Message<'a> {
+    msg_id: i64,
+    flags: u8,
+    engine_id: &'a [u8],
+    boots: u32,
+    time: u32,
+    /// Position of the authentication parameters in the message
+    auth_parameters: Range<usize>,
+    privacy_parameters: &'a [u8],
+    /// Scoped PDU, or the octet string holding it encrypted
+    data: &'a [u8],
+}
+
+impl<'a> V3Message<'a> {
+    fn decode(message: &'a [u8]) -> Result<V3Message<'a>> {
+        let mut reader = Reader::new(message).sequence()?;
+        if reader.integer()? != 3 {
+            bail!(anyhow!("not an SNMPv3 message"));
+        }
+        let mut header = reader.sequence()?;
+        let msg_id = header.integer()?;
+        header.integer()?;
+        let flags = *header.read(OCTET_STRING)?.first().unwrap_or(&0);
+        if header.integer()? != 3 {
+            bail!(anyhow!("unsupported security model"));
+        }
+        let mut usm = Reader::new(reader.read(OCTET_STRING)?).sequence()?;
+        let engine_id = usm.read(OCTET_STRING)?;
+        let boots = usm.integer()?.clamp(0, u32::MAX as i64) as u32;
+        let time = usm.integer()?.clamp(0, u32::MAX as i64) as u32;
+        usm.read(OCTET_STRING)?;
+        let auth = usm.read(OCTET_STRING)?;
+        let start = auth.as_ptr() as usize - message.as_ptr() as usize;
+        let privacy_parameters = usm.read(OCTET_STRING)?;
+        Ok(V3Message {
+            msg_id,
+            flags,
+            engine_id,
+            boots,
+            time,
+            auth_parameters: start..start + auth.len(),
+            privacy_parameters,
+            data: reader.rest(),
+        })
+    }
+}
+
+fn v3_message(msg_id: i32, flags: u8, security_parameters: &[u8], data: &[u8]) -> Vec<u8> {
+    let header = [
+        integer(msg_id as i64),
+        integer(MAX_MESSAGE_SIZE as i64),
+        tlv(OCTET_STRING, &[flags]),
+        // User-based security model
+        integer(3),
+    ]
+    .concat();
+    tlv(
+        SEQUENCE,
+        &[
+            integer(3),
+            tlv(SEQUENCE, &header),
+            tlv(OCTET_STRING, security_parameters),
+            data.to_vec(),
+        ]
+        .concat(),
+    )
+}
+
+fn usm_parameters(
+    engine_id: &[u8],
+    boots: u32,
+    time: u32,
+    username: &[u8],
+    auth: &[u8],
+    privacy: &[u8],
+) -> Vec<u8> {
+    tlv(
+        SEQUENCE,
+        &[
+            tlv(OCTET_STRING, engine_id),
+            integer(boots as i64),
+            integer(time as i64),
+            tlv(OCTET_STRING, username),
+            tlv(OCTET_STRING, auth),
+            tlv(OCTET_STRING, privacy),
+        ]
+        .concat(),
+    )
+}
+
+/// PDU with the engine ID as context engine ID and the default context
+fn scoped_pdu(engine_id: &[u8], pdu: &[u8]) -> Vec<u8> {
+    tlv(
+        SEQUENCE,
+        &[
+            tlv(OCTET_STRING, engine_id),
+            tlv(OCTET_STRING, &[]),
+            pdu.to_vec(),
+        ]
+        .concat(),
+    )
+}
+
+fn get_request(request_id: i32, oids: &[Vec<u32>]) -> Vec<u8> {
+    let bindings: Vec<u8> = oids
+        .iter()
+        .flat_map(|oid| tlv(SEQUENCE, &[encode_oid(oid), tlv(NULL, &[])].concat()))
+        .collect();
+    tlv(
+        GET_REQUEST,
+        &[
+            integer(request_id as i64),
+            integer(0),
+            integer(0),
+            tlv(SEQUENCE, &bindings),
+        ]
+        .concat(),
+    )
+}
+
+fn pdu_request_id(pdu: &[u8]) -> Result<i32> {
+    Ok(Reader::new(pdu).integer()? as i32)
+}
+
+/// Decodes the variable bindings of a response or report PDU, failing on error statuses.
+fn decode_bindings(pdu: &[u8]) -> Result<Vec<(Vec<u32>, Value)>> {
+    let mut pdu = Reader::new(pdu);
+    pdu.integer()?;
+    let status = pdu.integer()?;
+    let index = pdu.integer()?;
+    if status != 0 {
+        bail!(anyhow!(
+            "agent returned error {} for object {index}",
+            error_name(status)
+        ));
+    }
+    let mut list = pdu.sequence()?;
+    let mut bindings = vec![];
+    while !list.is_empty() {
+        let mut binding = list.sequence()?;
+        let oid = decode_oid(binding.read(OBJECT_IDENTIFIER)?)?;
+        let (tag, content) = binding.read_any()?;
+        bindings.push((oid, Value::decode(tag, content)?));
+    }
+    Ok(bindings)
+}
+
+fn error_name(status: i64) -> String {
+    let name = match status {
+        1 => "tooBig",
+        2 => "noSuchName",
+        3 => "badValue",
+        4 => "readOnly",
+        5 => "genErr",
+        6 => "noAccess",
+        16 => "authorizationError",
+        _ => return status.to_string(),
+    };
+    name.to_owned()
+}
+
+fn report_name(oid: &[u32]) -> String {
+    match oid {
+        [1, 3, 6, 1, 6, 3, 15, 1, 1, 1, 0] => "an unsupported security level".to_owned(),
+        [1, 3, 6, 1, 6, 3, 15, 1, 1, 3, 0] => "an unknown user name".to_owned(),
+        [1, 3, 6, 1, 6, 3, 15, 1, 1, 5, 0] => "a wrong digest, check the password".to_owned(),
+        [1, 3, 6, 1, 6, 3, 15, 1, 1, 6, 0] => "a decryption error, check the password".to_owned(),
+        oid => format_oid(oid),
+    }
+}
+
+fn check_password(password: &str) -> Result<()> {
+    if password.len() < 8 {
+        bail!(anyhow!("SNMPv3 passwords must have at least 8 characters"));
+    }
+    Ok(())
+}
+
+/// Key derived from a password, by hashing it repeated over 1 MB
+fn password_to_key(protocol: SnmpAuthProtocol, password: &str) -> Vec<u8> {
+    fn derive<D: Digest>(password: &[u8]) -> Vec<u8> {
+        let mut hasher = D::new();
+        let repeated: Vec<u8> = password.iter().cycle().take(1 << 20).copied().collect();
+        hasher.update(&repeated);
+        hasher.finalize().to_vec()
+    }
+    match protocol {
+        SnmpAuthProtocol::Md5 => derive::<Md5>(password.as_bytes()),
+        SnmpAuthProtocol::Sha => derive::<Sha1>(password.as_bytes()),
+    }
+}
+
+/// Key specific to an engine, so that a key leaked by an agent cannot be used with others
+fn localize_key(protocol: SnmpAuthProtocol, key: &[u8], engine_id: &[u8]) -> Vec<u8> {
+    let data = [key, engine_id, key].concat();
+    match protocol {
+        SnmpAuthProtocol::Md5 => Md5::digest(&data).to_vec(),
+        SnmpAuthProtocol::Sha => Sha1::digest(&data).to_vec(),
+    }
+}
+
+/// HMAC-MD5-96 or HMAC-SHA-96 of a message
+fn authenticate(protocol: SnmpAuthProtocol, key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut digest = match protocol {
+        SnmpAuthProtocol::Md5 => {
+            let mut mac = Hmac::<Md5>::new_from_slice(key).expect("any key size is valid");
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        }
+        SnmpAuthProtocol::Sha => {
+            let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("any key size is valid");
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        }
+    };
+    digest.truncate(12);
+    digest
+}
+
+/// Encrypts a scoped PDU, returning it with the privacy parameters to send along.
+fn encrypt(
+    protocol: SnmpPrivacyProtocol,
+    key: &[u8],
+    boots: u32,
+    time: u32,
+    salt: u64,
+    mut data: Vec<u8>,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let invalid_key = |_| anyhow!("invalid privacy key");
+    match protocol {
+        SnmpPrivacyProtocol::Aes => {
+            let salt = salt.to_be_bytes();
+            let iv = [&boots.to_be_bytes()[..], &time.to_be_bytes(), &salt].concat();
+            cfb_mode::Encryptor::<Aes128>::new_from_slices(&key[..16], &iv)
+                .map_err(invalid_key)?
+                .encrypt(&mut data);
+            Ok((data, salt.to_vec()))
+        }
+        SnmpPrivacyProtocol::Des => {
+            let salt = [boots.to_be_bytes(), (salt as u32).to_be_bytes()].concat();
+            let iv: Vec<u8> = key[8..16].iter().zip(&salt).map(|(a, b)| a ^ b).collect();
+            let len = data.len();
+            data.resize(len.next_multiple_of(8), 0);
+            let len = data.len();
+            cbc::Encryptor::<Des>::new_from_slices(&key[..8], &iv)
+                .map_err(invalid_key)?
+                .encrypt_padded_mut::<NoPadding>(&mut data, len)
+                .map_err(|_| anyhow!("cannot encrypt"))?;
+            Ok((data, salt))
+        }
+    }
+}
+
+fn decrypt(
+    protocol: SnmpPrivacyProtocol,
+    key: &[u8],
+    boots: u32,
+    time: u32,
+    privacy_parameters: &[u8],
+    encrypted: &[u8],
+) -> Result<Vec<u8>> {
+    if privacy_parameters.len() != 8 {
+        bail!(anyhow!("invalid privacy parameters"));
+    }
+    let invalid_key = |_| anyhow!("invalid privacy key");
+    let mut data = encrypted.to_vec();
+    match protocol {
+        SnmpPrivacyProtocol::Aes => {
+            let iv = [
+                &boots.to_be_bytes()[..],
+                &time.to_be_bytes(),
+                privacy_parameters,
+            ]
+            .concat();
+            cfb_mode::Decryptor::<Aes128>::new_from_slices(&key[..16], &iv)
+                .map_err(invalid_key)?
+                .decrypt(&mut data);
+        }
+        SnmpPrivacyProtocol::Des => {
+            let iv: Vec<u8> = key[8..16]
+                .iter()
+                .zip(privacy_parameters)
+                .map(|(a, b)| a ^ b)
+                .collect();
+            cbc::Decryptor::<Des>::new_from_slices(&key[..8], &iv)
+                .map_err(invalid_key)?
+                .decrypt_padded_mut::<NoPadding>(&mut data)
+                .map_err(|_| anyhow!("encrypted data is not a whole number of blocks"))?;
+        }
+    }
+    Ok(data)
+}
+
+/// Seed for the request IDs and the encryption salts
+fn seed() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    now.as_nanos() as u64 ^ (std::process::id() as u64) << 32
+}
+
+/// Reader of BER encoded data
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Reads the next element, returning its tag and content.
+    fn read_any(&mut self) -> Result<(u8, &'a [u8])> {
+        let truncated = || anyhow!("truncated data");
+        let (&tag, rest) = self.data.split_first().ok_or_else(truncated)?;
+        let (&first, mut rest) = rest.split_first().ok_or_else(truncated)?;
+        let len = if first < 0x80 {
+            first as usize
+        } else {
+            let count = (first & 0x7F) as usize;
+            if count == 0 || count > 4 || rest.len() < count {
+                bail!(anyhow!("invalid length"));
+            }
+            let (bytes, after) = rest.split_at(count);
+            rest = after;
+            bytes.iter().fold(0, |len, b| len << 8 | *b as usize)
+        };
+        if rest.len() < len {
+            bail!(truncated());
+        }
+        let (content, rest) = rest.split_at(len);
+        self.data = rest;
+        Ok((tag, content))
+    }
+
+    fn read(&mut self, tag: u8) -> Result<&'a [u8]> {
+        let (found, content) = self.read_any()?;
+        if found != tag {
+            bail!(anyhow!("expected tag {tag:#04x}, found {found:#04x}"));
+        }
+        Ok(content)
+    }
+
+    fn sequence(&mut self) -> Result<Reader<'a>> {
+        Ok(Reader::new(self.read(SEQUENCE)?))
+    }
+
+    fn integer(&mut self) -> Result<i64> {
+        decode_integer(self.read(INTEGER)?)
+    }
+}
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        encoded.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        encoded.push(0x80 | (bytes.len() - skip) as u8);
+        encoded.extend(&bytes[skip..]);
+    }
+    encoded.extend(content);
+    encoded
+}
+
+fn integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // Shortest two's complement form
+    let mut start = 0;
+    while start < 7
+        && ((bytes[start] == 0 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xFF && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    tlv(INTEGER, &bytes[start..])
+}
+
+fn decode_integer(content: &[u8]) -> Result<i64> {
+    if content.is_empty() || content.len() > 8 {
+        bail!(anyhow!("invalid integer length {}", content.len()));
+    }
+    let sign = if content[0] & 0x80 != 0 { -1 } else { 0 };
+    Ok(content.iter().fold(sign, |v, b| v << 8 | *b as i64))
+}
+
+fn decode_unsigned(content: &[u8]) -> Result<u64> {
+    let content = match content {
+        [0, rest @ ..] => rest,
+        content => content,
+    };
+    if content.len() > 8 {
+        bail!(anyhow!("invalid unsigned integer length {}", content.len()));
+    }
+    Ok(content.iter().fold(0, |v, b| v << 8 | *b as u64))
+}
+
+fn parse_oid(oid: &str) -> Result<Vec<u32>> {
+    let arcs = oid
+        .trim_start_matches('.')
+        .split('.')
+        .map(|arc| arc.parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| anyhow!("{oid} is not a numeric OID"))?;
+    if arcs.len() < 2 || arcs[0] > 2 || (arcs[0] < 2 && arcs[1] >= 40) {
+        bail!(anyhow!("{oid} is not a valid OID"));
+    }
+    Ok(arcs)
+}
+
+fn format_oid(oid: &[u32]) -> String {
+    oid.iter()
+        .map(|arc| arc.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn encode_oid(oid: &[u32]) -> Vec<u8> {
+    let mut content = vec![];
+    let first = oid[0] * 40 + oid[1];
+    for arc in std::iter::once(first).chain(oid[2..].iter().copied()) {
+        let mut bytes = vec![(arc & 0x7F) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            bytes.push(0x80 | (rest & 0x7F) as u8);
+            rest >>= 7;
+        }
+        content.extend(bytes.iter().rev());
+    }
+    tlv(OBJECT_IDENTIFIER, &content)
+}
+
+fn decode_oid(content: &[u8]) -> Result<Vec<u32>> {
+    let mut arcs = vec![];
+    let mut arc: u32 = 0;
+    for b in content {
+        if arc > u32::MAX >> 7 {
+            bail!(anyhow!("OID arc out of range"));
+        }
+        arc = arc << 7 | (b & 0x7F) as u32;
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (arc / 40).min(2);
+                arcs.extend([first, arc - 40 * first]);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        }
+    }
+    if arcs.is_empty() {
+        bail!(anyhow!("empty OID"));
+    }
+    Ok(arcs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::{spawn, JoinHandle};
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Key localization examples of RFC 3414 A.3
+    #[test]
+    fn rfc3414_keys() {
+        let engine_id = hex("000000000000000000000002");
+        let key = password_to_key(SnmpAuthProtocol::Md5, "maplesyrup");
+        assert_eq!(key, hex("9faf3283884e92834ebc9847d8edd963"));
+        assert_eq!(
+            localize_key(SnmpAuthProtocol::Md5, &key, &engine_id),
+            hex("526f5eed9fcce26f8964c2930787d82b")
+        );
+        let key = password_to_key(SnmpAuthProtocol::Sha, "maplesyrup");
+        assert_eq!(key, hex("9fb5cc0381497b3793528939ff788d5d79145211"));
+        assert_eq!(
+            localize_key(SnmpAuthProtocol::Sha, &key, &engine_id),
+            hex("6695febc9288e36282235fc7151f128497b38f3f")
+        );
+    }
+
+    #[test]
+    fn ber_length() {
+        for (len, header) in [
+            (0, vec![0x04, 0x00]),
+            (0x7F, vec![0x04, 0x7F]),
+            (0x80, vec![0x04, 0x81, 0x80]),
+            (0xFF, vec![0x04, 0x81, 0xFF]),
+            (0x100, vec![0x04, 0x82, 0x01, 0x00]),
+            (70000, vec![0x04, 0x83, 0x01, 0x11, 0x70]),
+        ] {
+            let content = vec![0xA5; len];
+            let encoded = tlv(OCTET_STRING, &content);
+            assert_eq!(encoded[..header.len()], header, "length {len}");
+            let mut reader = Reader::new(&encoded);
+            assert_eq!(reader.read(OCTET_STRING).unwrap(), content);
+            assert!(reader.is_empty());
+        }
+        // Truncated content, length of length missing or too long
+        assert!(Reader::new(&[0x04, 0x03, 0x01]).read_any().is_err());
+        assert!(Reader::new(&[0x04, 0x82, 0x01]).read_any().is_err());
+        assert!(Reader::new(&[0x04, 0x80]).read_any().is_err());
+        assert!(Reader::new(&[0x04, 0x85, 0, 0, 0, 0, 1, 0])
+            .read_any()
+            .is_err());
+        assert!(Reader::new(&[0x04]).read_any().is_err());
+    }
+
+    #[test]
+    fn ber_integer() {
+        for (value, encoded) in [
+            (0, vec![0x00]),
+            (127, vec![0x7F]),
+            (128, vec![0x00, 0x80]),
+            (256, vec![0x01, 0x00]),
+            (-1, vec![0xFF]),
+            (-128, vec![0x80]),
+            (-129, vec![0xFF, 0x7F]),
+            (i64::MAX, hex("7fffffffffffffff")),
+            (i64::MIN, hex("8000000000000000")),
+        ] {
+            assert_eq!(integer(value), tlv(INTEGER, &encoded), "{value}");
+            assert_eq!(Reader::new(&integer(value)).integer().unwrap(), value);
+        }
+        assert!(decode_integer(&[]).is_err());
+        assert!(decode_integer(&[0; 9]).is_err());
+    }
+
+    /// Unsigned integer of an application type, as sent by agents
+    fn unsigned(tag: u8, value: u64) -> Vec<u8> {
+        let bytes = value.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count().min(7);
+        let mut content = bytes[skip..].to_vec();
+        if content[0] & 0x80 != 0 {
+            content.insert(0, 0);
+        }
+        tlv(tag, &content)
+    }
+
+    #[test]
+    fn ber_counter64() {
+        for value in [0, 0x80, 0xFFFF_FFFF, 1 << 32, u64::MAX] {
+            let encoded = unsigned(COUNTER64, value);
+            let (tag, content) = Reader::new(&encoded).read_any().unwrap();
+            assert!(matches!(Value::decode(tag, content), Ok(Value::Counter64(v)) if v == value));
+        }
+        assert_eq!(unsigned(COUNTER64, u64::MAX)[1], 9);
+        assert!(decode_unsigned(&[1; 9]).is_err());
+        let encoded = unsigned(COUNTER32, 1 << 32);
+        let (tag, content) = Reader::new(&encoded).read_any().unwrap();
+        assert!(Value::decode(tag, content).is_err());
+    }
+
+    #[test]
+    fn ber_oid() {
+        for (oid, content) in [
+            ("1.3.6.1.2.1.1.3.0", hex("2b06010201010300")),
+            // Sub-identifiers of two and three bytes
+            ("1.3.6.1.4.1.2021.10.1.3.1", hex("2b060104018f650a010301")),
+            ("1.3.6.1.4.1.16384.1", hex("2b0601040181800001")),
+            ("2.999.3", hex("883703")),
+            ("1.3.6.1.4294967295", hex("2b06018fffffff7f")),
+        ] {
+            let arcs = parse_oid(oid).unwrap();
+            assert_eq!(encode_oid(&arcs), tlv(OBJECT_IDENTIFIER, &content), "{oid}");
+            assert_eq!(format_oid(&decode_oid(&content).unwrap()), oid);
+        }
+        assert_eq!(parse_oid(".1.3.6.1").unwrap(), [1, 3, 6, 1]);
+        assert!(parse_oid("1.3.six.1").is_err());
+        assert!(parse_oid("1.40.1").is_err());
+        assert!(decode_oid(&[]).is_err());
+        assert!(decode_oid(&hex("2b0690ffffffff7f")).is_err());
+    }
+
+    const UPTIME: &str = "1.3.6.1.2.1.1.3.0";
+    const LOAD: &str = "1.3.6.1.4.1.2021.10.1.3.1";
+    const TEMPERATURE: &str = "1.3.6.1.2.1.99.1.1.1.4.1";
+    const CHARGE: &str = "1.3.6.1.2.1.33.1.2.4.0";
+    const MISSING: &str = "1.3.6.1.2.1.1.99.0";
+
+    /// Response of the test agent to the get request in `pdu`
+    fn get_response(pdu: &[u8]) -> Result<Vec<u8>> {
+        let (tag, content) = Reader::new(pdu).read_any()?;
+        assert_eq!(tag, GET_REQUEST);
+        let mut request = Reader::new(content);
+        let request_id = request.integer()?;
+        request.integer()?;
+        request.integer()?;
+        let mut list = request.sequence()?;
+        let mut bindings = vec![];
+        while !list.is_empty() {
+            let mut binding = list.sequence()?;
+            let oid = decode_oid(binding.read(OBJECT_IDENTIFIER)?)?;
+            binding.read(NULL)?;
+            let value = match format_oid(&oid).as_str() {
+                UPTIME => unsigned(TIME_TICKS, 123456),
+                LOAD => tlv(OCTET_STRING, b"0.42"),
+                TEMPERATURE => integer(-125),
+                CHARGE => unsigned(GAUGE32, 87),
+                _ => tlv(NO_SUCH_INSTANCE, &[]),
+            };
+            bindings.extend(tlv(SEQUENCE, &[encode_oid(&oid), value].concat()));
+        }
+        Ok(tlv(
+            RESPONSE,
+            &[
+                integer(request_id),
+                integer(0),
+                integer(0),
+                tlv(SEQUENCE, &bindings),
+            ]
+            .concat(),
+        ))
+    }
+
+    /// Agent answering `count` messages on a local port
+    fn agent(
+        count: usize,
+        respond: impl Fn(&[u8]) -> Result<Vec<u8>> + Send + 'static,
+    ) -> (u16, JoinHandle<Result<()>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let thread = spawn(move || {
+            let mut buf = vec![0; MAX_MESSAGE_SIZE];
+            for _ in 0..count {
+                let (len, peer) = socket.recv_from(&mut buf)?;
+                socket.send_to(&respond(&buf[..len])?, peer)?;
+            }
+            Ok(())
+        });
+        (port, thread)
+    }
+
+    fn config(port: u16, version: &str) -> SnmpConfig {
+        serde_json::from_str(&format!(
+            r#"{{
+                "host": "127.0.0.1",
+                "port": {port},
+                "version": {version},
+                "timeout": "2s",
+                "series": {{
+                    "uptime": {{"oid": "{UPTIME}"}},
+                    "load": {{"oid": "{LOAD}"}},
+                    "temperature": {{"oid": "{TEMPERATURE}", "scale": 0.1}},
+                    "charge": {{"oid": "{CHARGE}", "scale": 0.01, "offset": 1}},
+                    "missing": {{"oid": "{MISSING}"}}
+                }}
+            }}"#
+        ))
+        .unwrap()
+    }
+
+    fn check_samples(snmp: &mut Snmp) {
+        assert!((snmp.sample("uptime").unwrap() - 1234.56).abs() < 1e-9);
+        assert_eq!(snmp.sample("load").unwrap(), 0.42);
+        assert_eq!(snmp.sample("temperature").unwrap(), -12.5);
+        assert!((snmp.sample("charge").unwrap() - 1.87).abs() < 1e-9);
+        let err = snmp.sample("missing").unwrap_err();
+        assert!(err.to_string().contains("no such instance"), "{err}");
+    }
+
+    #[test]
+    fn v2c_get() {
+        let (port, thread) = agent(1, |message| {
+            let mut message = Reader::new(message).sequence()?;
+            assert_eq!(message.integer()?, 1);
+            assert_eq!(message.read(OCTET_STRING)?, b"public");
+            let pdu = get_response(message.rest())?;
+            Ok(tlv(
+                SEQUENCE,
+                &[integer(1), tlv(OCTET_STRING, b"public"), pdu].concat(),
+            ))
+        });
+        let mut snmp = Snmp::new(config(port, r#"{"v2c": {"community": "public"}}"#)).unwrap();
+        check_samples(&mut snmp);
+        thread.join().unwrap().unwrap();
+    }
+
+    /// Agent using the user-based security model with authentication and privacy
+    fn v3_agent(
+        auth: SnmpAuthProtocol,
+        privacy: SnmpPrivacyProtocol,
+    ) -> (u16, JoinHandle<Result<()>>) {
+        let engine_id = b"\x80\x00\x1f\x88\x04test-agent".to_vec();
+        let (boots, time) = (7, 1234);
+        let auth_key = localize_key(auth, &password_to_key(auth, "auth-password"), &engine_id);
+        let privacy_key =
+            localize_key(auth, &password_to_key(auth, "privacy-password"), &engine_id);
+        // Discovery, then the authenticated request
+        agent(2, move |message| {
+            let parsed = V3Message::decode(message)?;
+            if parsed.engine_id.is_empty() {
+                assert_eq!(parsed.flags, FLAG_REPORTABLE);
+                let mut scoped = Reader::new(parsed.data).sequence()?;
+                scoped.read(OCTET_STRING)?;
+                scoped.read(OCTET_STRING)?;
+                let (_, pdu) = scoped.read_any()?;
+                let binding = [encode_oid(UNKNOWN_ENGINE_ID), unsigned(COUNTER32, 1)].concat();
+                let report = tlv(
+                    REPORT,
+                    &[
+                        integer(pdu_request_id(pdu)? as i64),
+                        integer(0),
+                        integer(0),
+                        tlv(SEQUENCE, &tlv(SEQUENCE, &binding)),
+                    ]
+                    .concat(),
+                );
+                let parameters = usm_parameters(&engine_id, boots, time, &[], &[], &[]);
+                return Ok(v3_message(
+                    parsed.msg_id as i32,
+                    0,
+                    &parameters,
+                    &scoped_pdu(&engine_id, &report),
+                ));
+            }
+
+            assert_eq!(parsed.flags, FLAG_AUTH | FLAG_PRIV | FLAG_REPORTABLE);
+            assert_eq!(parsed.engine_id, engine_id);
+            assert_eq!(parsed.boots, boots);
+            let mut zeroed = message.to_vec();
+            zeroed[parsed.auth_parameters.clone()].fill(0);
+            assert_eq!(
+                authenticate(auth, &auth_key, &zeroed),
+                message[parsed.auth_parameters.clone()]
+            );
+            let encrypted = Reader::new(parsed.data).read(OCTET_STRING)?;
+            let scoped = decrypt(
+                privacy,
+                &privacy_key,
+                parsed.boots,
+                parsed.time,
+                parsed.privacy_parameters,
+                encrypted,
+            )?;
+            let mut scoped = Reader::new(&scoped).sequence()?;
+            assert_eq!(scoped.read(OCTET_STRING)?, engine_id);
+            scoped.read(OCTET_STRING)?;
+            let response = get_response(scoped.rest())?;
+
+            let (encrypted, salt) = encrypt(
+                privacy,
+                &privacy_key,
+                boots,
+                time,
+                42,
+                scoped_pdu(&engine_id, &response),
+            )?;
+            let parameters = usm_parameters(&engine_id, boots, time, b"monitor", &[0; 12], &salt);
+            let mut response = v3_message(
+                parsed.msg_id as i32,
+                FLAG_AUTH | FLAG_PRIV,
+                &parameters,
+                &tlv(OCTET_STRING, &encrypted),
+            );
+            let range = V3Message::decode(&response)?.auth_parameters;
+            let digest = authenticate(auth, &auth_key, &response);
+            response[range].copy_from_slice(&digest);
+            Ok(response)
+        })
+    }
+
+    #[test]
+    fn v3_auth_priv_get() {
+        for (auth, privacy, version) in [
+            (
+                SnmpAuthProtocol::Sha,
+                SnmpPrivacyProtocol::Aes,
+                r#"{"v3": {"username": "monitor", "auth": {"password": "auth-password"}, "privacy": {"password": "privacy-password"}}}"#,
+            ),
+            (
+                SnmpAuthProtocol::Md5,
+                SnmpPrivacyProtocol::Des,
+                r#"{"v3": {"username": "monitor", "auth": {"protocol": "md5", "password": "auth-password"}, "privacy": {"protocol": "des", "password": "privacy-password"}}}"#,
+            ),
+        ] {
+            let (port, thread) = v3_agent(auth, privacy);
+            let mut snmp = Snmp::new(config(port, version)).unwrap();
+            check_samples(&mut snmp);
+            thread.join().unwrap().unwrap();
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::config::SystemConfig;
+use crate::sensors::Sensor;
+use anyhow::{anyhow, Context, Result};
+use nix::sys::statvfs::statvfs;
+
+/// Health metrics of the host, read from /proc and /sys
+pub struct System {
+    config: SystemConfig,
+    root: PathBuf,
+    /// Path on the partition whose free space is measured
+    disk_path: PathBuf,
+    /// Sectors read and written at the previous disk I/O measurement
+    disk_read: Counter,
+    disk_write: Counter,
+}
+
+impl System {
+    pub fn new(config: SystemConfig, db_path: &str) -> Result<System> {
+        // The partition of the database is monitored by default
+        let disk_path = match &config.disk_path {
+            Some(path) => PathBuf::from(path),
+            None => match Path::new(db_path).parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                _ => PathBuf::from("."),
+            },
+        };
+        let mut system = System {
+            root: PathBuf::from(&config.root),
+            disk_path,
+            config,
+            disk_read: Counter::default(),
+            disk_write: Counter::default(),
+        };
+        // Prime the counters, so that the first sample already gives a rate
+        if system.config.disk_read_series.is_some() || system.config.disk_write_series.is_some() {
+            let (read, written) = system.disk_sectors()?;
+            system.disk_read.rate(read);
+            system.disk_write.rate(written);
+        }
+        Ok(system)
+    }
+
+    fn read(&self, path: &str) -> Result<String> {
+        let path = self.root.join(path);
+        fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read file at {}", path.display()))
+    }
+
+    fn cpu_temperature(&self) -> Result<f64> {
+        let content = self.read("sys/class/thermal/thermal_zone0/temp")?;
+        Ok(parse_number(content.trim())? / 1000.0)
+    }
+
+    fn load_average(&self) -> Result<f64> {
+        let content = self.read("proc/loadavg")?;
+        parse_number(field(&content, 0)?)
+    }
+
+    fn memory_usage(&self) -> Result<f64> {
+        let content = self.read("proc/meminfo")?;
+        let value = |key: &str| -> Result<f64> {
+            let line = content
+                .lines()
+                .find(|l| l.starts_with(key))
+                .ok_or_else(|| anyhow!("No {key} in meminfo"))?;
+            parse_number(field(line, 1)?)
+        };
+        let total = value("MemTotal:")?;
+        let available = value("MemAvailable:")?;
+        Ok((total - available) / total * 100.0)
+    }
+
+    fn disk_free(&self) -> Result<f64> {
+        let path = &self.disk_path;
+        let stat = statvfs(path)
+            .with_context(|| format!("Cannot get file system statistics of {}", path.display()))?;
+        Ok(stat.blocks_available() as f64 * stat.fragment_size() as f64 / 1e6)
+    }
+
+    /// Sectors read and written since boot on the block device
+    fn disk_sectors(&self) -> Result<(f64, f64)> {
+        let content = self.read("proc/diskstats")?;
+        let line = content
+            .lines()
+            .find(|l| l.split_whitespace().nth(2) == Some(&self.config.block_device))
+            .ok_or_else(|| anyhow!("No {} block device in diskstats", self.config.block_device))?;
+        Ok((
+            parse_number(field(line, 5)?)?,
+            parse_number(field(line, 9)?)?,
+        ))
+    }
+
+    fn throttled(&self) -> Result<f64> {
+        let content = self.read("sys/devices/platform/soc/soc:firmware/get_throttled")?;
+        let raw = content.trim().trim_start_matches("0x");
+        let flags = u32::from_str_radix(raw, 16).with_context(|| {
+            format!(
+                "Failed to parse throttling flags from '{}'",
+                raw.escape_debug()
+            )
+        })?;
+        Ok(flags as f64)
+    }
+
+    fn uptime(&self) -> Result<f64> {
+        let content = self.read("proc/uptime")?;
+        parse_number(field(&content, 0)?)
+    }
+}
+
+impl Sensor for System {
+    fn sample(&mut self, series: &str) -> Result<f64> {
+        let is = |s: &Option<String>| s.as_ref().is_some_and(|s| s == series);
+        if is(&self.config.cpu_temperature_series) {
+            self.cpu_temperature()
+        } else if is(&self.config.load_average_series) {
+            self.load_average()
+        } else if is(&self.config.memory_usage_series) {
+            self.memory_usage()
+        } else if is(&self.config.disk_free_series) {
+            self.disk_free()
+        } else if is(&self.config.disk_read_series) {
+            let (read, _) = self.disk_sectors()?;
+            // Sectors are 512 bytes long
+            Ok(self.disk_read.rate(read) * 512.0 / 1000.0)
+        } else if is(&self.config.disk_write_series) {
+            let (_, written) = self.disk_sectors()?;
+            Ok(self.disk_write.rate(written) * 512.0 / 1000.0)
+        } else if is(&self.config.throttled_series) {
+            self.throttled()
+        } else if is(&self.config.uptime_series) {
+            self.uptime()
+        } else {
+            Err(anyhow!("no series configured with name {series}"))
+        }
+    }
+
+    fn series(&self) -> Vec<String> {
+        [
+            &self.config.cpu_temperature_series,
+            &self.config.load_average_series,
+            &self.config.memory_usage_series,
+            &self.config.disk_free_series,
+            &self.config.disk_read_series,
+            &self.config.disk_write_series,
+            &self.config.throttled_series,
+            &self.config.uptime_series,
+        ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect()
+    }
+}
+
+/// Monotonic counter, turned into a rate per second between two measurements
+#[derive(Default)]
+struct Counter {
+    previous: Option<(Instant, f64)>,
+}
+
+impl Counter {
+    fn rate(&mut self, value: f64) -> f64 {
+        let now = Instant::now();
+        let rate = match self.previous {
+            Some((t, prev)) if value >= prev => {
+                let elapsed = (now - t).as_secs_f64();
+                if elapsed > 0.0 {
+                    (value - prev) / elapsed
+                } else {
+                    0.0
+                }
+            }
+            // Counter reset
+            _ => 0.0,
+        };
+        self.previous = Some((now, value));
+        rate
+    }
+}
+
+fn field(line: &str, index: usize) -> Result<&str> {
+    line.split_whitespace()
+        .nth(index)
+        .ok_or_else(|| anyhow!("Missing field {index} in '{}'", line.escape_debug()))
+}
+
+fn parse_number(raw: &str) -> Result<f64> {
+    raw.parse()
+        .with_context(|| format!("Failed to parse number from '{}'", raw.escape_debug()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::time::Duration;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("raspi-system-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(root: &Path, path: &str, content: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn diskstats(read: u64, written: u64) -> String {
+        format!(
+            "   1       0 ram0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n \
+             179       0 mmcblk0 12345 678 {read} 1234 5678 901 {written} 5678 0 4321 6912 0 0 0 0\n \
+             179       1 mmcblk0p1 345 1200 23456 98 2 0 2 0 0 120 98 0 0 0 0\n"
+        )
+    }
+
+    #[test]
+    fn metrics() {
+        let root = test_dir("metrics");
+        write(&root, "sys/class/thermal/thermal_zone0/temp", "48312\n");
+        write(&root, "proc/loadavg", "0.52 0.58 0.59 1/278 12345\n");
+        write(
+            &root,
+            "proc/meminfo",
+            "MemTotal:        3884428 kB\nMemFree:         1803556 kB\nMemAvailable:    2913321 kB\n",
+        );
+        write(&root, "proc/diskstats", &diskstats(2469136, 987654));
+        write(&root, "proc/uptime", "350735.47 1382190.07\n");
+        write(
+            &root,
+            "sys/devices/platform/soc/soc:firmware/get_throttled",
+            "50005\n",
+        );
+        let config: SystemConfig = serde_json::from_value(serde_json::json!({
+            "root": root,
+            "disk_path": root,
+            "cpu_temperature_series": "cpu",
+            "load_average_series": "load",
+            "memory_usage_series": "memory",
+            "disk_free_series": "disk",
+            "disk_read_series": "read",
+            "disk_write_series": "write",
+            "throttled_series": "throttled",
+            "uptime_series": "uptime"
+        }))
+        .unwrap();
+        let mut system = System::new(config.clone(), "/var/lib/raspi/raspi.db").unwrap();
+        assert_eq!(system.disk_path, root);
+        assert_eq!(system.sample("cpu").unwrap(), 48.312);
+        assert_eq!(system.sample("load").unwrap(), 0.52);
+        assert_eq!(system.sample("memory").unwrap(), 25.0);
+        assert!(system.sample("disk").unwrap() > 0.0);
+        assert_eq!(system.sample("throttled").unwrap(), 327685.0);
+        assert_eq!(system.sample("uptime").unwrap(), 350735.47);
+        assert!(system.sample("other").is_err());
+
+        // The counters are primed, so that the first samples already give a rate
+        write(&root, "proc/diskstats", &diskstats(2469136 + 2000, 987654));
+        assert!(system.sample("read").unwrap() > 0.0);
+        assert_eq!(system.sample("write").unwrap(), 0.0);
+
+        write(
+            &root,
+            "proc/diskstats",
+            &diskstats(2469136, 987654).replace("mmcblk0 ", "sda "),
+        );
+        assert!(system.sample("read").is_err());
+        assert!(System::new(config, "raspi.db").is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn disk_path() {
+        let config: SystemConfig =
+            serde_json::from_value(serde_json::json!({"root": "/nonexistent"})).unwrap();
+        let system = System::new(config.clone(), "/var/lib/raspi/raspi.db").unwrap();
+        assert_eq!(system.disk_path, Path::new("/var/lib/raspi"));
+        let system = System::new(config, "raspi.db").unwrap();
+        assert_eq!(system.disk_path, Path::new("."));
+    }
+
+    #[test]
+    fn counter_rate() {
+        let mut counter = Counter::default();
+        assert_eq!(counter.rate(100.0), 0.0);
+        counter.previous = Some((Instant::now() - Duration::from_secs(2), 100.0));
+        let rate = counter.rate(300.0);
+        assert!(rate > 99.0 && rate <= 100.0, "{rate}");
+        // Counter reset
+        assert_eq!(counter.rate(10.0), 0.0);
+    }
+}
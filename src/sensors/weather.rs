@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::config::WeatherSeries;
+
+/// Weather fields common to the weather providers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WeatherField {
+    Temperature,
+    FeelsLike,
+    DewPoint,
+    Humidity,
+    Pressure,
+    WindSpeed,
+    WindDirection,
+    WindGust,
+    Clouds,
+    Precipitation,
+    Visibility,
+}
+
+/// Values reported by a weather provider. Fields missing from a report, such as the wind gust
+/// when the wind is steady, are left out.
+pub type WeatherValues = HashMap<WeatherField, f64>;
+
+/// Series configured for each weather field
+fn configured(series: &WeatherSeries) -> Vec<(WeatherField, &String)> {
+    [
+        (WeatherField::Temperature, &series.temperature_series),
+        (WeatherField::FeelsLike, &series.feels_like_series),
+        (WeatherField::DewPoint, &series.dew_point_series),
+        (WeatherField::Humidity, &series.humidity_series),
+        (WeatherField::Pressure, &series.pressure_series),
+        (WeatherField::WindSpeed, &series.wind_speed_series),
+        (WeatherField::WindDirection, &series.wind_direction_series),
+        (WeatherField::WindGust, &series.wind_gust_series),
+        (WeatherField::Clouds, &series.clouds_series),
+        (WeatherField::Precipitation, &series.precipitation_series),
+        (WeatherField::Visibility, &series.visibility_series),
+    ]
+    .into_iter()
+    .filter_map(|(field, s)| Some((field, s.as_ref()?)))
+    .collect()
+}
+
+/// Checks the provider reports all the fields associated to a series.
+pub fn check_fields(
+    series: &WeatherSeries,
+    supported: &[WeatherField],
+    provider: &str,
+) -> Result<()> {
+    for (field, s) in configured(series) {
+        if !supported.contains(&field) {
+            bail!(anyhow!(
+                "{provider} does not report {field:?}, it cannot feed the \"{s}\" series"
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub fn series(series: &WeatherSeries) -> Vec<String> {
+    configured(series)
+        .into_iter()
+        .map(|(_, s)| s.clone())
+        .collect()
+}
+
+/// Maps the values reported by a provider to their series.
+pub fn series_values(series: &WeatherSeries, values: &WeatherValues) -> HashMap<String, f64> {
+    configured(series)
+        .into_iter()
+        .filter_map(|(field, s)| Some((s.clone(), *values.get(&field)?)))
+        .collect()
+}
+
+/// Relative humidity in %, from the temperature and the dew point in °C (Magnus formula)
+pub fn relative_humidity(temperature: f64, dew_point: f64) -> f64 {
+    let magnus = |t: f64| (17.625 * t / (243.04 + t)).exp();
+    (100.0 * magnus(dew_point) / magnus(temperature)).clamp(0.0, 100.0)
+}
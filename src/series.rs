@@ -1,24 +1,38 @@
 use serde::{Deserialize, Serialize};
 use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 
-use crate::config::SeriesConfig;
-use crate::record::Record;
+use crate::config::{SeriesConfig, SeriesKind, ValueType};
+use crate::record::TypedRecord;
 
 pub struct SeriesState {
     pub id: String,
     pub sampling_interval: Duration,
+    pub value_type: ValueType,
     pub last_measure_instant: Option<Instant>,
-    pub last_measure: Option<Result<Record>>,
+    pub last_measure: Option<Result<TypedRecord>>,
 }
 
 impl SeriesState {
     pub fn new(cfg: &SeriesConfig) -> Result<Self> {
         let interval = humantime::parse_duration(&cfg.sampling_interval)?;
+        match (cfg.kind, cfg.wrap) {
+            (SeriesKind::Gauge, Some(_)) => {
+                bail!(anyhow!("the \"{}\" series wraps but is not a counter", cfg.id))
+            }
+            (SeriesKind::Counter, Some(wrap)) if wrap <= 0.0 => {
+                bail!(anyhow!("the wrap value of the \"{}\" series must be positive", cfg.id))
+            }
+            _ => {}
+        }
+        if cfg.kind == SeriesKind::Counter && cfg.value_type != ValueType::Number {
+            bail!(anyhow!("the \"{}\" counter series must hold numbers", cfg.id))
+        }
         Ok(Self {
             id: cfg.id.clone(),
             sampling_interval: interval,
+            value_type: cfg.value_type,
             last_measure_instant: None,
             last_measure: None,
         })
@@ -31,7 +45,7 @@ impl SeriesState {
         }
     }
 
-    pub fn notify_measured(&mut self, record: Result<Record>) {
+    pub fn notify_measured(&mut self, record: Result<TypedRecord>) {
         self.last_measure_instant = Some(Instant::now());
         self.last_measure = Some(record);
     }
@@ -44,6 +58,11 @@ pub struct SeriesDef {
     pub category: String,
     pub unit: String,
     pub color: String,
+    pub kind: SeriesKind,
+    /// Value at which a counter wraps around to 0
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wrap: Option<f64>,
+    pub value_type: ValueType,
 }
 
 impl SeriesDef {
@@ -54,6 +73,9 @@ impl SeriesDef {
             category: cfg.category,
             color: cfg.color,
             unit: cfg.unit,
+            kind: cfg.kind,
+            wrap: cfg.wrap,
+            value_type: cfg.value_type,
         }
     }
 }
@@ -9,6 +9,7 @@ use rouille::{content_encoding, Request, Response, router, try_or_400};
 use rusqlite::Error::QueryReturnedNoRows;
 
 use crate::config;
+use crate::config::{SeriesKind, ValueType};
 use crate::store::Store;
 
 #[derive(Debug)]
@@ -24,6 +25,19 @@ impl Display for MissingParamErr {
     }
 }
 
+#[derive(Debug)]
+struct InvalidIntervalErr {
+    interval: String,
+}
+
+impl Error for InvalidIntervalErr {}
+
+impl Display for InvalidIntervalErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid interval '{}', expected a duration of at least 1s", self.interval)
+    }
+}
+
 pub fn serve(cfg: config::Server, db_path: &str) -> Result<()> {
     let store = Mutex::new(Store::new(db_path)?);
     let addr = SocketAddr::new(IpAddr::from([0, 0, 0, 0]), cfg.port);
@@ -32,11 +46,12 @@ pub fn serve(cfg: config::Server, db_path: &str) -> Result<()> {
         let resp = router!(req,
             (GET) (/series/{series: String}) => {get_range(req, &series, &store)},
             (GET) (/series/{series: String}/latest) => {get_latest(req, &series, &store)},
+            (GET) (/series/{series: String}/issues) => {get_issues(req, &series, &store)},
             (GET) (/series) => {get_series_name(req, &store)},
             (GET) (/series_def) => {get_series(req, &store)},
             _ => Response::text("No such endpoint").with_status_code(404),
         );
-        let resp = content_encoding::apply(&req, resp);
+        let resp = content_encoding::apply(req, resp);
         let t2 = time::Instant::now();
         let ms = (t2 - t1).as_micros() as f32 / 1000.0;
         println!(
@@ -64,10 +79,101 @@ fn get_range(req: &Request, series: &str, store: &Mutex<Store>) -> Response {
 
     let from: u64 = try_or_400!(from.parse());
     let to: u64 = try_or_400!(to.parse());
-    let range = store.lock().unwrap().fetch(&series, from, to);
+    // Forecast series return the forecast as issued at `issued_at`, or the latest one
+    let issued_at: Option<u64> = match req.get_param("issued_at") {
+        Some(t) => Some(try_or_400!(t.parse())),
+        None => None,
+    };
+    // Counter series return their increases per `delta` interval, or their rate per `rate`
+    let delta = match req.get_param("delta") {
+        Some(d) => Some(try_or_400!(parse_interval(&d))),
+        None => None,
+    };
+    let rate = match req.get_param("rate") {
+        Some(r) => Some(try_or_400!(parse_interval(&r))),
+        None => None,
+    };
+    if delta.is_some() && rate.is_some() {
+        return Response::text("The delta and rate parameters are exclusive").with_status_code(400);
+    }
+    let store = store.lock().unwrap();
+    if delta.is_some() || rate.is_some() {
+        let def = match store.series_def(series) {
+            Ok(def) => def,
+            Err(err) if err.downcast_ref::<rusqlite::Error>().is_some_and(|e| *e == QueryReturnedNoRows) => return Response::with_status_code(
+                Response::text("No such series"),
+                404,
+            ),
+            Err(err) => return Response::with_status_code(
+                Response::text(format!("Internal server error: {}", err)),
+                500,
+            ),
+        };
+        if def.kind != SeriesKind::Counter {
+            return Response::text(format!("The {series} series is not a counter"))
+                .with_status_code(400);
+        }
+        let range = match (delta, rate) {
+            (Some(delta), _) => store.fetch_deltas(&def, from, to, delta),
+            (None, Some(rate)) => store.fetch_rates(&def, from, to, rate),
+            (None, None) => unreachable!(),
+        };
+        return match range {
+            Ok(range) => Response::json(&range),
+            Err(err) => Response::with_status_code(
+                Response::text(format!("Internal server error: {}", err)),
+                500,
+            ),
+        };
+    }
+    // Numbers are fetched apart from the values of other types, as plain records
+    let range = match store.value_type(series) {
+        Ok(ValueType::Number) => match (issued_at, store.is_forecast(series)) {
+            (Some(issued_at), _) => store.fetch_forecast(series, from, to, issued_at),
+            (None, Ok(true)) => store.fetch_forecast(series, from, to, i64::MAX as u64),
+            (None, Ok(false)) => store.fetch(series, from, to),
+            (None, Err(err)) => Err(err),
+        }
+        .map(|range| Response::json(&range)),
+        Ok(value_type) => store
+            .fetch_typed(series, value_type, from, to)
+            .map(|range| Response::json(&range)),
+        Err(err) => Err(err),
+    };
 
     match range {
-        Ok(range) => Response::json(&range),
+        Ok(range) => range,
+        Err(err) => Response::with_status_code(
+            Response::text(format!("Internal server error: {}", err)),
+            500,
+        ),
+    }
+}
+
+/// Parses a duration such as "1h" or "1day" into a number of seconds.
+fn parse_interval(interval: &str) -> Result<u64, InvalidIntervalErr> {
+    match humantime::parse_duration(interval).map(|d| d.as_secs()) {
+        Ok(secs) if secs > 0 => Ok(secs),
+        _ => Err(InvalidIntervalErr {
+            interval: interval.into(),
+        }),
+    }
+}
+
+fn get_issues(req: &Request, series: &str, store: &Mutex<Store>) -> Response {
+    let from = try_or_400!(req.get_param("from").ok_or(MissingParamErr {
+        name: "from".into()
+    }));
+    let to = try_or_400!(req
+        .get_param("to")
+        .ok_or(MissingParamErr { name: "to".into() }));
+
+    let from: u64 = try_or_400!(from.parse());
+    let to: u64 = try_or_400!(to.parse());
+    let issues = store.lock().unwrap().forecast_issues(series, from, to);
+
+    match issues {
+        Ok(issues) => Response::json(&issues),
         Err(err) => Response::with_status_code(
             Response::text(format!("Internal server error: {}", err)),
             500,
@@ -76,12 +182,19 @@ fn get_range(req: &Request, series: &str, store: &Mutex<Store>) -> Response {
 }
 
 fn get_latest(_req: &Request, series: &str, store: &Mutex<Store>) -> Response {
-    let latest = store.lock().unwrap().latest(series);
+    let store = store.lock().unwrap();
+    let latest = match store.value_type(series) {
+        Ok(ValueType::Number) => store.latest(series).map(|latest| Response::json(&latest)),
+        Ok(value_type) => store
+            .latest_typed(series, value_type)
+            .map(|latest| Response::json(&latest)),
+        Err(err) => Err(err),
+    };
 
     match latest {
-        Ok(latest) => Response::json(&latest),
+        Ok(latest) => latest,
         Err(err) if err.downcast_ref::<rusqlite::Error>().is_some_and(|e| *e == QueryReturnedNoRows) => Response::with_status_code(
-            Response::text(format!("No value found for this series")),
+            Response::text("No value found for this series"),
             404,
         ),
         Err(err) => Response::with_status_code(
@@ -1,7 +1,9 @@
-use anyhow::Result;
-use rusqlite::{params, Connection};
+use anyhow::{anyhow, bail, Result};
+use rusqlite::types::{FromSqlError, Value as SqlValue, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, Row};
 
-use crate::record::Record;
+use crate::config::{SeriesKind, ValueType};
+use crate::record::{Forecast, Record, TypedRecord, Value};
 use crate::series::SeriesDef;
 
 pub struct Store {
@@ -20,6 +22,16 @@ impl Store {
         )",
             (),
         )?;
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS forecasts (
+            issued_at INT NOT NULL,
+            timestamp INT NOT NULL,
+            series    TEXT NOT NULL,
+            value     REAL NOT NULL,
+            PRIMARY KEY (series, issued_at, timestamp)
+        )",
+            (),
+        )?;
         db.execute(
             "CREATE TABLE IF NOT EXISTS series (
             id        TEXT NOT NULL,
@@ -27,10 +39,28 @@ impl Store {
             category  TEXT NOT NULL,
             unit      TEXT NOT NULL,
             color     TEXT NOT NULL,
+            kind      TEXT NOT NULL DEFAULT 'gauge',
+            wrap      REAL,
+            value_type TEXT NOT NULL DEFAULT 'number',
             PRIMARY KEY (id)
         )",
             (),
         )?;
+        // Databases created before the counter and typed series lack their columns
+        add_missing_column(&db, "series", "kind", "TEXT NOT NULL DEFAULT 'gauge'")?;
+        add_missing_column(&db, "series", "wrap", "REAL")?;
+        add_missing_column(&db, "series", "value_type", "TEXT NOT NULL DEFAULT 'number'")?;
+        // Values of the series that are not numbers, stored as INTEGER for integers and
+        // booleans, as TEXT for states and as a JSON array for vectors
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS typed_records (
+            timestamp INT NOT NULL,
+            series    TEXT NOT NULL,
+            value     NOT NULL,
+            PRIMARY KEY (timestamp, series)
+        )",
+            (),
+        )?;
         Ok(Store { db })
     }
 
@@ -42,6 +72,21 @@ impl Store {
         Ok(())
     }
 
+    pub fn save_typed(&self, record: &TypedRecord, series: &str) -> Result<()> {
+        let value = match &record.value {
+            Value::Boolean(b) => SqlValue::Integer(*b as i64),
+            Value::Integer(i) => SqlValue::Integer(*i),
+            Value::Number(n) => SqlValue::Real(*n),
+            Value::State(s) => SqlValue::Text(s.clone()),
+            Value::Vector(v) => SqlValue::Text(serde_json::to_string(v)?),
+        };
+        self.db.execute(
+            "INSERT INTO typed_records (timestamp, series, value) VALUES (?1, ?2, ?3)",
+            params![record.timestamp, series, value],
+        )?;
+        Ok(())
+    }
+
     pub fn fetch(&self, series: &str, from: u64, to: u64) -> Result<Vec<Record>> {
         let mut stmt = self.db.prepare(
             "SELECT timestamp, value
@@ -64,6 +109,90 @@ impl Store {
         }
         Ok(records)
     }
+
+    /// Fetches the increases of a counter series over the intervals of length `interval`
+    /// seconds, aligned on the Unix epoch, between `from` and `to`. Each record is timestamped
+    /// with the start of its interval.
+    pub fn fetch_deltas(
+        &self,
+        series: &SeriesDef,
+        from: u64,
+        to: u64,
+        interval: u64,
+    ) -> Result<Vec<Record>> {
+        let mut deltas: Vec<Record> = vec![];
+        for (_, timestamp, increase) in self.increases(series, from, to)? {
+            let start = timestamp - timestamp % interval;
+            match deltas.last_mut() {
+                Some(last) if last.timestamp == start => last.value += increase,
+                _ => deltas.push(Record {
+                    timestamp: start,
+                    value: increase,
+                }),
+            }
+        }
+        Ok(deltas)
+    }
+
+    /// Fetches the rates of a counter series between `from` and `to`, as the increase per `per`
+    /// seconds since the previous record.
+    pub fn fetch_rates(
+        &self,
+        series: &SeriesDef,
+        from: u64,
+        to: u64,
+        per: u64,
+    ) -> Result<Vec<Record>> {
+        Ok(self
+            .increases(series, from, to)?
+            .into_iter()
+            .map(|(previous, timestamp, increase)| Record {
+                timestamp,
+                value: increase * per as f64 / (timestamp - previous) as f64,
+            })
+            .collect())
+    }
+
+    /// Computes the increases of a counter between its consecutive records, from the record
+    /// preceding `from` to `to`. A decrease is a wrap-around if the counter wraps and was in the
+    /// upper half of its range, and a reset to 0 otherwise.
+    fn increases(&self, series: &SeriesDef, from: u64, to: u64) -> Result<Vec<(u64, u64, f64)>> {
+        let mut stmt = self.db.prepare(
+            "SELECT timestamp, value
+             FROM records
+             WHERE series = ?1 AND timestamp <= ?3 AND timestamp >= COALESCE(
+                (SELECT MAX(timestamp) FROM records WHERE series = ?1 AND timestamp < ?2),
+                ?2
+             )
+             ORDER BY timestamp",
+        )?;
+        let iter = stmt.query_map(params![series.id, from, to], |row| {
+            Ok((row.get::<_, u64>(0)?, row.get::<_, f64>(1)?))
+        })?;
+
+        let mut increases = vec![];
+        let mut previous: Option<(u64, f64)> = None;
+        for record in iter {
+            let (timestamp, value) = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    println!("Warning: a record could not be read in the database: {e}");
+                    continue;
+                }
+            };
+            if let Some((previous_timestamp, previous_value)) = previous {
+                let increase = match series.wrap {
+                    _ if value >= previous_value => value - previous_value,
+                    Some(wrap) if previous_value > wrap / 2.0 => wrap - previous_value + value,
+                    _ => value,
+                };
+                increases.push((previous_timestamp, timestamp, increase));
+            }
+            previous = Some((timestamp, value));
+        }
+        Ok(increases)
+    }
+
     pub fn latest(&self, series: &str) -> Result<Record> {
         let mut stmt = self.db.prepare(
             "SELECT timestamp, value
@@ -82,37 +211,214 @@ impl Store {
         Ok(record)
     }
 
+    /// Fetches the records of a series whose values are not numbers.
+    pub fn fetch_typed(
+        &self,
+        series: &str,
+        value_type: ValueType,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<TypedRecord>> {
+        let mut stmt = self.db.prepare(
+            "SELECT timestamp, value
+             FROM typed_records
+             WHERE series = ?1 AND timestamp >= ?2 AND timestamp <= ?3
+             ORDER BY timestamp",
+        )?;
+        let iter = stmt.query_map(params![series, from, to], |row| {
+            Ok(TypedRecord {
+                timestamp: row.get(0)?,
+                value: read_value(row.get_ref(1)?, value_type)?,
+            })
+        })?;
+
+        let mut records = vec![];
+        for record in iter {
+            match record {
+                Ok(record) => records.push(record),
+                Err(e) => println!("Warning: a record could not be read in the database: {e}"),
+            }
+        }
+        Ok(records)
+    }
+
+    pub fn latest_typed(&self, series: &str, value_type: ValueType) -> Result<TypedRecord> {
+        let mut stmt = self.db.prepare(
+            "SELECT timestamp, value
+             FROM typed_records
+             WHERE series = ?1
+             ORDER BY timestamp DESC
+             LIMIT 1",
+        )?;
+        let record = stmt.query_row([series], |row| {
+            Ok(TypedRecord {
+                timestamp: row.get(0)?,
+                value: read_value(row.get_ref(1)?, value_type)?,
+            })
+        })?;
+
+        Ok(record)
+    }
+
+    /// Saves a forecast. Saving the same issue again replaces its values.
+    pub fn save_forecast(&mut self, forecast: &Forecast, series: &str) -> Result<()> {
+        let tx = self.db.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO forecasts (issued_at, timestamp, series, value)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for record in &forecast.records {
+                stmt.execute(params![
+                    forecast.issued_at,
+                    record.timestamp,
+                    series,
+                    record.value
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Fetches the forecast records of the last issue published at or before `issued_at`.
+    pub fn fetch_forecast(
+        &self,
+        series: &str,
+        from: u64,
+        to: u64,
+        issued_at: u64,
+    ) -> Result<Vec<Record>> {
+        let mut stmt = self.db.prepare(
+            "SELECT timestamp, value
+             FROM forecasts
+             WHERE series = ?1 AND timestamp >= ?2 AND timestamp <= ?3 AND issued_at = (
+                SELECT MAX(issued_at)
+                FROM forecasts
+                WHERE series = ?1 AND issued_at <= ?4
+             )
+             ORDER BY timestamp",
+        )?;
+        let iter = stmt.query_map(params![series, from, to, issued_at], |row| {
+            Ok(Record {
+                timestamp: row.get(0)?,
+                value: row.get(1)?,
+            })
+        })?;
+
+        let mut records = vec![];
+        for record in iter {
+            match record {
+                Ok(record) => records.push(record),
+                Err(e) => println!("Warning: a record could not be read in the database: {e}"),
+            }
+        }
+        Ok(records)
+    }
+
+    /// Lists the issue times of the forecasts of a series, between `from` and `to`.
+    pub fn forecast_issues(&self, series: &str, from: u64, to: u64) -> Result<Vec<u64>> {
+        let mut stmt = self.db.prepare(
+            "SELECT DISTINCT issued_at
+             FROM forecasts
+             WHERE series = ?1 AND issued_at >= ?2 AND issued_at <= ?3
+             ORDER BY issued_at",
+        )?;
+        let iter = stmt.query_map(params![series, from, to], |row| row.get(0))?;
+        let mut issues = vec![];
+        for issue in iter {
+            issues.push(issue?);
+        }
+        Ok(issues)
+    }
+
+    /// Whether the series holds forecasts rather than measures
+    pub fn is_forecast(&self, series: &str) -> Result<bool> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT EXISTS (SELECT 1 FROM forecasts WHERE series = ?1)")?;
+        Ok(stmt.query_row([series], |row| row.get(0))?)
+    }
+
+    /// Type of the values of a series, numbers for unknown series
+    pub fn value_type(&self, series: &str) -> Result<ValueType> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT value_type FROM series WHERE id = ?1")?;
+        let value_type = stmt
+            .query_row([series], |row| Ok(parse_value_type(row.get_ref(0)?.as_str()?)))
+            .optional()?;
+        Ok(value_type.unwrap_or_default())
+    }
+
+    /// Adds or updates the definitions of series. The type of the values of a series cannot
+    /// change, as its records could not be read anymore.
     pub fn update_series(&self, series: &[SeriesDef]) -> Result<()> {
+        for s in series {
+            let stored = self
+                .db
+                .query_row(
+                    "SELECT value_type FROM series WHERE id = ?1",
+                    [&s.id],
+                    |row| Ok(parse_value_type(row.get_ref(0)?.as_str()?)),
+                )
+                .optional()?;
+            if let Some(stored) = stored.filter(|t| *t != s.value_type) {
+                bail!(anyhow!(
+                    "the \"{}\" series holds {} values, they cannot be changed to {} values",
+                    s.id,
+                    value_type_name(stored),
+                    value_type_name(s.value_type)
+                ));
+            }
+        }
         let mut stmt = self.db.prepare(
-            "INSERT INTO series (id, name, category, unit, color)
-             VALUES (?1, ?2, ?3, ?4, ?5)
+            "INSERT INTO series (id, name, category, unit, color, kind, wrap, value_type)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
              ON CONFLICT(id) DO UPDATE SET
                 name=excluded.name,
                 category=excluded.category,
                 unit=excluded.unit,
-                color=excluded.color
+                color=excluded.color,
+                kind=excluded.kind,
+                wrap=excluded.wrap,
+                value_type=excluded.value_type
             ",
         )?;
         for s in series {
-            stmt.execute([&s.id, &s.name, &s.category, &s.unit, &s.color])?;
+            let kind = match s.kind {
+                SeriesKind::Gauge => "gauge",
+                SeriesKind::Counter => "counter",
+            };
+            stmt.execute(params![
+                s.id,
+                s.name,
+                s.category,
+                s.unit,
+                s.color,
+                kind,
+                s.wrap,
+                value_type_name(s.value_type)
+            ])?;
         }
         Ok(())
     }
 
+    pub fn series_def(&self, series: &str) -> Result<SeriesDef> {
+        let mut stmt = self.db.prepare(
+            "SELECT id, name, category, unit, color, kind, wrap, value_type
+             FROM series
+             WHERE id = ?1",
+        )?;
+        Ok(stmt.query_row([series], row_to_series_def)?)
+    }
+
     pub fn series(&self) -> Result<Vec<SeriesDef>> {
         let mut stmt = self.db.prepare(
-            "SELECT id, name, category, unit, color
+            "SELECT id, name, category, unit, color, kind, wrap, value_type
              FROM series",
         )?;
-        let series_iter = stmt.query_map([], |row| {
-            Ok(SeriesDef {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                category: row.get(2)?,
-                unit: row.get(3)?,
-                color: row.get(4)?,
-            })
-        })?;
+        let series_iter = stmt.query_map([], row_to_series_def)?;
 
         let mut series = vec![];
         for s in series_iter {
@@ -121,3 +427,234 @@ impl Store {
         Ok(series)
     }
 }
+
+fn row_to_series_def(row: &Row) -> rusqlite::Result<SeriesDef> {
+    Ok(SeriesDef {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        category: row.get(2)?,
+        unit: row.get(3)?,
+        color: row.get(4)?,
+        kind: match row.get_ref(5)?.as_str()? {
+            "counter" => SeriesKind::Counter,
+            _ => SeriesKind::Gauge,
+        },
+        wrap: row.get(6)?,
+        value_type: parse_value_type(row.get_ref(7)?.as_str()?),
+    })
+}
+
+fn value_type_name(value_type: ValueType) -> &'static str {
+    match value_type {
+        ValueType::Number => "number",
+        ValueType::Integer => "integer",
+        ValueType::Boolean => "boolean",
+        ValueType::State => "state",
+        ValueType::Vector => "vector",
+    }
+}
+
+fn parse_value_type(value_type: &str) -> ValueType {
+    match value_type {
+        "integer" => ValueType::Integer,
+        "boolean" => ValueType::Boolean,
+        "state" => ValueType::State,
+        "vector" => ValueType::Vector,
+        _ => ValueType::Number,
+    }
+}
+
+fn read_value(value: ValueRef, value_type: ValueType) -> rusqlite::Result<Value> {
+    Ok(match value_type {
+        ValueType::Boolean => Value::Boolean(value.as_i64()? != 0),
+        ValueType::Integer => Value::Integer(value.as_i64()?),
+        ValueType::Number => Value::Number(value.as_f64()?),
+        ValueType::State => Value::State(value.as_str()?.to_owned()),
+        ValueType::Vector => Value::Vector(
+            serde_json::from_str(value.as_str()?)
+                .map_err(|e| FromSqlError::Other(Box::new(e)))?,
+        ),
+    })
+}
+
+/// Adds a column to a table created by a previous version.
+fn add_missing_column(db: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let exists: bool = db.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+        [table, column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        db.execute(&format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"), ())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn def(id: &str, name: &str, value_type: ValueType) -> SeriesDef {
+        SeriesDef {
+            id: id.to_owned(),
+            name: name.to_owned(),
+            category: "Test".to_owned(),
+            unit: "".to_owned(),
+            color: "#000000".to_owned(),
+            kind: SeriesKind::Gauge,
+            wrap: None,
+            value_type,
+        }
+    }
+
+    fn counter(store: &Store, wrap: Option<f64>, records: &[(u64, f64)]) -> SeriesDef {
+        let mut series = def("energy", "Energy", ValueType::Number);
+        series.kind = SeriesKind::Counter;
+        series.wrap = wrap;
+        for (timestamp, value) in records {
+            let record = Record {
+                timestamp: *timestamp,
+                value: *value,
+            };
+            store.save(record, "energy").unwrap();
+        }
+        series
+    }
+
+    fn pairs(records: Vec<Record>) -> Vec<(u64, f64)> {
+        records
+            .into_iter()
+            .map(|r| (r.timestamp, r.value))
+            .collect()
+    }
+
+    #[test]
+    fn monotonic_counter() {
+        let store = Store::new(":memory:").unwrap();
+        let series = counter(
+            &store,
+            None,
+            &[(50, 1.0), (100, 2.0), (160, 5.0), (230, 5.0), (290, 11.0)],
+        );
+        // The record preceding `from` gives the increase of the first record
+        assert_eq!(
+            pairs(store.fetch_deltas(&series, 100, 300, 100).unwrap()),
+            [(100, 4.0), (200, 6.0)]
+        );
+        assert_eq!(
+            pairs(store.fetch_deltas(&series, 160, 300, 100).unwrap()),
+            [(100, 3.0), (200, 6.0)]
+        );
+        // Increases per minute, divided by the seconds since the previous record
+        assert_eq!(
+            pairs(store.fetch_rates(&series, 150, 300, 60).unwrap()),
+            [(160, 3.0), (230, 0.0), (290, 6.0)]
+        );
+        assert!(store.fetch_rates(&series, 300, 400, 60).unwrap().is_empty());
+    }
+
+    #[test]
+    fn counter_wrap_and_reset() {
+        let store = Store::new(":memory:").unwrap();
+        let wrap = 4294967296.0;
+        let series = counter(
+            &store,
+            Some(wrap),
+            &[(0, 4294967000.0), (10, 200.0), (20, 1000.0), (30, 10.0)],
+        );
+        // Wrapped from the upper half of the range, reset from the lower half
+        assert_eq!(
+            pairs(store.fetch_rates(&series, 0, 30, 1).unwrap()),
+            [(10, 49.6), (20, 80.0), (30, 1.0)]
+        );
+
+        let store = Store::new(":memory:").unwrap();
+        let series = counter(&store, None, &[(0, 4294967000.0), (10, 200.0), (20, 250.0)]);
+        assert_eq!(
+            pairs(store.fetch_deltas(&series, 0, 20, 10).unwrap()),
+            [(10, 200.0), (20, 50.0)]
+        );
+    }
+
+    #[test]
+    fn typed_records() {
+        let store = Store::new(":memory:").unwrap();
+        let series = [
+            (
+                "door",
+                ValueType::Boolean,
+                vec![Value::Boolean(false), Value::Boolean(true)],
+            ),
+            (
+                "count",
+                ValueType::Integer,
+                vec![Value::Integer(-3), Value::Integer(1 << 40)],
+            ),
+            (
+                "mode",
+                ValueType::State,
+                vec![
+                    Value::State("heating".to_owned()),
+                    Value::State("".to_owned()),
+                ],
+            ),
+            (
+                "wind",
+                ValueType::Vector,
+                vec![
+                    Value::Vector(vec![1.5, -2.0]),
+                    Value::Vector(vec![0.1, 0.2, 0.3]),
+                ],
+            ),
+        ];
+        let defs: Vec<_> = series.iter().map(|(id, t, _)| def(id, id, *t)).collect();
+        store.update_series(&defs).unwrap();
+
+        for (id, value_type, values) in series {
+            assert_eq!(store.value_type(id).unwrap(), value_type);
+            for (i, value) in values.iter().enumerate() {
+                let record = TypedRecord {
+                    timestamp: 1000 + i as u64,
+                    value: value.clone(),
+                };
+                store.save_typed(&record, id).unwrap();
+            }
+            let fetched = store.fetch_typed(id, value_type, 0, 2000).unwrap();
+            let fetched: Vec<_> = fetched
+                .into_iter()
+                .map(|r| (r.timestamp, r.value))
+                .collect();
+            assert_eq!(
+                fetched,
+                [(1000, values[0].clone()), (1001, values[1].clone())]
+            );
+            assert_eq!(
+                store.fetch_typed(id, value_type, 1001, 2000).unwrap().len(),
+                1
+            );
+            assert_eq!(store.latest_typed(id, value_type).unwrap().value, values[1]);
+        }
+        assert_eq!(store.value_type("unknown").unwrap(), ValueType::Number);
+    }
+
+    #[test]
+    fn value_type_change() {
+        let store = Store::new(":memory:").unwrap();
+        store
+            .update_series(&[def("door", "Door", ValueType::Boolean)])
+            .unwrap();
+        store
+            .update_series(&[def("door", "Front door", ValueType::Boolean)])
+            .unwrap();
+        assert_eq!(store.series_def("door").unwrap().name, "Front door");
+
+        let err = store
+            .update_series(&[def("door", "Door", ValueType::State)])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the \"door\" series holds boolean values, they cannot be changed to state values"
+        );
+        assert_eq!(store.value_type("door").unwrap(), ValueType::Boolean);
+    }
+}